@@ -1,4 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::compile_protos("../proto/mnist.proto")?;
+    tonic_build::configure().compile_protos(
+        &["../proto/mnist.proto", "../proto/grpc_predict_v2.proto"],
+        &["../proto"],
+    )?;
     Ok(())
 }
@@ -1,26 +1,134 @@
 use crate::config::{ConfigBuilder, ServerConfig};
-use crate::inference_engine::ModelArchitecture;
-use clap::{Parser, ValueEnum};
+use crate::inference_engine::decision::RejectionPolicy;
+use crate::inference_engine::{InferenceEngineBuilder, ModelArchitecture, PredictOptions};
+use clap::{Parser, Subcommand, ValueEnum};
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::Result;
+use crate::inference_engine::integrity::{IntegrityPolicy, load_public_key};
+use crate::inference_engine::ood::OodMethod;
 use crate::inference_engine::weights_provider::LocalFileProvider;
+use crate::rollout::{CandidateConfig, RolloutMode};
 use candle_core::{DType, Device};
+use mnist::train::OptimizerKind;
+use mnist::{ModelSpec, Quantization};
 
 #[derive(Debug, Parser)]
 #[command(name = "Mnist Inference Server")]
 #[command(about = "A Rust ML inference server for MNIST predictions using candle")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Model architecture to use
+    /// Run a maintenance command instead of starting the server
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Model to serve, only absent when a subcommand is given
+    #[command(flatten)]
+    pub model: Option<ModelArgs>,
+
+    /// Server bind address
+    #[arg(long, default_value = "[::1]:50051")]
+    pub address: String,
+
+    /// Bind address of the HTTP/JSON gateway, e.g. `[::1]:8080`, disabled if omitted
+    #[arg(long)]
+    pub http_address: Option<String>,
+
+    /// Accept gRPC-Web requests from browser clients on the gRPC address
+    #[arg(long)]
+    pub grpc_web: bool,
+
+    /// Origin allowed to make cross-origin requests, e.g. `https://digits.example.com`, or
+    /// `*` for any; can be repeated
+    #[arg(long = "allowed-origin")]
+    pub allowed_origins: Vec<String>,
+
+    /// Tracing level (trace, debug, info, warn, error)
+    #[arg(long, default_value = "info")]
+    pub log_level: String,
+
+    /// Log format (pretty, json, compact)
+    #[arg(long, default_value = "pretty")]
+    pub log_format: LogFormat,
+
+    /// Number of most probable digits returned when the request does not ask for a number
+    #[arg(long, default_value_t = 1)]
+    pub top_k: usize,
+
+    /// Reject predictions whose probability is below this value, 0 disables the check
+    #[arg(long, default_value_t = 0.0)]
+    pub min_confidence: f32,
+
+    /// Reject predictions whose top-2 probabilities differ by less than this value
+    #[arg(long, default_value_t = 0.0)]
+    pub min_margin: f32,
+
+    /// Name the model is served under, defaults to the name in an ensemble file or `mnist`
+    #[arg(long)]
+    pub model_name: Option<String>,
+
+    /// Weights of a candidate model to compare against the served one on live traffic
+    #[arg(long)]
+    pub candidate_weights: Option<PathBuf>,
+
+    /// Architecture of the candidate model, read from its manifest if omitted
+    #[arg(long, value_enum, requires = "candidate_weights")]
+    pub candidate_architecture: Option<ModelArchitecture>,
+
+    /// Path to the candidate model manifest, defaults to the sidecar of its weights
+    #[arg(long, requires = "candidate_weights")]
+    pub candidate_manifest: Option<PathBuf>,
+
+    /// Whether the candidate runs in the background (shadow) or answers its share (canary)
+    #[arg(long, value_enum, default_value_t = RolloutMode::Shadow)]
+    pub rollout_mode: RolloutMode,
+
+    /// Percentage of the requests sent to the candidate model
+    #[arg(long, default_value_t = 10.0)]
+    pub rollout_percent: f32,
+
+    /// Directory collecting the corrected samples sent with `SubmitFeedback`
+    #[arg(long)]
+    pub feedback_dir: Option<PathBuf>,
+}
+
+/// Arguments selecting the model to load, shared by the server and the subcommands
+#[derive(Debug, Clone, clap::Args)]
+pub struct ModelArgs {
+    /// Model architecture to use, read from the model manifest if omitted
     #[arg(long, value_enum)]
-    pub model_architecture: ModelArchitecture,
+    pub model_architecture: Option<ModelArchitecture>,
 
     /// Path to model weights file
     #[arg(long)]
     pub model_weights: PathBuf,
 
+    /// Path to the model manifest, defaults to `<model>.manifest.{json,toml}` next to the weights
+    #[arg(long)]
+    pub model_manifest: Option<PathBuf>,
+
+    /// JSON or TOML layer spec of a sequential model, overriding the layers in the manifest
+    #[arg(long)]
+    pub model_spec: Option<PathBuf>,
+
+    /// Expected SHA-256 digest of the weights file (hex)
+    #[arg(long)]
+    pub weights_sha256: Option<String>,
+
+    /// Verify the weights against a `<weights>.sha256` sidecar file
+    #[arg(long)]
+    pub weights_sha256_sidecar: bool,
+
+    /// Ed25519 public key (PEM, hex or raw bytes) the weights must be signed with
+    #[arg(long)]
+    pub weights_public_key: Option<PathBuf>,
+
+    /// Detached Ed25519 signature of the weights file, defaults to `<weights>.sig`
+    #[arg(long, requires = "weights_public_key")]
+    pub weights_signature: Option<PathBuf>,
+
     /// Device to use for inference (cpu, cuda)
     #[arg(long, default_value = "cpu")]
     pub device: String,
@@ -29,17 +137,297 @@ pub struct Args {
     #[arg(long, default_value = "f32")]
     pub dtype: String,
 
-    /// Server bind address
-    #[arg(long, default_value = "[::1]:50051")]
-    pub address: String,
+    /// Quantize the linear layers of an MLP or ConvNet (f16, q8_0, q5_1, q5_0, q4_1, q4_0);
+    /// `.gguf` weights are always served quantized
+    #[arg(long)]
+    pub quantization: Option<Quantization>,
+}
 
-    /// Tracing level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info")]
-    pub log_level: String,
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Check the tensors in a weights file against the model architecture
+    Validate(ValidateArgs),
+    /// Fit a softmax temperature on a labeled validation set and store it in the manifest
+    Calibrate(CalibrateArgs),
+    /// Report the accuracy, per-digit precision/recall and throughput on a labeled dataset
+    Evaluate(EvaluateArgs),
+    /// Train an MLP or ConvNet on a labeled dataset and save weights the server can load
+    Train(TrainArgs),
+    /// Fine-tune served weights on collected feedback and save them as a new version
+    Finetune(FinetuneArgs),
+    /// Convert safetensors weights to a quantized GGUF file and report the accuracy change
+    Quantize(QuantizeArgs),
+    /// Fit out-of-distribution scoring on labeled training data and store it in the manifest
+    FitOod(FitOodArgs),
+}
 
-    /// Log format (pretty, json, compact)
-    #[arg(long, default_value = "pretty")]
-    pub log_format: LogFormat,
+#[derive(Debug, clap::Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// Also fail if the weights contain tensors the architecture does not use
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CalibrateArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// MNIST images in IDX format, e.g. `t10k-images-idx3-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub images: PathBuf,
+
+    /// MNIST labels in IDX format, e.g. `t10k-labels-idx1-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub labels: PathBuf,
+
+    /// Only use the first N samples
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Number of images per forward pass
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: usize,
+
+    /// Number of bins of the reliability diagram
+    #[arg(long, default_value_t = 15)]
+    pub bins: usize,
+
+    /// Report the fitted temperature without writing it to the manifest
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct EvaluateArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// MNIST images in IDX format, e.g. `t10k-images-idx3-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub images: PathBuf,
+
+    /// MNIST labels in IDX format, e.g. `t10k-labels-idx1-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub labels: PathBuf,
+
+    /// Only use the first N samples
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Number of images per forward pass
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: usize,
+
+    /// Exit with an error if the accuracy is below this value (between 0 and 1)
+    #[arg(long)]
+    pub min_accuracy: Option<f32>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct TrainArgs {
+    /// Architecture to train (mlp or conv)
+    #[arg(long, value_enum)]
+    pub model_architecture: ModelArchitecture,
+
+    /// Training images in IDX format, e.g. `train-images-idx3-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub images: PathBuf,
+
+    /// Training labels in IDX format, e.g. `train-labels-idx1-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub labels: PathBuf,
+
+    /// Test images to report the accuracy on after every epoch
+    #[arg(long, requires = "test_labels")]
+    pub test_images: Option<PathBuf>,
+
+    /// Test labels matching `--test-images`
+    #[arg(long, requires = "test_images")]
+    pub test_labels: Option<PathBuf>,
+
+    /// Only train on the first N samples
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Where to save the weights (.safetensors), checkpointed after every epoch
+    #[arg(long)]
+    pub output: PathBuf,
+
+    /// Continue training from the weights in `--output`
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Optimizer (sgd, adamw)
+    #[arg(long, default_value = "adamw")]
+    pub optimizer: OptimizerKind,
+
+    #[arg(long, default_value_t = 1e-3)]
+    pub learning_rate: f64,
+
+    /// Decoupled weight decay of AdamW
+    #[arg(long, default_value_t = 0.01)]
+    pub weight_decay: f64,
+
+    #[arg(long, default_value_t = 5)]
+    pub epochs: usize,
+
+    #[arg(long, default_value_t = 64)]
+    pub batch_size: usize,
+
+    /// Seed of the shuffling of the training set
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+
+    /// Device to train on (cpu, cuda)
+    #[arg(long, default_value = "cpu")]
+    pub device: String,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FinetuneArgs {
+    /// Weights to start from, usually the served ones
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// Directory the server collected feedback in (`--feedback-dir`)
+    #[arg(long)]
+    pub feedback_dir: PathBuf,
+
+    /// Original training images mixed into the feedback so the model does not forget them
+    #[arg(long, requires = "replay_labels")]
+    pub replay_images: Option<PathBuf>,
+
+    /// Labels matching `--replay-images`
+    #[arg(long, requires = "replay_images")]
+    pub replay_labels: Option<PathBuf>,
+
+    /// Only mix in the first N replayed samples
+    #[arg(long)]
+    pub replay_limit: Option<usize>,
+
+    /// Test images to report the accuracy on before and after fine-tuning
+    #[arg(long, requires = "test_labels")]
+    pub test_images: Option<PathBuf>,
+
+    /// Test labels matching `--test-images`
+    #[arg(long, requires = "test_images")]
+    pub test_labels: Option<PathBuf>,
+
+    /// Where to save the weights, defaults to the next `<model>-v<N>.safetensors`
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Optimizer (sgd, adamw)
+    #[arg(long, default_value = "adamw")]
+    pub optimizer: OptimizerKind,
+
+    #[arg(long, default_value_t = 1e-4)]
+    pub learning_rate: f64,
+
+    /// Decoupled weight decay of AdamW
+    #[arg(long, default_value_t = 0.01)]
+    pub weight_decay: f64,
+
+    #[arg(long, default_value_t = 3)]
+    pub epochs: usize,
+
+    #[arg(long, default_value_t = 32)]
+    pub batch_size: usize,
+
+    /// Seed of the shuffling of the samples
+    #[arg(long, default_value_t = 42)]
+    pub seed: u64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct QuantizeArgs {
+    /// Safetensors weights to convert, `--quantization` selects the format (default q8_0)
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// Where to save the GGUF file, defaults to `<model>-<quantization>.gguf`
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Labeled images to compare the accuracy of the original and quantized models on
+    #[arg(long, requires = "labels")]
+    pub images: Option<PathBuf>,
+
+    /// Labels matching `--images`
+    #[arg(long, requires = "images")]
+    pub labels: Option<PathBuf>,
+
+    /// Only use the first N samples
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Number of images per forward pass
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: usize,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FitOodArgs {
+    #[command(flatten)]
+    pub model: ModelArgs,
+
+    /// MNIST images in IDX format, e.g. `train-images-idx3-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub images: PathBuf,
+
+    /// MNIST labels in IDX format, e.g. `train-labels-idx1-ubyte` (optionally gzipped)
+    #[arg(long)]
+    pub labels: PathBuf,
+
+    /// Only use the first N samples
+    #[arg(long)]
+    pub limit: Option<usize>,
+
+    /// Number of images per forward pass
+    #[arg(long, default_value_t = 256)]
+    pub batch_size: usize,
+
+    /// How inputs are scored
+    #[arg(long, value_enum, default_value_t = OodMethod::Mahalanobis)]
+    pub method: OodMethod,
+
+    /// Share of the dataset scoring below the threshold, the rest would be flagged
+    #[arg(long, default_value_t = 0.99)]
+    pub quantile: f32,
+
+    /// Report the scores and threshold without writing them to the manifest
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    pub json: bool,
+}
+
+impl TrainArgs {
+    pub fn get_device(&self) -> Result<Device> {
+        parse_device(&self.device)
+    }
 }
 
 #[derive(Debug, Clone, ValueEnum)]
@@ -49,17 +437,22 @@ pub enum LogFormat {
     Compact,
 }
 
-impl Args {
+/// Convert a device name to a candle_core::Device
+fn parse_device(device: &str) -> Result<Device> {
+    match device.to_lowercase().as_str() {
+        "cpu" => Ok(Device::Cpu),
+        "cuda" => Ok(Device::cuda_if_available(0)?),
+        _ => Err(crate::Error::custom(format!(
+            "Unsupported device: {}. Supported devices: cpu, cuda",
+            device
+        ))),
+    }
+}
+
+impl ModelArgs {
     /// Convert the device string to a candle_core::Device
     pub fn get_device(&self) -> Result<Device> {
-        match self.device.to_lowercase().as_str() {
-            "cpu" => Ok(Device::Cpu),
-            "cuda" => Ok(Device::cuda_if_available(0)?),
-            _ => Err(crate::Error::custom(format!(
-                "Unsupported device: {}. Supported devices: cpu, cuda",
-                self.device
-            ))),
-        }
+        parse_device(&self.device)
     }
 
     /// Convert the dtype string to a candle_core::DType
@@ -75,13 +468,71 @@ impl Args {
         }
     }
 
+    /// Collect the weights integrity checks requested on the command line
+    pub fn get_integrity_policy(&self) -> Result<IntegrityPolicy> {
+        let mut policy = IntegrityPolicy::new().with_sha256_sidecar(self.weights_sha256_sidecar);
+        if let Some(digest) = &self.weights_sha256 {
+            policy = policy.with_sha256(digest.clone());
+        }
+        if let Some(key_path) = &self.weights_public_key {
+            policy = policy.with_public_key(load_public_key(key_path)?);
+        }
+        if let Some(signature_path) = &self.weights_signature {
+            policy = policy.with_signature_path(signature_path.clone());
+        }
+        Ok(policy)
+    }
+
     /// Convert the model weights path to a LocalFileProvider
     pub fn get_weights_provider(&self) -> Result<LocalFileProvider> {
-        LocalFileProvider::from_str(
+        let provider = LocalFileProvider::from_str(
             self.model_weights
                 .to_str()
                 .ok_or_else(|| crate::Error::custom("Invalid UTF-8 in weights path"))?,
-        )
+        )?;
+        let provider = match &self.model_manifest {
+            Some(manifest) => provider.with_manifest(manifest.clone()),
+            None => provider,
+        };
+        Ok(provider.with_integrity(self.get_integrity_policy()?))
+    }
+
+    /// Read the sequential model spec, if one was given
+    pub fn get_model_spec(&self) -> Result<Option<ModelSpec>> {
+        self.model_spec
+            .as_ref()
+            .map(|path| {
+                ModelSpec::from_file(path).map_err(|e| {
+                    crate::Error::custom(format!("Invalid model spec {}: {}", path.display(), e))
+                })
+            })
+            .transpose()
+    }
+
+    /// Create an InferenceEngineBuilder for the selected model
+    pub fn engine_builder(&self) -> Result<InferenceEngineBuilder> {
+        let mut builder = InferenceEngineBuilder::new()
+            .device(self.get_device()?)
+            .dtype(self.get_dtype()?);
+        if let Some(quantization) = self.quantization {
+            builder = builder.quantization(quantization);
+        }
+        if let Some(arch) = self.model_architecture {
+            builder = builder.model_architecture(arch);
+        }
+        if let Some(spec) = self.get_model_spec()? {
+            builder = builder.model_spec(spec);
+        }
+        Ok(builder)
+    }
+}
+
+impl Args {
+    /// Get the model arguments of the server
+    pub fn get_model_args(&self) -> Result<&ModelArgs> {
+        self.model
+            .as_ref()
+            .ok_or_else(|| crate::Error::custom("--model-weights is required"))
     }
 
     /// Get the server address
@@ -91,6 +542,18 @@ impl Args {
             .map_err(|e| crate::Error::custom(format!("Invalid address '{}': {}", self.address, e)))
     }
 
+    /// Get the address of the HTTP/JSON gateway, if enabled
+    pub fn get_http_address(&self) -> Result<Option<SocketAddr>> {
+        self.http_address
+            .as_ref()
+            .map(|address| {
+                address.parse().map_err(|e| {
+                    crate::Error::custom(format!("Invalid HTTP address '{}': {}", address, e))
+                })
+            })
+            .transpose()
+    }
+
     /// Get the tracing level
     pub fn get_tracing_level(&self) -> Result<tracing::Level> {
         match self.log_level.to_lowercase().as_str() {
@@ -106,16 +569,79 @@ impl Args {
         }
     }
 
+    /// Default prediction options of the server
+    pub fn get_predict_options(&self) -> Result<PredictOptions> {
+        Ok(PredictOptions {
+            top_k: self.top_k,
+            rejection: RejectionPolicy::new()
+                .with_min_confidence(self.min_confidence)?
+                .with_min_margin(self.min_margin)?,
+            ..Default::default()
+        })
+    }
+
+    /// Candidate model to roll out, if one was given
+    ///
+    /// The candidate is checked with the sidecar digest and signature options of the
+    /// served model; an explicit `--weights-sha256` only applies to the served weights.
+    pub fn get_candidate_config(&self) -> Result<Option<CandidateConfig>> {
+        let Some(weights) = &self.candidate_weights else {
+            return Ok(None);
+        };
+        let model = self.get_model_args()?;
+        let mut policy = IntegrityPolicy::new().with_sha256_sidecar(model.weights_sha256_sidecar);
+        if let Some(key_path) = &model.weights_public_key {
+            policy = policy.with_public_key(load_public_key(key_path)?);
+        }
+        let provider = LocalFileProvider::new(weights.clone()).with_integrity(policy);
+        let provider = match &self.candidate_manifest {
+            Some(manifest) => provider.with_manifest(manifest.clone()),
+            None => provider,
+        };
+
+        let mut candidate =
+            CandidateConfig::new(provider, self.rollout_mode, self.rollout_percent)?;
+        if let Some(arch) = self.candidate_architecture {
+            candidate = candidate.with_model_architecture(arch);
+        }
+        Ok(Some(candidate))
+    }
+
     /// Convert CLI args to ServerConfig
     pub fn to_server_config(&self) -> Result<ServerConfig> {
-        ConfigBuilder::new()
+        let model = self.get_model_args()?;
+        let mut builder = ConfigBuilder::new()
             .address(self.get_address()?)
-            .device(self.get_device()?)
-            .dtype(self.get_dtype()?)
-            .weights_provider(self.get_weights_provider()?)
-            .model_architecture(self.model_architecture)
+            .device(model.get_device()?)
+            .dtype(model.get_dtype()?)
+            .weights_provider(model.get_weights_provider()?)
+            .predict_options(self.get_predict_options()?)
             .tracing_level(self.get_tracing_level()?)
-            .build()
+            .format(self.log_format.clone())
+            .grpc_web(self.grpc_web)
+            .allowed_origins(self.allowed_origins.clone());
+        if let Some(address) = self.get_http_address()? {
+            builder = builder.http_address(address);
+        }
+        if let Some(quantization) = model.quantization {
+            builder = builder.quantization(quantization);
+        }
+        if let Some(arch) = model.model_architecture {
+            builder = builder.model_architecture(arch);
+        }
+        if let Some(spec) = model.get_model_spec()? {
+            builder = builder.model_spec(spec);
+        }
+        if let Some(name) = &self.model_name {
+            builder = builder.model_name(name.clone());
+        }
+        if let Some(candidate) = self.get_candidate_config()? {
+            builder = builder.candidate(candidate);
+        }
+        if let Some(dir) = &self.feedback_dir {
+            builder = builder.feedback_dir(dir.clone());
+        }
+        builder.build()
     }
 }
 
@@ -124,9 +650,48 @@ mod tests {
     use super::*;
     use clap::Parser;
 
+    fn model_args() -> ModelArgs {
+        ModelArgs {
+            model_architecture: Some(ModelArchitecture::Conv),
+            model_weights: PathBuf::from("test.bin"),
+            model_manifest: None,
+            model_spec: None,
+            weights_sha256: None,
+            weights_sha256_sidecar: false,
+            weights_public_key: None,
+            weights_signature: None,
+            device: "cpu".to_string(),
+            dtype: "f32".to_string(),
+            quantization: None,
+        }
+    }
+
+    fn args(model: ModelArgs) -> Args {
+        Args {
+            command: None,
+            model: Some(model),
+            address: "[::1]:50051".to_string(),
+            http_address: None,
+            grpc_web: false,
+            allowed_origins: Vec::new(),
+            log_level: "info".to_string(),
+            log_format: LogFormat::Pretty,
+            top_k: 1,
+            min_confidence: 0.0,
+            min_margin: 0.0,
+            model_name: None,
+            candidate_weights: None,
+            candidate_architecture: None,
+            candidate_manifest: None,
+            rollout_mode: RolloutMode::Shadow,
+            rollout_percent: 10.0,
+            feedback_dir: None,
+        }
+    }
+
     #[test]
     fn test_parse_args() {
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
             "rs-candle",
             "--model-architecture",
             "conv",
@@ -139,71 +704,390 @@ mod tests {
         ])
         .unwrap();
 
-        assert!(matches!(args.model_architecture, ModelArchitecture::Conv));
-        assert_eq!(args.model_weights, PathBuf::from("/path/to/weights.bin"));
-        assert_eq!(args.device, "cpu");
-        assert_eq!(args.dtype, "f32");
+        assert!(matches!(
+            args.get_model_args().unwrap().model_architecture,
+            Some(ModelArchitecture::Conv)
+        ));
+        assert_eq!(
+            args.get_model_args().unwrap().model_weights,
+            PathBuf::from("/path/to/weights.bin")
+        );
+        assert_eq!(args.get_model_args().unwrap().device, "cpu");
+        assert_eq!(args.get_model_args().unwrap().dtype, "f32");
+        assert!(args.command.is_none());
     }
 
     #[test]
     fn test_default_values() {
-        let args = Args::try_parse_from(&[
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-architecture",
+            "mlp",
+            "--model-weights",
+            "/path/to/weights.bin",
+        ])
+        .unwrap();
+
+        assert!(matches!(
+            args.get_model_args().unwrap().model_architecture,
+            Some(ModelArchitecture::MLP)
+        ));
+        assert_eq!(args.get_model_args().unwrap().device, "cpu");
+        assert_eq!(args.get_model_args().unwrap().dtype, "f32");
+        assert!(args.get_model_args().unwrap().weights_sha256.is_none());
+        assert!(!args.get_model_args().unwrap().weights_sha256_sidecar);
+    }
+
+    #[test]
+    fn test_architecture_optional() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--model-manifest",
+            "/path/to/model.manifest.toml",
+        ])
+        .unwrap();
+
+        assert!(args.get_model_args().unwrap().model_architecture.is_none());
+        assert_eq!(
+            args.get_model_args().unwrap().model_manifest,
+            Some(PathBuf::from("/path/to/model.manifest.toml"))
+        );
+    }
+
+    #[test]
+    fn test_parse_integrity_args() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-architecture",
+            "conv",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--weights-sha256",
+            "abc123",
+            "--weights-sha256-sidecar",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args.get_model_args().unwrap().weights_sha256.as_deref(),
+            Some("abc123")
+        );
+        assert!(args.get_model_args().unwrap().weights_sha256_sidecar);
+
+        let policy = args
+            .get_model_args()
+            .unwrap()
+            .get_integrity_policy()
+            .unwrap();
+        assert_eq!(policy.sha256.as_deref(), Some("abc123"));
+        assert!(policy.sha256_sidecar);
+        assert!(policy.public_key.is_none());
+    }
+
+    #[test]
+    fn test_signature_requires_public_key() {
+        let result = Args::try_parse_from([
+            "rs-candle",
+            "--model-architecture",
+            "conv",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--weights-signature",
+            "/path/to/weights.sig",
+        ]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_validate_command() {
+        let args = Args::try_parse_from([
             "rs-candle",
+            "validate",
             "--model-architecture",
             "mlp",
             "--model-weights",
             "/path/to/weights.bin",
+            "--strict",
         ])
         .unwrap();
 
-        assert!(matches!(args.model_architecture, ModelArchitecture::MLP));
-        assert_eq!(args.device, "cpu");
-        assert_eq!(args.dtype, "f32");
+        match args.command {
+            Some(Command::Validate(validate)) => {
+                assert!(validate.strict);
+                assert!(!validate.json);
+                assert_eq!(
+                    validate.model.model_weights,
+                    PathBuf::from("/path/to/weights.bin")
+                );
+            }
+            other => panic!("Expected validate command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_predict_options() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--top-k",
+            "3",
+            "--min-confidence",
+            "0.8",
+        ])
+        .unwrap();
+
+        let options = args.get_predict_options().unwrap();
+        assert_eq!(options.top_k, 3);
+        assert_eq!(options.rejection.min_confidence, 0.8);
+        assert_eq!(options.rejection.min_margin, 0.0);
+
+        let mut args = args;
+        args.min_margin = 2.0;
+        assert!(args.get_predict_options().is_err());
+    }
+
+    #[test]
+    fn test_parse_calibrate_command() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "calibrate",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--images",
+            "t10k-images-idx3-ubyte",
+            "--labels",
+            "t10k-labels-idx1-ubyte",
+            "--dry-run",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Calibrate(calibrate)) => {
+                assert!(calibrate.dry_run);
+                assert_eq!(calibrate.bins, 15);
+                assert_eq!(calibrate.labels, PathBuf::from("t10k-labels-idx1-ubyte"));
+            }
+            other => panic!("Expected calibrate command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_evaluate_command() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "evaluate",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--images",
+            "t10k-images-idx3-ubyte.gz",
+            "--labels",
+            "t10k-labels-idx1-ubyte.gz",
+            "--min-accuracy",
+            "0.98",
+            "--json",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Evaluate(evaluate)) => {
+                assert!(evaluate.json);
+                assert_eq!(evaluate.batch_size, 256);
+                assert_eq!(evaluate.min_accuracy, Some(0.98));
+            }
+            other => panic!("Expected evaluate command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_train_command() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "train",
+            "--model-architecture",
+            "conv",
+            "--images",
+            "train-images-idx3-ubyte.gz",
+            "--labels",
+            "train-labels-idx1-ubyte.gz",
+            "--output",
+            "model.safetensors",
+            "--optimizer",
+            "sgd",
+            "--learning-rate",
+            "0.05",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Train(train)) => {
+                assert!(matches!(train.model_architecture, ModelArchitecture::Conv));
+                assert_eq!(train.optimizer, OptimizerKind::Sgd);
+                assert_eq!(train.learning_rate, 0.05);
+                assert_eq!(train.epochs, 5);
+                assert!(train.test_images.is_none());
+            }
+            other => panic!("Expected train command, got {:?}", other),
+        }
+
+        let result = Args::try_parse_from([
+            "rs-candle",
+            "train",
+            "--model-architecture",
+            "mlp",
+            "--images",
+            "train-images-idx3-ubyte",
+            "--labels",
+            "train-labels-idx1-ubyte",
+            "--output",
+            "model.safetensors",
+            "--optimizer",
+            "rmsprop",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_finetune_command() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "finetune",
+            "--model-weights",
+            "/path/to/weights.safetensors",
+            "--feedback-dir",
+            "feedback",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Finetune(finetune)) => {
+                assert_eq!(finetune.feedback_dir, PathBuf::from("feedback"));
+                assert_eq!(finetune.learning_rate, 1e-4);
+                assert!(finetune.output.is_none());
+            }
+            other => panic!("Expected finetune command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_quantize_command() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "quantize",
+            "--model-weights",
+            "/path/to/weights.safetensors",
+            "--quantization",
+            "Q4_0",
+            "--images",
+            "t10k-images-idx3-ubyte",
+            "--labels",
+            "t10k-labels-idx1-ubyte",
+        ])
+        .unwrap();
+
+        match args.command {
+            Some(Command::Quantize(quantize)) => {
+                assert_eq!(quantize.model.quantization, Some(Quantization::Q4_0));
+                assert!(quantize.output.is_none());
+                assert!(quantize.images.is_some());
+            }
+            other => panic!("Expected quantize command, got {:?}", other),
+        }
+
+        let result = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.safetensors",
+            "--quantization",
+            "q3",
+        ]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_web_args() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--grpc-web",
+            "--allowed-origin",
+            "https://digits.example.com",
+            "--allowed-origin",
+            "http://localhost:5173",
+        ])
+        .unwrap();
+
+        let config = args.to_server_config().unwrap();
+        assert!(config.web.grpc_web);
+        assert_eq!(
+            config.web.allowed_origins,
+            ["https://digits.example.com", "http://localhost:5173"]
+        );
+    }
+
+    #[test]
+    fn test_parse_candidate_args() {
+        let args = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--candidate-weights",
+            "/path/to/candidate.bin",
+            "--candidate-architecture",
+            "conv",
+            "--rollout-mode",
+            "canary",
+            "--rollout-percent",
+            "5",
+        ])
+        .unwrap();
+
+        let candidate = args.get_candidate_config().unwrap().unwrap();
+        assert_eq!(candidate.mode, RolloutMode::Canary);
+        assert_eq!(candidate.percent, 5.0);
+        assert!(matches!(
+            candidate.model_architecture,
+            Some(ModelArchitecture::Conv)
+        ));
+
+        let args =
+            Args::try_parse_from(["rs-candle", "--model-weights", "/path/to/weights.bin"]).unwrap();
+        assert!(args.get_candidate_config().unwrap().is_none());
+
+        let result = Args::try_parse_from([
+            "rs-candle",
+            "--model-weights",
+            "/path/to/weights.bin",
+            "--candidate-architecture",
+            "conv",
+        ]);
+        assert!(result.is_err());
     }
 
     #[test]
     fn test_get_device() {
-        let args = Args {
-            model_architecture: ModelArchitecture::Conv,
-            model_weights: PathBuf::from("test.bin"),
-            device: "cpu".to_string(),
-            dtype: "f32".to_string(),
-            address: "[::1]:50051".to_string(),
-            log_level: "info".to_string(),
-            log_format: LogFormat::Pretty,
-        };
+        let args = args(model_args());
 
-        let device = args.get_device().unwrap();
+        let device = args.get_model_args().unwrap().get_device().unwrap();
         assert!(matches!(device, Device::Cpu));
     }
 
     #[test]
     fn test_get_dtype() {
-        let args = Args {
-            model_architecture: ModelArchitecture::Conv,
-            model_weights: PathBuf::from("test.bin"),
-            device: "cpu".to_string(),
-            dtype: "f32".to_string(),
-            address: "[::1]:50051".to_string(),
-            log_level: "info".to_string(),
-            log_format: LogFormat::Pretty,
-        };
+        let args = args(model_args());
 
-        let dtype = args.get_dtype().unwrap();
+        let dtype = args.get_model_args().unwrap().get_dtype().unwrap();
         assert_eq!(dtype, DType::F32);
     }
 
     #[test]
     fn test_get_address() {
-        let args = Args {
-            model_architecture: ModelArchitecture::Conv,
-            model_weights: PathBuf::from("test.bin"),
-            device: "cpu".to_string(),
-            dtype: "f32".to_string(),
-            address: "127.0.0.1:8080".to_string(),
-            log_level: "info".to_string(),
-            log_format: LogFormat::Pretty,
-        };
+        let mut args = args(model_args());
+        args.address = "127.0.0.1:8080".to_string();
 
         let addr = args.get_address().unwrap();
         assert_eq!(addr.to_string(), "127.0.0.1:8080");
@@ -211,15 +1095,8 @@ mod tests {
 
     #[test]
     fn test_get_tracing_level() {
-        let args = Args {
-            model_architecture: ModelArchitecture::Conv,
-            model_weights: PathBuf::from("test.bin"),
-            device: "cpu".to_string(),
-            dtype: "f32".to_string(),
-            address: "[::1]:50051".to_string(),
-            log_level: "debug".to_string(),
-            log_format: LogFormat::Pretty,
-        };
+        let mut args = args(model_args());
+        args.log_level = "debug".to_string();
 
         let level = args.get_tracing_level().unwrap();
         assert_eq!(level, tracing::Level::DEBUG);
@@ -0,0 +1,128 @@
+use serde::Serialize;
+
+use super::{dataset_logits, load_dataset};
+use crate::cli::CalibrateArgs;
+use crate::inference_engine::calibration::{
+    ReliabilityDiagram, fit_temperature, negative_log_likelihood, softmax,
+};
+use crate::inference_engine::weights_provider::WeightsProvider;
+use crate::{Error, Result};
+
+/// Calibration quality of the model before and after temperature scaling
+#[derive(Debug, Serialize)]
+struct CalibrationReport {
+    samples: usize,
+    accuracy: f32,
+    temperature: f32,
+    /// Temperature previously stored in the manifest, if any
+    previous_temperature: Option<f32>,
+    uncalibrated: Calibration,
+    calibrated: Calibration,
+}
+
+#[derive(Debug, Serialize)]
+struct Calibration {
+    nll: f32,
+    ece: f32,
+    reliability: ReliabilityDiagram,
+}
+
+impl Calibration {
+    fn new(logits: &[Vec<f32>], labels: &[u32], temperature: f32, bins: usize) -> Self {
+        let probabilities: Vec<Vec<f32>> = logits.iter().map(|l| softmax(l, temperature)).collect();
+        let reliability = ReliabilityDiagram::new(&probabilities, labels, bins);
+        Self {
+            nll: negative_log_likelihood(logits, labels, temperature),
+            ece: reliability.ece,
+            reliability,
+        }
+    }
+}
+
+/// Fit the temperature of the selected model and write it into its manifest
+///
+/// The raw logits are used, so a temperature already in the manifest is replaced
+/// rather than compounded.
+pub fn run(args: CalibrateArgs) -> Result<()> {
+    let dataset = load_dataset(&args.images, &args.labels, args.limit)?;
+    let provider = args.model.get_weights_provider()?;
+    let engine = args.model.engine_builder()?.build(provider.clone())?;
+
+    let logits = dataset_logits(&engine, &dataset, args.batch_size)?;
+    let labels: Vec<u32> = dataset.labels().iter().map(|&label| label as u32).collect();
+    let correct = logits
+        .iter()
+        .zip(&labels)
+        .filter(|(logits, label)| argmax(logits) == **label)
+        .count();
+
+    let temperature = fit_temperature(&logits, &labels);
+    let report = CalibrationReport {
+        samples: labels.len(),
+        accuracy: correct as f32 / labels.len() as f32,
+        temperature,
+        previous_temperature: engine.manifest().temperature,
+        uncalibrated: Calibration::new(&logits, &labels, 1.0, args.bins),
+        calibrated: Calibration::new(&logits, &labels, temperature, args.bins),
+    };
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::custom(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print_report(&report);
+    }
+
+    if !args.dry_run {
+        let mut manifest = provider.load_manifest()?.unwrap_or_default();
+        manifest.temperature = Some(temperature);
+        let path = provider.manifest_path();
+        manifest.save(&path)?;
+        eprintln!("Wrote temperature {:.4} to {}", temperature, path.display());
+    }
+    Ok(())
+}
+
+fn argmax(values: &[f32]) -> u32 {
+    values
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(0, |(i, _)| i as u32)
+}
+
+fn print_report(report: &CalibrationReport) {
+    println!(
+        "Samples: {}, accuracy: {:.2}%",
+        report.samples,
+        report.accuracy * 100.0
+    );
+    if let Some(previous) = report.previous_temperature {
+        println!("Previous temperature: {:.4}", previous);
+    }
+    println!("Fitted temperature:   {:.4}", report.temperature);
+    println!();
+    println!("              NLL      ECE");
+    for (name, calibration) in [
+        ("uncalibrated", &report.uncalibrated),
+        ("calibrated", &report.calibrated),
+    ] {
+        println!(
+            "{:<12} {:>6.4}   {:>6.4}",
+            name, calibration.nll, calibration.ece
+        );
+    }
+    println!();
+    println!("Reliability (calibrated):");
+    println!("  confidence     count  confidence  accuracy");
+    for bin in &report.calibrated.reliability.bins {
+        if bin.count == 0 {
+            continue;
+        }
+        println!(
+            "  {:.2} - {:.2}  {:>8}  {:>10.4}  {:>8.4}",
+            bin.lower, bin.upper, bin.count, bin.confidence, bin.accuracy
+        );
+    }
+}
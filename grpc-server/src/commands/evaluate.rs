@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::load_dataset;
+use crate::cli::EvaluateArgs;
+use crate::inference_engine::PredictOptions;
+use crate::{Error, Result};
+
+const NUM_CLASSES: usize = 10;
+
+/// Accuracy and throughput of a model on a labeled dataset
+#[derive(Debug, Serialize)]
+struct EvaluationReport {
+    samples: usize,
+    accuracy: f32,
+    classes: Vec<ClassMetrics>,
+    /// `confusion[label][digit]` counts the images of `label` predicted as `digit`
+    confusion: Vec<Vec<usize>>,
+    throughput: Throughput,
+}
+
+#[derive(Debug, Serialize)]
+struct ClassMetrics {
+    class: u32,
+    /// Images of this class in the dataset
+    support: usize,
+    /// Fraction of the predictions of this class that are correct, 0 if never predicted
+    precision: f32,
+    /// Fraction of the images of this class predicted correctly, 0 if absent
+    recall: f32,
+}
+
+#[derive(Debug, Serialize)]
+struct Throughput {
+    batch_size: usize,
+    seconds: f64,
+    images_per_second: f64,
+}
+
+impl EvaluationReport {
+    fn new(labels: &[u32], digits: &[u32], batch_size: usize, elapsed: Duration) -> Self {
+        let mut confusion = vec![vec![0usize; NUM_CLASSES]; NUM_CLASSES];
+        for (&label, &digit) in labels.iter().zip(digits) {
+            confusion[label as usize][(digit as usize).min(NUM_CLASSES - 1)] += 1;
+        }
+        let correct: usize = (0..NUM_CLASSES).map(|class| confusion[class][class]).sum();
+        let classes = (0..NUM_CLASSES)
+            .map(|class| {
+                let support: usize = confusion[class].iter().sum();
+                let predicted: usize = confusion.iter().map(|row| row[class]).sum();
+                let hits = confusion[class][class] as f32;
+                ClassMetrics {
+                    class: class as u32,
+                    support,
+                    precision: hits / predicted.max(1) as f32,
+                    recall: hits / support.max(1) as f32,
+                }
+            })
+            .collect();
+        let seconds = elapsed.as_secs_f64();
+        Self {
+            samples: labels.len(),
+            accuracy: correct as f32 / labels.len().max(1) as f32,
+            classes,
+            confusion,
+            throughput: Throughput {
+                batch_size,
+                seconds,
+                images_per_second: labels.len() as f64 / seconds.max(f64::EPSILON),
+            },
+        }
+    }
+}
+
+/// Score the selected model against a labeled dataset
+///
+/// Fails if the accuracy is below `--min-accuracy`, so the command can gate CI jobs.
+pub fn run(args: EvaluateArgs) -> Result<()> {
+    let dataset = load_dataset(&args.images, &args.labels, args.limit)?;
+    let engine = args
+        .model
+        .engine_builder()?
+        .build(args.model.get_weights_provider()?)?;
+
+    let batch_size = args.batch_size.max(1);
+    let indices: Vec<usize> = (0..dataset.len()).collect();
+    let mut digits = Vec::with_capacity(dataset.len());
+    let mut elapsed = Duration::ZERO;
+    for batch in indices.chunks(batch_size) {
+        let inputs = batch.iter().map(|&i| dataset.pixels(i)).collect();
+        // Only the forward passes are timed, not reading the images
+        let started = Instant::now();
+        let predictions = engine.predict_batch(inputs, &PredictOptions::default())?;
+        elapsed += started.elapsed();
+        digits.extend(predictions.into_iter().map(|prediction| prediction.digit));
+    }
+
+    let labels: Vec<u32> = dataset.labels().iter().map(|&label| label as u32).collect();
+    let report = EvaluationReport::new(&labels, &digits, batch_size, elapsed);
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::custom(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print_report(&report);
+    }
+
+    if let Some(min_accuracy) = args.min_accuracy
+        && report.accuracy < min_accuracy
+    {
+        return Err(Error::custom(format!(
+            "Accuracy {:.4} is below the minimum of {:.4}",
+            report.accuracy, min_accuracy
+        )));
+    }
+    Ok(())
+}
+
+fn print_report(report: &EvaluationReport) {
+    println!(
+        "Samples: {}, accuracy: {:.2}%",
+        report.samples,
+        report.accuracy * 100.0
+    );
+    println!(
+        "Throughput: {:.1} images/s ({:.3}s, batches of {})",
+        report.throughput.images_per_second,
+        report.throughput.seconds,
+        report.throughput.batch_size
+    );
+    println!();
+    println!("digit  support  precision  recall");
+    for class in &report.classes {
+        println!(
+            "{:>5}  {:>7}  {:>9.4}  {:>6.4}",
+            class.class, class.support, class.precision, class.recall
+        );
+    }
+    println!();
+    println!("Confusion matrix (rows: label, columns: predicted digit):");
+    print!("     ");
+    for digit in 0..NUM_CLASSES {
+        print!("{:>6}", digit);
+    }
+    println!();
+    for (label, row) in report.confusion.iter().enumerate() {
+        print!("{:>5}", label);
+        for count in row {
+            print!("{:>6}", count);
+        }
+        println!();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluation_report() {
+        let labels = [0, 0, 1, 1, 2];
+        let digits = [0, 1, 1, 1, 2];
+        let report = EvaluationReport::new(&labels, &digits, 2, Duration::from_secs(2));
+
+        assert_eq!(report.samples, 5);
+        assert!((report.accuracy - 0.8).abs() < 1e-6);
+        assert_eq!(report.confusion[0][1], 1);
+        assert_eq!(report.classes[0].support, 2);
+        assert!((report.classes[0].precision - 1.0).abs() < 1e-6);
+        assert!((report.classes[0].recall - 0.5).abs() < 1e-6);
+        assert!((report.classes[1].precision - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(report.classes[5].support, 0);
+        assert_eq!(report.classes[5].precision, 0.0);
+        assert!((report.throughput.images_per_second - 2.5).abs() < 1e-9);
+    }
+}
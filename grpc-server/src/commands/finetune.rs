@@ -0,0 +1,197 @@
+use std::path::{Path, PathBuf};
+
+use candle_core::{DType, Device};
+use candle_nn::{Module, VarBuilder, VarMap};
+use mnist::{ConvNet, MnistMLP, TrainConfig, Trainer};
+
+use super::load_dataset;
+use crate::cli::FinetuneArgs;
+use crate::feedback::FeedbackStore;
+use crate::inference_engine::ModelArchitecture;
+use crate::inference_engine::weights_provider::{LocalFileProvider, WeightsProvider};
+use crate::{Error, Result};
+
+/// Fine-tune the served weights on the collected feedback and save a new version
+///
+/// The base weights go through the usual manifest, integrity and validation checks, and
+/// the base model is left untouched.
+pub fn run(args: FinetuneArgs) -> Result<()> {
+    let provider = args.model.get_weights_provider()?;
+    let engine = args.model.engine_builder()?.build(provider.clone())?;
+    let architecture = engine.architecture();
+    let mut manifest = engine.manifest().clone();
+    drop(engine);
+
+    let mut dataset = FeedbackStore::open(&args.feedback_dir)?.dataset()?;
+    let feedback_samples = dataset.len();
+    if let (Some(images), Some(labels)) = (&args.replay_images, &args.replay_labels) {
+        dataset.extend(&load_dataset(images, labels, args.replay_limit)?)?;
+    }
+    let test_set = match (&args.test_images, &args.test_labels) {
+        (Some(images), Some(labels)) => Some(load_dataset(images, labels, None)?),
+        _ => None,
+    };
+
+    let device = args.model.get_device()?;
+    let mut varmap = VarMap::new();
+    let varbuilder = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+    let model: Box<dyn Module> = match architecture {
+        ModelArchitecture::MLP => Box::new(MnistMLP::new(varbuilder)?),
+        ModelArchitecture::Conv => Box::new(ConvNet::new(varbuilder)?),
+        other => {
+            return Err(Error::invalid_argument(format!(
+                "Fine-tuning is only supported for the mlp and conv architectures, got {:?}",
+                other
+            )));
+        }
+    };
+    load_verified(&varmap, &provider)?;
+
+    let config = TrainConfig {
+        optimizer: args.optimizer,
+        learning_rate: args.learning_rate,
+        weight_decay: args.weight_decay,
+        epochs: args.epochs,
+        batch_size: args.batch_size,
+        seed: args.seed,
+    };
+    let mut trainer = Trainer::new(config, device, architecture.input_shape());
+    if !manifest.normalization.is_identity() {
+        trainer =
+            trainer.with_normalization(manifest.normalization.mean, manifest.normalization.std);
+    }
+
+    println!(
+        "Fine-tuning {} on {} feedback samples ({} in total)",
+        args.model.model_weights.display(),
+        feedback_samples,
+        dataset.len()
+    );
+    let base_accuracy = test_set
+        .as_ref()
+        .map(|test_set| trainer.accuracy(&*model, test_set))
+        .transpose()?;
+    if let Some(accuracy) = base_accuracy {
+        println!("Base test accuracy {:.2}%", accuracy * 100.0);
+    }
+    let history = trainer.fit(&*model, &varmap, &dataset, |stats| {
+        println!(
+            "Epoch {:>3}: loss {:.4}, accuracy {:.2}%",
+            stats.epoch,
+            stats.loss,
+            stats.accuracy * 100.0
+        );
+        Ok(())
+    })?;
+
+    let output = match args.output {
+        Some(output) => output,
+        None => next_version(&args.model.model_weights),
+    };
+    varmap.save(&output)?;
+
+    // The temperature and the OOD threshold were fitted to the base weights
+    manifest.temperature = None;
+    manifest.ood = None;
+    manifest.architecture = Some(architecture);
+    manifest.input_shape = Some(architecture.input_shape());
+    manifest
+        .metrics
+        .insert("feedback_samples".to_string(), feedback_samples as f64);
+    if let Some(last) = history.last() {
+        manifest
+            .metrics
+            .insert("train_accuracy".to_string(), last.accuracy as f64);
+        manifest
+            .metrics
+            .insert("train_loss".to_string(), last.loss as f64);
+    }
+    if let Some(test_set) = &test_set {
+        let accuracy = trainer.accuracy(&*model, test_set)?;
+        println!("Fine-tuned test accuracy {:.2}%", accuracy * 100.0);
+        manifest
+            .metrics
+            .insert("test_accuracy".to_string(), accuracy as f64);
+    }
+    let manifest_path = LocalFileProvider::new(output.clone()).manifest_path();
+    manifest.save(&manifest_path)?;
+    eprintln!(
+        "Saved weights to {} and manifest to {}",
+        output.display(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+/// Fill the variables from the weights of `provider`, from the same bytes the integrity
+/// checks ran on
+fn load_verified(varmap: &VarMap, provider: &LocalFileProvider) -> Result<()> {
+    let tensors = provider.load_weights()?.tensors(&Device::Cpu)?;
+    for (name, var) in varmap.data().lock().unwrap().iter() {
+        let tensor = tensors
+            .get(name)
+            .ok_or_else(|| Error::custom(format!("Missing tensor {} in the base weights", name)))?;
+        var.set(&tensor.to_dtype(var.dtype())?.to_device(var.device())?)?;
+    }
+    Ok(())
+}
+
+/// First free `<name>-v<N>.safetensors` next to the weights, counting up from their version
+///
+/// The fine-tuned weights of a sharded checkpoint are saved as a single file named after
+/// its index.
+fn next_version(weights: &Path) -> PathBuf {
+    let name = weights
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("model");
+    let stem = [".safetensors.index.json", ".safetensors"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+    let (name, version) = stem
+        .rsplit_once("-v")
+        .and_then(|(name, version)| Some((name, version.parse::<u32>().ok()?)))
+        .unwrap_or((stem, 0));
+    (version + 1..)
+        .map(|version| weights.with_file_name(format!("{}-v{}.safetensors", name, version)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_next_version() {
+        let dir = TempDir::new("finetune");
+
+        let base = dir.join("mnist_convnet.safetensors");
+        assert_eq!(
+            next_version(&base),
+            dir.join("mnist_convnet-v1.safetensors")
+        );
+        assert_eq!(
+            next_version(&dir.join("mnist_convnet-v3.safetensors")),
+            dir.join("mnist_convnet-v4.safetensors")
+        );
+
+        std::fs::write(dir.join("mnist_convnet-v1.safetensors"), b"").unwrap();
+        assert_eq!(
+            next_version(&base),
+            dir.join("mnist_convnet-v2.safetensors")
+        );
+
+        // Sharded checkpoints are versioned by the name of their index
+        assert_eq!(
+            next_version(&dir.join("mnist_convnet.safetensors.index.json")),
+            dir.join("mnist_convnet-v2.safetensors")
+        );
+        assert_eq!(
+            next_version(&dir.join("large-v2.safetensors.index.json")),
+            dir.join("large-v3.safetensors")
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use mnist::MnistDataset;
+use serde::Serialize;
+
+use super::{dataset_logits, load_dataset};
+use crate::cli::FitOodArgs;
+use crate::inference_engine::InferenceEngine;
+use crate::inference_engine::ood::{FeatureStatistics, OodConfig, OodMethod, quantile_threshold};
+use crate::inference_engine::weights_provider::WeightsProvider;
+use crate::{Error, Result};
+
+/// Scores of the dataset and the threshold picked from them
+#[derive(Debug, Serialize)]
+struct OodReport {
+    samples: usize,
+    method: OodMethod,
+    /// Number of features the Mahalanobis statistics were fitted on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    features: Option<usize>,
+    quantile: f32,
+    threshold: f32,
+    /// Threshold previously stored in the manifest, if any
+    previous_threshold: Option<f32>,
+    scores: ScoreSummary,
+}
+
+#[derive(Debug, Serialize)]
+struct ScoreSummary {
+    min: f32,
+    mean: f32,
+    median: f32,
+    max: f32,
+}
+
+impl ScoreSummary {
+    fn new(scores: &[f32]) -> Result<Self> {
+        Ok(Self {
+            min: scores.iter().copied().fold(f32::INFINITY, f32::min),
+            mean: scores.iter().sum::<f32>() / scores.len() as f32,
+            median: quantile_threshold(scores, 0.5)?,
+            max: scores.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+        })
+    }
+}
+
+/// Fit OOD scoring of the selected model and write it into its manifest
+///
+/// The dataset should be the training data, or data like it: the threshold is the score
+/// below which `quantile` of it falls, so that share of in-distribution inputs passes.
+pub fn run(args: FitOodArgs) -> Result<()> {
+    let dataset = load_dataset(&args.images, &args.labels, args.limit)?;
+    let provider = args.model.get_weights_provider()?;
+    let engine = args.model.engine_builder()?.build(provider.clone())?;
+
+    let logits = dataset_logits(&engine, &dataset, args.batch_size)?;
+    let labels: Vec<u32> = dataset.labels().iter().map(|&label| label as u32).collect();
+    let features = match args.method {
+        OodMethod::Mahalanobis => Some(dataset_features(&engine, &dataset, args.batch_size)?),
+        _ => None,
+    };
+    let statistics = features
+        .as_ref()
+        .map(|features| FeatureStatistics::fit(features, &labels))
+        .transpose()?;
+
+    let mut config = OodConfig {
+        method: args.method,
+        threshold: None,
+        statistics,
+    };
+    let scores = logits
+        .iter()
+        .enumerate()
+        .map(|(i, logits)| {
+            let features = features.as_ref().map(|features| features[i].as_slice());
+            config.score(logits, features)
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    let threshold = quantile_threshold(&scores, args.quantile)?;
+    config.threshold = Some(threshold);
+
+    let report = OodReport {
+        samples: scores.len(),
+        method: args.method,
+        features: config
+            .statistics
+            .as_ref()
+            .map(|statistics| statistics.dimensions()),
+        quantile: args.quantile,
+        threshold,
+        previous_threshold: engine.ood().and_then(|ood| ood.threshold),
+        scores: ScoreSummary::new(&scores)?,
+    };
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::custom(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print_report(&report);
+    }
+
+    if !args.dry_run {
+        let mut manifest = provider.load_manifest()?.unwrap_or_default();
+        manifest.ood = Some(config);
+        let path = provider.manifest_path();
+        manifest.save(&path)?;
+        eprintln!(
+            "Wrote {:?} OOD threshold {:.4} to {}",
+            args.method,
+            threshold,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Run the model over the dataset in batches and collect the penultimate features
+fn dataset_features(
+    engine: &InferenceEngine,
+    dataset: &MnistDataset,
+    batch_size: usize,
+) -> Result<Vec<Vec<f32>>> {
+    let indices: Vec<usize> = (0..dataset.len()).collect();
+    let mut features = Vec::with_capacity(dataset.len());
+    for batch in indices.chunks(batch_size.max(1)) {
+        let inputs = batch.iter().map(|&i| dataset.pixels(i)).collect();
+        features.extend(engine.embed_batch(inputs)?);
+    }
+    Ok(features)
+}
+
+fn print_report(report: &OodReport) {
+    println!("Samples: {}, method: {:?}", report.samples, report.method);
+    if let Some(features) = report.features {
+        println!("Features: {}", features);
+    }
+    println!();
+    println!("       min      mean    median       max");
+    println!(
+        "{:>10.4}{:>10.4}{:>10.4}{:>10.4}",
+        report.scores.min, report.scores.mean, report.scores.median, report.scores.max
+    );
+    println!();
+    if let Some(previous) = report.previous_threshold {
+        println!("Previous threshold: {:.4}", previous);
+    }
+    println!(
+        "Threshold:          {:.4} ({:.1}% of the samples below)",
+        report.threshold,
+        report.quantile * 100.0
+    );
+}
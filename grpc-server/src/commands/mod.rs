@@ -0,0 +1,63 @@
+//! Maintenance subcommands of the server binary
+use std::path::Path;
+
+use mnist::MnistDataset;
+
+use crate::cli::Command;
+use crate::inference_engine::InferenceEngine;
+use crate::{Error, Result};
+
+pub mod calibrate;
+pub mod evaluate;
+pub mod finetune;
+pub mod fit_ood;
+pub mod quantize;
+pub mod train;
+pub mod validate;
+
+/// Run a subcommand to completion
+pub async fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Validate(args) => validate::run(args),
+        Command::Calibrate(args) => calibrate::run(args),
+        Command::Evaluate(args) => evaluate::run(args),
+        Command::Train(args) => train::run(args),
+        Command::Finetune(args) => finetune::run(args),
+        Command::Quantize(args) => quantize::run(args),
+        Command::FitOod(args) => fit_ood::run(args),
+    }
+}
+
+/// Read a labeled MNIST dataset from IDX files, keeping at most `limit` samples
+fn load_dataset(images: &Path, labels: &Path, limit: Option<usize>) -> Result<MnistDataset> {
+    let mut dataset = MnistDataset::load(images, labels)
+        .map_err(|e| Error::custom(format!("Failed to read dataset: {}", e)))?;
+    if let Some(limit) = limit {
+        dataset.truncate(limit);
+    }
+    if dataset.is_empty() {
+        return Err(Error::custom("Dataset is empty"));
+    }
+    if dataset.image_size() != (28, 28) {
+        return Err(Error::custom(format!(
+            "Expected 28x28 images, got {:?}",
+            dataset.image_size()
+        )));
+    }
+    Ok(dataset)
+}
+
+/// Run the model over the dataset in batches and collect the raw logits
+fn dataset_logits(
+    engine: &InferenceEngine,
+    dataset: &MnistDataset,
+    batch_size: usize,
+) -> Result<Vec<Vec<f32>>> {
+    let indices: Vec<usize> = (0..dataset.len()).collect();
+    let mut logits = Vec::with_capacity(dataset.len());
+    for batch in indices.chunks(batch_size.max(1)) {
+        let inputs = batch.iter().map(|&i| dataset.pixels(i)).collect();
+        logits.extend(engine.logits_batch(inputs)?);
+    }
+    Ok(logits)
+}
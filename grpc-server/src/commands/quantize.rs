@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+
+use candle_core::Device;
+use candle_core::quantized::gguf_file::Value;
+use mnist::{Quantization, QuantizedWeights};
+use serde::Serialize;
+
+use super::{dataset_logits, load_dataset};
+use crate::cli::QuantizeArgs;
+use crate::inference_engine::weights_provider::{LocalFileProvider, WeightsProvider};
+use crate::inference_engine::{InferenceEngine, ModelArchitecture};
+use crate::{Error, Result};
+
+/// Size and accuracy of the quantized weights compared to the original ones
+#[derive(Debug, Serialize)]
+struct QuantizationReport {
+    quantization: String,
+    output: PathBuf,
+    original_bytes: u64,
+    quantized_bytes: u64,
+    tensors: Vec<TensorFormat>,
+    /// Set if a labeled dataset was given
+    evaluation: Option<AccuracyDelta>,
+}
+
+#[derive(Debug, Serialize)]
+struct TensorFormat {
+    name: String,
+    /// Stored shape, matrices are padded to a multiple of the block size
+    shape: Vec<usize>,
+    format: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AccuracyDelta {
+    samples: usize,
+    original_accuracy: f32,
+    quantized_accuracy: f32,
+    /// Quantized minus original accuracy
+    delta: f32,
+    /// Fraction of the images both models predict the same digit for
+    agreement: f32,
+}
+
+impl AccuracyDelta {
+    fn new(labels: &[u32], original: &[u32], quantized: &[u32]) -> Self {
+        let original_accuracy = matching(labels, original);
+        let quantized_accuracy = matching(labels, quantized);
+        Self {
+            samples: labels.len(),
+            original_accuracy,
+            quantized_accuracy,
+            delta: quantized_accuracy - original_accuracy,
+            agreement: matching(original, quantized),
+        }
+    }
+}
+
+/// Fraction of the positions where both slices hold the same digit
+fn matching(a: &[u32], b: &[u32]) -> f32 {
+    a.iter().zip(b).filter(|(a, b)| a == b).count() as f32 / a.len().max(1) as f32
+}
+
+/// Quantize the linear layers of an MLP or ConvNet into a GGUF file the server can load
+///
+/// The manifest is copied next to the GGUF file. With `--images/--labels` both models
+/// are evaluated and the change in accuracy is reported.
+pub fn run(mut args: QuantizeArgs) -> Result<()> {
+    let quantization = args.model.quantization.take().unwrap_or(Quantization::Q8_0);
+    let provider = args.model.get_weights_provider()?;
+    if provider.is_gguf() {
+        return Err(Error::invalid_argument(format!(
+            "{} is already quantized",
+            args.model.model_weights.display()
+        )));
+    }
+    // Building the engine runs the usual manifest, integrity and validation checks
+    let original = args.model.engine_builder()?.build(provider.clone())?;
+    let architecture = original.architecture();
+    if !matches!(
+        architecture,
+        ModelArchitecture::MLP | ModelArchitecture::Conv
+    ) {
+        return Err(Error::invalid_argument(format!(
+            "Quantization is only supported for the mlp and conv architectures, got {:?}",
+            architecture
+        )));
+    }
+
+    let tensors = provider.load_weights()?.tensors(&Device::Cpu)?;
+    let weights = QuantizedWeights::quantize(&tensors, quantization, &Device::Cpu)?;
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| default_output(&args.model.model_weights, quantization));
+    write_gguf(&weights, &output, architecture, quantization)?;
+
+    let mut manifest = original.manifest().clone();
+    manifest.architecture = Some(architecture);
+    manifest.input_shape = Some(architecture.input_shape());
+    let manifest_path = LocalFileProvider::new(output.clone()).manifest_path();
+    manifest.save(&manifest_path)?;
+
+    let evaluation = match (&args.images, &args.labels) {
+        (Some(images), Some(labels)) => {
+            let dataset = load_dataset(images, labels, args.limit)?;
+            let quantized = args
+                .model
+                .engine_builder()?
+                .build(LocalFileProvider::new(output.clone()))?;
+            let labels: Vec<u32> = dataset.labels().iter().map(|&label| label as u32).collect();
+            let digits = |engine: &InferenceEngine| -> Result<Vec<u32>> {
+                Ok(dataset_logits(engine, &dataset, args.batch_size)?
+                    .iter()
+                    .map(|logits| argmax(logits))
+                    .collect())
+            };
+            let delta = AccuracyDelta::new(&labels, &digits(&original)?, &digits(&quantized)?);
+            manifest.metrics.insert(
+                "quantized_accuracy".to_string(),
+                delta.quantized_accuracy as f64,
+            );
+            manifest
+                .metrics
+                .insert("quantized_accuracy_delta".to_string(), delta.delta as f64);
+            manifest.save(&manifest_path)?;
+            Some(delta)
+        }
+        _ => None,
+    };
+
+    let report = QuantizationReport {
+        quantization: quantization.to_string(),
+        original_bytes: file_size(&args.model.model_weights)?,
+        quantized_bytes: file_size(&output)?,
+        output,
+        tensors: weights
+            .tensor_infos()
+            .into_iter()
+            .map(|(name, shape, dtype)| TensorFormat {
+                name,
+                shape,
+                format: format!("{:?}", dtype).to_lowercase(),
+            })
+            .collect(),
+        evaluation,
+    };
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::custom(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print_report(&report);
+    }
+    eprintln!(
+        "Saved weights to {} and manifest to {}",
+        report.output.display(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+fn write_gguf(
+    weights: &QuantizedWeights,
+    output: &Path,
+    architecture: ModelArchitecture,
+    quantization: Quantization,
+) -> Result<()> {
+    let mut file = std::fs::File::create(output)
+        .map_err(|e| Error::custom(format!("Failed to create {}: {}", output.display(), e)))?;
+    let architecture = Value::String(format!("{:?}", architecture).to_lowercase());
+    let quantization = Value::String(quantization.to_string());
+    weights.write_gguf(
+        &mut file,
+        &[
+            ("mnist.architecture", &architecture),
+            ("mnist.quantization", &quantization),
+        ],
+    )?;
+    Ok(())
+}
+
+/// `<name>-<quantization>.gguf` next to the weights
+fn default_output(weights: &Path, quantization: Quantization) -> PathBuf {
+    let stem = weights
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("model")
+        .trim_end_matches(".safetensors");
+    weights.with_file_name(format!("{}-{}.gguf", stem, quantization))
+}
+
+fn file_size(path: &Path) -> Result<u64> {
+    std::fs::metadata(path)
+        .map(|metadata| metadata.len())
+        .map_err(|e| Error::custom(format!("Failed to read {}: {}", path.display(), e)))
+}
+
+fn argmax(values: &[f32]) -> u32 {
+    values
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(0, |(class, _)| class as u32)
+}
+
+fn print_report(report: &QuantizationReport) {
+    println!(
+        "Quantized to {}: {} -> {} bytes ({:.1}%)",
+        report.quantization,
+        report.original_bytes,
+        report.quantized_bytes,
+        report.quantized_bytes as f64 * 100.0 / report.original_bytes.max(1) as f64
+    );
+    for tensor in &report.tensors {
+        println!(
+            "  {:<18} {:<12} {}",
+            tensor.name,
+            format!("{:?}", tensor.shape),
+            tensor.format
+        );
+    }
+    if let Some(evaluation) = &report.evaluation {
+        println!(
+            "Accuracy on {} samples: {:.2}% -> {:.2}% ({:+.2} points), {:.2}% of the predictions unchanged",
+            evaluation.samples,
+            evaluation.original_accuracy * 100.0,
+            evaluation.quantized_accuracy * 100.0,
+            evaluation.delta * 100.0,
+            evaluation.agreement * 100.0
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accuracy_delta() {
+        let labels = [0, 1, 2, 3];
+        let delta = AccuracyDelta::new(&labels, &[0, 1, 2, 0], &[0, 1, 0, 0]);
+        assert_eq!(delta.samples, 4);
+        assert!((delta.original_accuracy - 0.75).abs() < 1e-6);
+        assert!((delta.quantized_accuracy - 0.5).abs() < 1e-6);
+        assert!((delta.delta + 0.25).abs() < 1e-6);
+        assert!((delta.agreement - 0.75).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_default_output() {
+        assert_eq!(
+            default_output(Path::new("models/mnist.safetensors"), Quantization::Q8_0),
+            PathBuf::from("models/mnist-q8_0.gguf")
+        );
+    }
+}
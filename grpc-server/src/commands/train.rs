@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use candle_core::DType;
+use candle_nn::{Module, VarBuilder, VarMap};
+use mnist::{ConvNet, MnistMLP, TrainConfig, Trainer};
+
+use super::load_dataset;
+use crate::cli::TrainArgs;
+use crate::inference_engine::ModelArchitecture;
+use crate::inference_engine::manifest::ModelManifest;
+use crate::inference_engine::weights_provider::LocalFileProvider;
+use crate::{Error, Result};
+
+/// Train a model on IDX files and save it with a manifest the server can load
+///
+/// The weights are checkpointed to `--output` after every epoch.
+pub fn run(args: TrainArgs) -> Result<()> {
+    let device = args.get_device()?;
+    let train_set = load_dataset(&args.images, &args.labels, args.limit)?;
+    let test_set = match (&args.test_images, &args.test_labels) {
+        (Some(images), Some(labels)) => Some(load_dataset(images, labels, None)?),
+        _ => None,
+    };
+
+    let mut varmap = VarMap::new();
+    let varbuilder = VarBuilder::from_varmap(&varmap, DType::F32, &device);
+    let model: Box<dyn Module> = match args.model_architecture {
+        ModelArchitecture::MLP => Box::new(MnistMLP::new(varbuilder)?),
+        ModelArchitecture::Conv => Box::new(ConvNet::new(varbuilder)?),
+        other => {
+            return Err(Error::invalid_argument(format!(
+                "Training is only supported for the mlp and conv architectures, got {:?}",
+                other
+            )));
+        }
+    };
+    if args.resume {
+        varmap.load(&args.output)?;
+        eprintln!("Resuming from {}", args.output.display());
+    }
+
+    let config = TrainConfig {
+        optimizer: args.optimizer,
+        learning_rate: args.learning_rate,
+        weight_decay: args.weight_decay,
+        epochs: args.epochs,
+        batch_size: args.batch_size,
+        seed: args.seed,
+    };
+    let trainer = Trainer::new(config, device, args.model_architecture.input_shape());
+    let mut test_accuracy = None;
+    let history = trainer.fit(&*model, &varmap, &train_set, |stats| {
+        let mut line = format!(
+            "Epoch {:>3}: loss {:.4}, train accuracy {:.2}%",
+            stats.epoch,
+            stats.loss,
+            stats.accuracy * 100.0
+        );
+        if let Some(test_set) = &test_set {
+            let accuracy = trainer.accuracy(&*model, test_set)?;
+            line.push_str(&format!(", test accuracy {:.2}%", accuracy * 100.0));
+            test_accuracy = Some(accuracy);
+        }
+        println!("{}", line);
+        varmap.save(&args.output)
+    })?;
+
+    let mut metrics = BTreeMap::new();
+    if let Some(last) = history.last() {
+        metrics.insert("train_accuracy".to_string(), last.accuracy as f64);
+        metrics.insert("train_loss".to_string(), last.loss as f64);
+    }
+    if let Some(accuracy) = test_accuracy {
+        metrics.insert("test_accuracy".to_string(), accuracy as f64);
+    }
+    let manifest = ModelManifest {
+        architecture: Some(args.model_architecture),
+        input_shape: Some(args.model_architecture.input_shape()),
+        metrics,
+        ..Default::default()
+    };
+    let manifest_path = LocalFileProvider::new(args.output.clone()).manifest_path();
+    manifest.save(&manifest_path)?;
+    eprintln!(
+        "Saved weights to {} and manifest to {}",
+        args.output.display(),
+        manifest_path.display()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference_engine::InferenceEngine;
+    use crate::inference_engine::weights_provider::WeightsProvider;
+    use crate::test_util::TempDir;
+    use mnist::MnistDataset;
+    use mnist::train::OptimizerKind;
+
+    #[test]
+    fn test_checkpoint_is_served() {
+        let dir = TempDir::new("train");
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        for label in 0..10u8 {
+            let image = vec![label * 25; 28 * 28];
+            MnistDataset::append(&images, &labels, (28, 28), &image, label).unwrap();
+        }
+
+        let output = dir.join("mnist_mlp.safetensors");
+        run(TrainArgs {
+            model_architecture: ModelArchitecture::MLP,
+            images: images.clone(),
+            labels: labels.clone(),
+            test_images: Some(images),
+            test_labels: Some(labels),
+            limit: None,
+            output: output.clone(),
+            resume: false,
+            optimizer: OptimizerKind::Sgd,
+            learning_rate: 0.1,
+            weight_decay: 0.0,
+            epochs: 2,
+            batch_size: 4,
+            seed: 42,
+            device: "cpu".to_string(),
+        })
+        .unwrap();
+
+        // The architecture is taken from the manifest written next to the weights
+        let provider = LocalFileProvider::new(output);
+        let manifest = provider.load_manifest().unwrap().unwrap();
+        assert!(manifest.metrics.contains_key("test_accuracy"));
+        let engine = InferenceEngine::builder().build(provider).unwrap();
+        assert_eq!(engine.architecture(), ModelArchitecture::MLP);
+        let prediction = engine.predict(vec![0.5; 784]).unwrap();
+        assert_eq!(prediction.probabilities.len(), 10);
+    }
+}
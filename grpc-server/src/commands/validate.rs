@@ -0,0 +1,32 @@
+use crate::cli::ValidateArgs;
+use crate::{Error, Result};
+
+/// Print the validation report for the selected weights
+///
+/// Fails if the model cannot be built from the weights, or with `--strict` if the
+/// weights also contain tensors the architecture does not use.
+pub fn run(args: ValidateArgs) -> Result<()> {
+    let provider = args.model.get_weights_provider()?;
+    let report = args.model.engine_builder()?.validate(&provider)?;
+
+    if args.json {
+        let json = serde_json::to_string_pretty(&report)
+            .map_err(|e| Error::custom(format!("Failed to serialize report: {}", e)))?;
+        println!("{}", json);
+    } else {
+        println!("{}", report);
+    }
+
+    let passed = if args.strict {
+        report.is_exact()
+    } else {
+        report.is_valid()
+    };
+    if !passed {
+        return Err(Error::custom(format!(
+            "{} failed validation",
+            args.model.model_weights.display()
+        )));
+    }
+    Ok(())
+}
@@ -1,18 +1,24 @@
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use crate::cli::LogFormat;
-use crate::inference_engine::ModelArchitecture;
 use crate::inference_engine::weights_provider::LocalFileProvider;
+use crate::inference_engine::{ModelArchitecture, PredictOptions};
+use crate::rollout::CandidateConfig;
 use crate::{Error, Result};
 use candle_core::{DType, Device};
+use mnist::{ModelSpec, Quantization};
 
 /// Server configuration
 #[derive(Debug, Clone)]
 pub struct ServerConfig {
     pub address: SocketAddr,
+    /// Address of the HTTP/JSON gateway, disabled if not set
+    pub http_address: Option<SocketAddr>,
     pub service: ServiceConfig,
     pub tracing: TracingConfig,
+    pub web: WebConfig,
 }
 
 /// Service-specific configuration
@@ -20,8 +26,30 @@ pub struct ServerConfig {
 pub struct ServiceConfig {
     pub device: Device,
     pub dtype: DType,
+    /// Quantization of the linear layers, applied to the served and candidate models
+    pub quantization: Option<Quantization>,
     pub weights_provider: LocalFileProvider,
-    pub model_architecture: ModelArchitecture,
+    /// Architecture to serve, taken from the model manifest if not set
+    pub model_architecture: Option<ModelArchitecture>,
+    /// Layers of a sequential model, taken from the model manifest if not set
+    pub model_spec: Option<ModelSpec>,
+    /// Options applied to requests that do not set their own
+    pub predict_options: PredictOptions,
+    /// Name the model is served under, defaults to the name in the model artifact
+    pub model_name: Option<String>,
+    /// Candidate model compared against the served one in shadow or canary mode
+    pub candidate: Option<CandidateConfig>,
+    /// Directory collecting corrected samples sent with `SubmitFeedback`, disabled if not set
+    pub feedback_dir: Option<PathBuf>,
+}
+
+/// Access from browsers
+#[derive(Debug, Clone, Default)]
+pub struct WebConfig {
+    /// Accept gRPC-Web requests, so browsers can call the service without a proxy
+    pub grpc_web: bool,
+    /// Origins allowed to make cross-origin requests, `*` for any; CORS is disabled if empty
+    pub allowed_origins: Vec<String>,
 }
 
 /// Tracing configuration
@@ -35,8 +63,10 @@ impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             address: "[::1]:50051".parse().unwrap(),
+            http_address: None,
             service: ServiceConfig::default(),
             tracing: TracingConfig::default(),
+            web: WebConfig::default(),
         }
     }
 }
@@ -46,8 +76,14 @@ impl Default for ServiceConfig {
         Self {
             device: Device::Cpu,
             dtype: DType::F32,
+            quantization: None,
             weights_provider: LocalFileProvider::from_str("model.safetensors").unwrap(),
-            model_architecture: ModelArchitecture::MLP,
+            model_architecture: Some(ModelArchitecture::MLP),
+            model_spec: None,
+            predict_options: PredictOptions::default(),
+            model_name: None,
+            candidate: None,
+            feedback_dir: None,
         }
     }
 }
@@ -71,6 +107,11 @@ impl ServerConfig {
         self
     }
 
+    pub fn with_http_address(mut self, address: SocketAddr) -> Self {
+        self.http_address = Some(address);
+        self
+    }
+
     pub fn with_service_config(mut self, service: ServiceConfig) -> Self {
         self.service = service;
         self
@@ -80,6 +121,11 @@ impl ServerConfig {
         self.tracing = tracing;
         self
     }
+
+    pub fn with_web_config(mut self, web: WebConfig) -> Self {
+        self.web = web;
+        self
+    }
 }
 
 impl ServiceConfig {
@@ -87,15 +133,69 @@ impl ServiceConfig {
         device: Device,
         dtype: DType,
         weights_provider: LocalFileProvider,
-        model_architecture: ModelArchitecture,
+        model_architecture: Option<ModelArchitecture>,
     ) -> Self {
         Self {
             device,
             dtype,
+            quantization: None,
             weights_provider,
             model_architecture,
+            model_spec: None,
+            predict_options: PredictOptions::default(),
+            model_name: None,
+            candidate: None,
+            feedback_dir: None,
         }
     }
+
+    pub fn with_quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
+    pub fn with_model_spec(mut self, spec: ModelSpec) -> Self {
+        self.model_spec = Some(spec);
+        self
+    }
+
+    pub fn with_predict_options(mut self, options: PredictOptions) -> Self {
+        self.predict_options = options;
+        self
+    }
+
+    pub fn with_model_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.model_name = Some(name.into());
+        self
+    }
+
+    pub fn with_candidate(mut self, candidate: CandidateConfig) -> Self {
+        self.candidate = Some(candidate);
+        self
+    }
+
+    pub fn with_feedback_dir(mut self, dir: PathBuf) -> Self {
+        self.feedback_dir = Some(dir);
+        self
+    }
+}
+
+impl WebConfig {
+    pub fn new(grpc_web: bool) -> Self {
+        Self {
+            grpc_web,
+            allowed_origins: Vec::new(),
+        }
+    }
+
+    pub fn with_allowed_origins<I, S>(mut self, origins: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.allowed_origins = origins.into_iter().map(Into::into).collect();
+        self
+    }
 }
 
 impl TracingConfig {
@@ -112,24 +212,40 @@ impl TracingConfig {
 /// Configuration builder for easy construction from CLI args or environment
 pub struct ConfigBuilder {
     address: Option<SocketAddr>,
+    http_address: Option<SocketAddr>,
     device: Option<Device>,
     dtype: Option<DType>,
+    quantization: Option<Quantization>,
     weights_provider: Option<LocalFileProvider>,
     model_architecture: Option<ModelArchitecture>,
+    model_spec: Option<ModelSpec>,
+    predict_options: Option<PredictOptions>,
+    model_name: Option<String>,
+    candidate: Option<CandidateConfig>,
+    feedback_dir: Option<PathBuf>,
     tracing_level: Option<tracing::Level>,
     format: Option<LogFormat>,
+    web: WebConfig,
 }
 
 impl ConfigBuilder {
     pub fn new() -> Self {
         Self {
             address: None,
+            http_address: None,
             device: None,
             dtype: None,
+            quantization: None,
             weights_provider: None,
             model_architecture: None,
+            model_spec: None,
+            predict_options: None,
+            model_name: None,
+            candidate: None,
+            feedback_dir: None,
             tracing_level: None,
             format: None,
+            web: WebConfig::default(),
         }
     }
 
@@ -138,6 +254,11 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn http_address(mut self, address: SocketAddr) -> Self {
+        self.http_address = Some(address);
+        self
+    }
+
     pub fn device(mut self, device: Device) -> Self {
         self.device = Some(device);
         self
@@ -148,6 +269,11 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
     pub fn weights_provider(mut self, provider: LocalFileProvider) -> Self {
         self.weights_provider = Some(provider);
         self
@@ -158,6 +284,31 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn model_spec(mut self, spec: ModelSpec) -> Self {
+        self.model_spec = Some(spec);
+        self
+    }
+
+    pub fn predict_options(mut self, options: PredictOptions) -> Self {
+        self.predict_options = Some(options);
+        self
+    }
+
+    pub fn model_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.model_name = Some(name.into());
+        self
+    }
+
+    pub fn candidate(mut self, candidate: CandidateConfig) -> Self {
+        self.candidate = Some(candidate);
+        self
+    }
+
+    pub fn feedback_dir(mut self, dir: PathBuf) -> Self {
+        self.feedback_dir = Some(dir);
+        self
+    }
+
     pub fn tracing_level(mut self, level: tracing::Level) -> Self {
         self.tracing_level = Some(level);
         self
@@ -168,16 +319,30 @@ impl ConfigBuilder {
         self
     }
 
+    pub fn grpc_web(mut self, enabled: bool) -> Self {
+        self.web.grpc_web = enabled;
+        self
+    }
+
+    pub fn allowed_origins(mut self, origins: Vec<String>) -> Self {
+        self.web.allowed_origins = origins;
+        self
+    }
+
     pub fn build(self) -> Result<ServerConfig> {
         let service = ServiceConfig {
             device: self.device.unwrap_or(Device::Cpu),
             dtype: self.dtype.unwrap_or(DType::F32),
+            quantization: self.quantization,
             weights_provider: self
                 .weights_provider
                 .ok_or_else(|| Error::custom("Weights provider must be specified"))?,
-            model_architecture: self
-                .model_architecture
-                .ok_or_else(|| Error::custom("Model architecture must be specified"))?,
+            model_architecture: self.model_architecture,
+            model_spec: self.model_spec,
+            predict_options: self.predict_options.unwrap_or_default(),
+            model_name: self.model_name,
+            candidate: self.candidate,
+            feedback_dir: self.feedback_dir,
         };
 
         let tracing = TracingConfig {
@@ -189,8 +354,10 @@ impl ConfigBuilder {
             address: self
                 .address
                 .unwrap_or_else(|| "[::1]:50051".parse().unwrap()),
+            http_address: self.http_address,
             service,
             tracing,
+            web: self.web,
         })
     }
 }
@@ -214,9 +381,11 @@ mod tests {
         assert_eq!(config.service.dtype, DType::F32);
         assert!(matches!(
             config.service.model_architecture,
-            ModelArchitecture::MLP
+            Some(ModelArchitecture::MLP)
         ));
         assert_eq!(config.tracing.level, tracing::Level::INFO);
+        assert!(!config.web.grpc_web);
+        assert!(config.web.allowed_origins.is_empty());
     }
 
     #[test]
@@ -229,13 +398,20 @@ mod tests {
             .weights_provider(provider)
             .model_architecture(ModelArchitecture::Conv)
             .tracing_level(tracing::Level::DEBUG)
+            .grpc_web(true)
+            .allowed_origins(vec!["https://digits.example.com".to_string()])
             .build()
             .unwrap();
 
         assert_eq!(config.address.to_string(), "127.0.0.1:8080");
+        assert!(config.web.grpc_web);
+        assert_eq!(
+            config.web.allowed_origins,
+            vec!["https://digits.example.com".to_string()]
+        );
         assert!(matches!(
             config.service.model_architecture,
-            ModelArchitecture::Conv
+            Some(ModelArchitecture::Conv)
         ));
         assert_eq!(config.tracing.level, tracing::Level::DEBUG);
     }
@@ -1,5 +1,7 @@
 use tonic::Status;
 
+use crate::inference_engine::validation::ValidationReport;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 use derive_more::{Display, From};
@@ -9,6 +11,25 @@ pub enum Error {
     #[from]
     Custom(String),
 
+    /// Model weights failed a configured integrity check
+    #[display("Integrity check failed: {_0}")]
+    Integrity(String),
+
+    /// Request options that cannot be honored
+    #[display("Invalid argument: {_0}")]
+    InvalidArgument(String),
+
+    /// A request addressed a model that is not served
+    #[display("Model not found: {_0}")]
+    ModelNotFound(String),
+
+    /// A request needs a feature the server was started without
+    #[display("Not enabled: {_0}")]
+    NotEnabled(String),
+
+    /// Model weights do not match the architecture
+    InvalidWeights(Box<ValidationReport>),
+
     // External errors
     #[from]
     CandleError(candle_core::Error),
@@ -18,6 +39,14 @@ impl Error {
     pub fn custom<S: Into<String>>(msg: S) -> Self {
         Error::Custom(msg.into())
     }
+
+    pub fn integrity<S: Into<String>>(msg: S) -> Self {
+        Error::Integrity(msg.into())
+    }
+
+    pub fn invalid_argument<S: Into<String>>(msg: S) -> Self {
+        Error::InvalidArgument(msg.into())
+    }
 }
 
 impl From<&str> for Error {
@@ -30,7 +59,14 @@ impl From<Error> for Status {
     fn from(error: Error) -> Status {
         match error {
             // Map your custom error variants to appropriate gRPC status codes
-            _ => Status::unknown("An unknown error occurred"),
+            Error::Integrity(_) => Status::failed_precondition("Model weights failed verification"),
+            Error::InvalidWeights(report) => Status::failed_precondition(report.to_string()),
+            Error::InvalidArgument(msg) => Status::invalid_argument(msg),
+            Error::ModelNotFound(name) => Status::not_found(format!("Model {} not found", name)),
+            Error::NotEnabled(msg) => Status::unimplemented(msg),
+            Error::Custom(_) | Error::CandleError(_) => {
+                Status::unknown("An unknown error occurred")
+            }
         }
     }
 }
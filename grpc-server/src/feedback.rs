@@ -0,0 +1,178 @@
+//! On-disk collection of human-corrected samples
+//!
+//! Corrected images are stored as a pair of IDX files in the layout of the MNIST dataset,
+//! so they can be read with [`MnistDataset::load`] for fine-tuning:
+//!
+//! ```text
+//! <dir>/feedback-images-idx3-ubyte   28x28 images, white digits on a black background
+//! <dir>/feedback-labels-idx1-ubyte   corrected labels
+//! <dir>/feedback.jsonl               one record per sample, in the same order
+//! <dir>/raw/<id>                     image bytes as submitted
+//! ```
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use mnist::MnistDataset;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+const IMAGES_FILE: &str = "feedback-images-idx3-ubyte";
+const LABELS_FILE: &str = "feedback-labels-idx1-ubyte";
+const RECORDS_FILE: &str = "feedback.jsonl";
+
+/// A sample submitted by a reviewer
+#[derive(Debug, Clone)]
+pub struct FeedbackSample {
+    /// Image bytes as sent to `Predict`
+    pub data: Vec<u8>,
+    /// 28x28 pixels decoded from `data`, white digits on a black background
+    pub pixels: Vec<u8>,
+    pub label: u8,
+    pub predicted_label: Option<u8>,
+    pub model: String,
+}
+
+/// Record of a stored sample in `feedback.jsonl`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeedbackRecord {
+    pub id: String,
+    /// Position of the sample in the IDX files
+    pub index: usize,
+    pub label: u8,
+    pub predicted_label: Option<u8>,
+    pub model: String,
+    /// Seconds since the Unix epoch
+    pub received_at: u64,
+}
+
+/// Directory collecting feedback samples
+#[derive(Debug)]
+pub struct FeedbackStore {
+    dir: PathBuf,
+    /// Serializes appends, which must keep the IDX files and the records in sync
+    lock: Mutex<()>,
+}
+
+impl FeedbackStore {
+    /// Open the store in `dir`, creating the directory if needed
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(dir.join("raw")).map_err(|e| {
+            Error::custom(format!(
+                "Failed to create feedback directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        Ok(Self {
+            dir,
+            lock: Mutex::new(()),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Store a sample and return its record
+    pub fn record(&self, sample: FeedbackSample) -> Result<FeedbackRecord> {
+        if sample.label > 9 {
+            return Err(Error::invalid_argument(format!(
+                "label must be a digit, got {}",
+                sample.label
+            )));
+        }
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let id = uuid::Uuid::new_v4().to_string();
+        let write_error =
+            |e: std::io::Error| Error::custom(format!("Failed to store feedback: {}", e));
+        std::fs::write(self.dir.join("raw").join(&id), &sample.data).map_err(write_error)?;
+
+        let count = MnistDataset::append(
+            self.dir.join(IMAGES_FILE),
+            self.dir.join(LABELS_FILE),
+            (28, 28),
+            &sample.pixels,
+            sample.label,
+        )?;
+        let record = FeedbackRecord {
+            id,
+            index: count - 1,
+            label: sample.label,
+            predicted_label: sample.predicted_label,
+            model: sample.model,
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+        };
+        let line = serde_json::to_string(&record)
+            .map_err(|e| Error::custom(format!("Failed to serialize feedback: {}", e)))?;
+        let mut records = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(RECORDS_FILE))
+            .map_err(write_error)?;
+        writeln!(records, "{}", line).map_err(write_error)?;
+        Ok(record)
+    }
+
+    /// Read the collected samples
+    pub fn dataset(&self) -> Result<MnistDataset> {
+        MnistDataset::load(self.dir.join(IMAGES_FILE), self.dir.join(LABELS_FILE)).map_err(|e| {
+            Error::custom(format!(
+                "Failed to read feedback in {}: {}",
+                self.dir.display(),
+                e
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn sample(label: u8) -> FeedbackSample {
+        FeedbackSample {
+            data: vec![1, 2, 3],
+            pixels: vec![label * 20; 784],
+            label,
+            predicted_label: Some(1),
+            model: "mnist".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_feedback_store() {
+        let dir = TempDir::new("feedback");
+        let store = FeedbackStore::open(&dir).unwrap();
+        assert!(store.dataset().is_err());
+
+        let first = store.record(sample(7)).unwrap();
+        let second = store.record(sample(4)).unwrap();
+        assert_eq!(first.index, 0);
+        assert_eq!(second.index, 1);
+        assert_eq!(
+            std::fs::read(dir.join("raw").join(&first.id)).unwrap(),
+            [1, 2, 3]
+        );
+
+        let dataset = store.dataset().unwrap();
+        assert_eq!(dataset.labels(), [7, 4]);
+        assert_eq!(dataset.image(1), [80; 784]);
+
+        let records = std::fs::read_to_string(dir.join(RECORDS_FILE)).unwrap();
+        let records: Vec<FeedbackRecord> = records
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].id, second.id);
+
+        assert!(store.record(sample(12)).is_err());
+    }
+}
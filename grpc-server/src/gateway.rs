@@ -0,0 +1,786 @@
+//! HTTP/JSON gateway to the gRPC service
+//!
+//! Requests are translated to the protobuf messages and handled by the same
+//! [`MnistService`], so both protocols share validation, rollouts and feedback:
+//!
+//! ```text
+//! POST /v1/models/{name}:predict    MnistImage      -> MnistPrediction
+//! POST /v1/models/{name}:feedback   Feedback        -> FeedbackReceipt
+//! POST /v1/models/{name}:recognize  NumberImage     -> RecognizedNumber
+//! POST /v1/models/{name}:explain    ExplainRequest  -> Explanation
+//! POST /v1/models/{name}:embed      EmbedRequest    -> Embeddings
+//! GET  /metrics                                      Prometheus text format
+//! ```
+//!
+//! The image is sent either as a `multipart/form-data` upload in an `image` field, with
+//! the other fields as text parts, or as JSON with the image base64-encoded. Embed requests
+//! take several `image` parts, or an `images` list in JSON. Errors are
+//! returned with the HTTP status that grpc-gateway maps the gRPC status code to.
+use std::sync::Arc;
+
+use axum::Router;
+use axum::extract::{FromRequest, Multipart, Path, Request, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use tonic::{Code, Status};
+
+use crate::proto::mnist_server::Mnist;
+use crate::proto::{
+    self, EmbedRequest, Embeddings, ExplainRequest, Explanation, Feedback, MnistImage,
+    MnistPrediction, NumberImage, RecognizedNumber,
+};
+use crate::service::{MnistService, check_embed_images};
+use crate::{Error, Result};
+
+/// Routes of the gateway, sharing `service` with the gRPC server
+pub fn router(service: Arc<MnistService>) -> Router {
+    Router::new()
+        .route("/v1/models/{target}", post(model_method))
+        .route("/metrics", get(metrics))
+        .with_state(service)
+}
+
+/// Fields of a predict or feedback request, named as in the protobuf messages
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct GatewayRequest {
+    /// Base64-encoded image, only used in JSON bodies
+    image: Option<String>,
+    /// Base64-encoded images of an embed request, only used in JSON bodies
+    images: Vec<String>,
+    /// `softmax`, `log_softmax` or `logits`
+    output_mode: Option<String>,
+    temperature: f32,
+    top_k: u32,
+    min_confidence: f32,
+    min_margin: f32,
+    include_members: bool,
+    test_time_augmentation: Option<bool>,
+    /// Corrected digit of a feedback request, or the digit to explain
+    label: Option<i32>,
+    predicted_label: Option<i32>,
+    /// `occlusion` or `gradient`
+    method: Option<String>,
+    /// Return a PNG heatmap with an explanation
+    heatmap: bool,
+    /// Scale embeddings to unit length
+    normalize: bool,
+}
+
+impl GatewayRequest {
+    fn into_image(self, data: Vec<u8>, model: String) -> Result<MnistImage> {
+        let output_mode = match self.output_mode.as_deref() {
+            None => proto::OutputMode::Softmax,
+            Some(mode) => {
+                proto::OutputMode::from_str_name(&mode.to_uppercase()).ok_or_else(|| {
+                    Error::invalid_argument(format!(
+                        "unknown output_mode '{mode}', expected softmax, log_softmax or logits"
+                    ))
+                })?
+            }
+        };
+        Ok(MnistImage {
+            data,
+            output_mode: output_mode as i32,
+            temperature: self.temperature,
+            top_k: self.top_k,
+            min_confidence: self.min_confidence,
+            min_margin: self.min_margin,
+            include_members: self.include_members,
+            model,
+            test_time_augmentation: self.test_time_augmentation,
+        })
+    }
+
+    fn into_feedback(self, data: Vec<u8>, model: String) -> Result<Feedback> {
+        let label = self
+            .label
+            .ok_or_else(|| Error::invalid_argument("feedback requires a label"))?;
+        Ok(Feedback {
+            data,
+            label,
+            predicted_label: self.predicted_label,
+            model,
+        })
+    }
+
+    fn into_number_image(self, data: Vec<u8>, model: String) -> NumberImage {
+        NumberImage {
+            data,
+            min_confidence: self.min_confidence,
+            model,
+        }
+    }
+
+    fn into_explain_request(self, data: Vec<u8>, model: String) -> Result<ExplainRequest> {
+        let method = match self.method.as_deref() {
+            None => proto::ExplainMethod::Occlusion,
+            Some(method) => proto::ExplainMethod::from_str_name(&method.to_uppercase())
+                .ok_or_else(|| {
+                    Error::invalid_argument(format!(
+                        "unknown method '{method}', expected occlusion or gradient"
+                    ))
+                })?,
+        };
+        Ok(ExplainRequest {
+            data,
+            label: self.label,
+            method: method as i32,
+            render_heatmap: self.heatmap,
+            model,
+        })
+    }
+}
+
+/// JSON form of [`MnistPrediction`]
+#[derive(Debug, Serialize, Deserialize)]
+struct PredictResponse {
+    model: String,
+    label: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label_name: Option<String>,
+    probabilities: Vec<f32>,
+    scores: Vec<f32>,
+    top_k: Vec<ClassScore>,
+    rejected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rejection_reason: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    members: Vec<MemberPrediction>,
+    views: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ood_score: Option<f32>,
+    out_of_distribution: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ClassScore {
+    label: i32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label_name: Option<String>,
+    probability: f32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MemberPrediction {
+    name: String,
+    label: i32,
+    probabilities: Vec<f32>,
+}
+
+impl From<MnistPrediction> for PredictResponse {
+    fn from(prediction: MnistPrediction) -> Self {
+        Self {
+            model: prediction.model,
+            label: prediction.label,
+            label_name: non_empty(prediction.label_name),
+            probabilities: prediction.probabilities,
+            scores: prediction.scores,
+            top_k: prediction
+                .top_k
+                .into_iter()
+                .map(|score| ClassScore {
+                    label: score.label,
+                    label_name: non_empty(score.label_name),
+                    probability: score.probability,
+                })
+                .collect(),
+            rejected: prediction.rejected,
+            rejection_reason: non_empty(prediction.rejection_reason),
+            members: prediction
+                .members
+                .into_iter()
+                .map(|member| MemberPrediction {
+                    name: member.name,
+                    label: member.label,
+                    probabilities: member.probabilities,
+                })
+                .collect(),
+            views: prediction.views,
+            ood_score: prediction.ood_score,
+            out_of_distribution: prediction.out_of_distribution,
+        }
+    }
+}
+
+/// JSON form of [`RecognizedNumber`]
+#[derive(Debug, Serialize, Deserialize)]
+struct NumberResponse {
+    model: String,
+    number: String,
+    confidence: f32,
+    rejected: bool,
+    digits: Vec<DigitResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DigitResponse {
+    label: i32,
+    probability: f32,
+    rejected: bool,
+    bounding_box: BoundingBox,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BoundingBox {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+impl From<RecognizedNumber> for NumberResponse {
+    fn from(number: RecognizedNumber) -> Self {
+        Self {
+            model: number.model,
+            number: number.number,
+            confidence: number.confidence,
+            rejected: number.rejected,
+            digits: number
+                .digits
+                .into_iter()
+                .map(|digit| DigitResponse {
+                    label: digit.label,
+                    probability: digit.probability,
+                    rejected: digit.rejected,
+                    bounding_box: digit
+                        .bounding_box
+                        .map(|b| BoundingBox {
+                            x: b.x,
+                            y: b.y,
+                            width: b.width,
+                            height: b.height,
+                        })
+                        .unwrap_or_default(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// JSON form of [`Embeddings`], one list of features per image
+#[derive(Debug, Serialize, Deserialize)]
+struct EmbedResponse {
+    model: String,
+    dimensions: u32,
+    embeddings: Vec<Vec<f32>>,
+}
+
+impl From<Embeddings> for EmbedResponse {
+    fn from(embeddings: Embeddings) -> Self {
+        Self {
+            model: embeddings.model,
+            dimensions: embeddings.dimensions,
+            embeddings: embeddings
+                .embeddings
+                .into_iter()
+                .map(|embedding| embedding.values)
+                .collect(),
+        }
+    }
+}
+
+/// JSON form of [`Explanation`], with the heatmap base64-encoded
+#[derive(Debug, Serialize, Deserialize)]
+struct ExplainResponse {
+    model: String,
+    label: i32,
+    probability: f32,
+    predicted_label: i32,
+    attributions: Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    heatmap: Option<String>,
+}
+
+impl From<Explanation> for ExplainResponse {
+    fn from(explanation: Explanation) -> Self {
+        Self {
+            model: explanation.model,
+            label: explanation.label,
+            probability: explanation.probability,
+            predicted_label: explanation.predicted_label,
+            attributions: explanation.attributions,
+            heatmap: (!explanation.heatmap.is_empty())
+                .then(|| base64::engine::general_purpose::STANDARD.encode(explanation.heatmap)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FeedbackResponse {
+    id: String,
+    samples: u64,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+/// A gRPC status returned as an HTTP response
+#[derive(Debug)]
+struct GatewayError(Status);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ErrorBody {
+    /// gRPC status code
+    code: i32,
+    message: String,
+}
+
+impl From<Status> for GatewayError {
+    fn from(status: Status) -> Self {
+        GatewayError(status)
+    }
+}
+
+impl From<Error> for GatewayError {
+    fn from(error: Error) -> Self {
+        GatewayError(error.into())
+    }
+}
+
+impl IntoResponse for GatewayError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody {
+            code: self.0.code() as i32,
+            message: self.0.message().to_string(),
+        };
+        (http_status(self.0.code()), axum::Json(body)).into_response()
+    }
+}
+
+/// HTTP status of a gRPC status code, as mapped by grpc-gateway
+fn http_status(code: Code) -> StatusCode {
+    match code {
+        Code::Ok => StatusCode::OK,
+        // Client Closed Request, as used by nginx
+        Code::Cancelled => StatusCode::from_u16(499).unwrap(),
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => {
+            StatusCode::BAD_REQUEST
+        }
+        Code::DeadlineExceeded => StatusCode::GATEWAY_TIMEOUT,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::AlreadyExists | Code::Aborted => StatusCode::CONFLICT,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::ResourceExhausted => StatusCode::TOO_MANY_REQUESTS,
+        Code::Unimplemented => StatusCode::NOT_IMPLEMENTED,
+        Code::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+        Code::Unknown | Code::Internal | Code::DataLoss => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+/// Dispatch `POST /v1/models/{name}:{method}` to the gRPC method
+async fn model_method(
+    State(service): State<Arc<MnistService>>,
+    Path(target): Path<String>,
+    request: Request,
+) -> std::result::Result<Response, GatewayError> {
+    let Some((model, method)) = target.rsplit_once(':') else {
+        return Err(
+            Status::not_found(format!("No method in {target}, expected <model>:predict")).into(),
+        );
+    };
+    let model = model.to_string();
+    match method {
+        "predict" => {
+            let (fields, data) = read_request(request).await?;
+            let image = fields.into_image(data, model)?;
+            let prediction = service.predict(tonic::Request::new(image)).await?;
+            Ok(axum::Json(PredictResponse::from(prediction.into_inner())).into_response())
+        }
+        "feedback" => {
+            let (fields, data) = read_request(request).await?;
+            let feedback = fields.into_feedback(data, model)?;
+            let receipt = service
+                .submit_feedback(tonic::Request::new(feedback))
+                .await?
+                .into_inner();
+            let response = FeedbackResponse {
+                id: receipt.id,
+                samples: receipt.samples,
+            };
+            Ok(axum::Json(response).into_response())
+        }
+        "recognize" => {
+            let (fields, data) = read_request(request).await?;
+            let image = fields.into_number_image(data, model);
+            let number = service
+                .recognize_number(tonic::Request::new(image))
+                .await?
+                .into_inner();
+            Ok(axum::Json(NumberResponse::from(number)).into_response())
+        }
+        "explain" => {
+            let (fields, data) = read_request(request).await?;
+            let request = fields.into_explain_request(data, model)?;
+            let explanation = service
+                .explain(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            Ok(axum::Json(ExplainResponse::from(explanation)).into_response())
+        }
+        "embed" => {
+            let (fields, images) = read_images(request).await?;
+            let request = EmbedRequest {
+                images,
+                normalize: fields.normalize,
+                model,
+            };
+            let embeddings = service
+                .embed(tonic::Request::new(request))
+                .await?
+                .into_inner();
+            Ok(axum::Json(EmbedResponse::from(embeddings)).into_response())
+        }
+        other => Err(Status::not_found(format!("Unknown method {other}")).into()),
+    }
+}
+
+/// Serve the metrics of the service, and of the rollout if there is a candidate
+async fn metrics(State(service): State<Arc<MnistService>>) -> Response {
+    let mut text = String::new();
+    service.metrics().snapshot().render_prometheus(&mut text);
+    if let Some(rollout) = service.rollout_metrics() {
+        rollout.snapshot().render_prometheus(&mut text);
+    }
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], text).into_response()
+}
+
+/// Read the request fields and the single image of a multipart or JSON body
+async fn read_request(request: Request) -> Result<(GatewayRequest, Vec<u8>)> {
+    let (fields, mut images) = read_images(request).await?;
+    match images.len() {
+        0 => Err(Error::invalid_argument("missing field `image`")),
+        1 => Ok((fields, images.remove(0))),
+        n => Err(Error::invalid_argument(format!(
+            "expected one image, got {n}"
+        ))),
+    }
+}
+
+/// Read the request fields and every image, sent as `image` parts or `image`/`images` in JSON
+///
+/// More than `MAX_EMBED_IMAGES` images are rejected before any of them is decoded.
+async fn read_images(request: Request) -> Result<(GatewayRequest, Vec<Vec<u8>>)> {
+    let multipart = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("multipart/form-data"));
+    if multipart {
+        let multipart = Multipart::from_request(request, &())
+            .await
+            .map_err(|e| Error::invalid_argument(e.body_text()))?;
+        return read_multipart(multipart).await;
+    }
+
+    let axum::Json(mut fields) = axum::Json::<GatewayRequest>::from_request(request, &())
+        .await
+        .map_err(|e| Error::invalid_argument(e.body_text()))?;
+    check_embed_images(fields.images.len() + usize::from(fields.image.is_some()))?;
+    let images = fields
+        .image
+        .take()
+        .into_iter()
+        .chain(std::mem::take(&mut fields.images))
+        .map(|image| {
+            base64::engine::general_purpose::STANDARD
+                .decode(image.trim())
+                .map_err(|e| Error::invalid_argument(format!("image is not valid base64: {e}")))
+        })
+        .collect::<Result<_>>()?;
+    Ok((fields, images))
+}
+
+/// Read the `image` parts as bytes and the other parts as the fields of [`GatewayRequest`]
+async fn read_multipart(mut multipart: Multipart) -> Result<(GatewayRequest, Vec<Vec<u8>>)> {
+    let invalid =
+        |e: axum::extract::multipart::MultipartError| Error::invalid_argument(e.body_text());
+    let mut images = Vec::new();
+    let mut fields = serde_json::Map::new();
+    while let Some(field) = multipart.next_field().await.map_err(invalid)? {
+        let name = field.name().unwrap_or_default().to_string();
+        if name == "image" {
+            check_embed_images(images.len() + 1)?;
+            images.push(field.bytes().await.map_err(invalid)?.to_vec());
+            continue;
+        }
+        let text = field.text().await.map_err(invalid)?;
+        // Numbers and booleans are sent as text, anything else is kept as a string
+        let value = match serde_json::from_str(&text) {
+            Ok(value @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_))) => value,
+            _ => serde_json::Value::String(text),
+        };
+        fields.insert(name, value);
+    }
+    let fields = serde_json::from_value(serde_json::Value::Object(fields))
+        .map_err(|e| Error::invalid_argument(format!("invalid multipart fields: {e}")))?;
+    Ok((fields, images))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServiceConfig;
+    use crate::inference_engine::ModelArchitecture;
+    use crate::inference_engine::weights_provider::LocalFileProvider;
+    use crate::service::MAX_EMBED_IMAGES;
+    use crate::test_util::random_weights;
+    use axum::body::Body;
+    use candle_core::{DType, Device};
+    use tower::ServiceExt;
+
+    fn service() -> Arc<MnistService> {
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        let config = ServiceConfig::new(
+            Device::Cpu,
+            DType::F32,
+            LocalFileProvider::new(path),
+            Some(ModelArchitecture::MLP),
+        );
+        Arc::new(MnistService::new(config).unwrap())
+    }
+
+    fn png() -> Vec<u8> {
+        let image = image::GrayImage::from_fn(28, 28, |x, _| image::Luma([(x * 9) as u8]));
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    async fn send(router: Router, request: http::Request<Body>) -> (StatusCode, Vec<u8>) {
+        let response = router.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    fn json_request(uri: &str, body: serde_json::Value) -> http::Request<Body> {
+        http::Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_predict_json() {
+        let router = router(service());
+        let image = base64::engine::general_purpose::STANDARD.encode(png());
+        let request = json_request(
+            "/v1/models/mnist:predict",
+            serde_json::json!({"image": image, "top_k": 3, "output_mode": "log_softmax"}),
+        );
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        let prediction: PredictResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(prediction.model, "mnist");
+        assert_eq!(prediction.top_k.len(), 3);
+        assert_eq!(prediction.top_k[0].label, prediction.label);
+        assert!(prediction.scores.iter().all(|score| *score <= 0.0));
+
+        // Errors carry the gRPC code and the mapped HTTP status
+        let request = json_request(
+            "/v1/models/other:predict",
+            serde_json::json!({"image": image}),
+        );
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, Code::NotFound as i32);
+
+        let request = json_request(
+            "/v1/models/mnist:predict",
+            serde_json::json!({"image": "bm90IGFuIGltYWdl"}),
+        );
+        let (status, _) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let request = json_request(
+            "/v1/models/mnist:feedback",
+            serde_json::json!({"image": image, "label": 3}),
+        );
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+        let error: ErrorBody = serde_json::from_slice(&body).unwrap();
+        assert_eq!(error.code, Code::Unimplemented as i32);
+    }
+
+    #[tokio::test]
+    async fn test_explain() {
+        let router = router(service());
+        let image = base64::engine::general_purpose::STANDARD.encode(png());
+        let request = json_request(
+            "/v1/models/mnist:explain",
+            serde_json::json!({"image": image, "label": 4, "method": "gradient", "heatmap": true}),
+        );
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let explanation: ExplainResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(explanation.label, 4);
+        assert_eq!(explanation.attributions.len(), 784);
+        let heatmap = base64::engine::general_purpose::STANDARD
+            .decode(explanation.heatmap.unwrap())
+            .unwrap();
+        assert_eq!(
+            image::load_from_memory(&heatmap)
+                .unwrap()
+                .to_rgb8()
+                .dimensions(),
+            (280, 280)
+        );
+
+        let request = json_request(
+            "/v1/models/mnist:explain",
+            serde_json::json!({"image": image, "method": "lime"}),
+        );
+        let (status, _) = send(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_embed() {
+        let router = router(service());
+        let image = base64::engine::general_purpose::STANDARD.encode(png());
+        let request = json_request(
+            "/v1/models/mnist:embed",
+            serde_json::json!({"images": [image, image], "normalize": true}),
+        );
+        let (status, body) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let response: EmbedResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.dimensions, 64);
+        assert_eq!(response.embeddings.len(), 2);
+        assert_eq!(response.embeddings[0], response.embeddings[1]);
+        let norm: f32 = response.embeddings[0].iter().map(|x| x * x).sum();
+        assert!((norm - 1.0).abs() < 1e-4);
+
+        // Single-image methods reject several images
+        let request = json_request(
+            "/v1/models/mnist:predict",
+            serde_json::json!({"images": [image, image]}),
+        );
+        let (status, _) = send(router.clone(), request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Too many images are rejected before they are decoded
+        let request = json_request(
+            "/v1/models/mnist:embed",
+            serde_json::json!({"images": vec!["not base64"; MAX_EMBED_IMAGES + 1]}),
+        );
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(String::from_utf8_lossy(&body).contains("at most"));
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let router = router(service());
+        let image = base64::engine::general_purpose::STANDARD.encode(png());
+        for augment in [false, true] {
+            let request = json_request(
+                "/v1/models/mnist:predict",
+                serde_json::json!({"image": image, "test_time_augmentation": augment}),
+            );
+            let (status, body) = send(router.clone(), request).await;
+            assert_eq!(status, StatusCode::OK);
+            let prediction: PredictResponse = serde_json::from_slice(&body).unwrap();
+            assert_eq!(prediction.views, if augment { 9 } else { 1 });
+        }
+
+        let request = http::Request::get("/metrics").body(Body::empty()).unwrap();
+        let (status, body) = send(router, request).await;
+        assert_eq!(status, StatusCode::OK);
+        let text = String::from_utf8(body).unwrap();
+        assert!(text.contains("mnist_inference_latency_seconds_count{augmentation=\"none\"} 1"));
+        assert!(
+            text.contains("mnist_inference_augmented_views_total 9"),
+            "{}",
+            text
+        );
+    }
+
+    #[tokio::test]
+    async fn test_predict_multipart() {
+        let boundary = "mnist-boundary";
+        let mut body = Vec::new();
+        body.extend(
+            format!("--{boundary}\r\nContent-Disposition: form-data; name=\"top_k\"\r\n\r\n2\r\n")
+                .as_bytes(),
+        );
+        body.extend(format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"image\"; filename=\"digit.png\"\r\nContent-Type: image/png\r\n\r\n"
+        ).as_bytes());
+        body.extend(png());
+        body.extend(format!("\r\n--{boundary}--\r\n").as_bytes());
+        let request = http::Request::post("/v1/models/mnist:predict")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(Body::from(body))
+            .unwrap();
+
+        let (status, body) = send(router(service()), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let prediction: PredictResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(prediction.top_k.len(), 2);
+        let total: f32 = prediction.probabilities.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_recognize_number() {
+        // Two strokes in dark ink on a light page
+        let page = image::GrayImage::from_fn(90, 40, |x, y| {
+            let ink = (5..35).contains(&y) && ((10..16).contains(&x) || (50..58).contains(&x));
+            image::Luma([if ink { 30 } else { 240 }])
+        });
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        page.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        let image = base64::engine::general_purpose::STANDARD.encode(bytes.into_inner());
+
+        let request = json_request(
+            "/v1/models/mnist:recognize",
+            serde_json::json!({"image": image, "min_confidence": 0.99}),
+        );
+        let (status, body) = send(router(service()), request).await;
+        assert_eq!(status, StatusCode::OK, "{}", String::from_utf8_lossy(&body));
+        let number: NumberResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(number.number.len(), 2);
+        assert_eq!(number.digits.len(), 2);
+        let first = &number.digits[0];
+        assert_eq!(number.number[..1], first.label.to_string());
+        assert_eq!((first.bounding_box.x, first.bounding_box.width), (10, 6));
+        assert_eq!((first.bounding_box.y, first.bounding_box.height), (5, 30));
+        assert_eq!(number.digits[1].bounding_box.x, 50);
+        let confidence: f32 = number.digits.iter().map(|d| d.probability).product();
+        assert!((number.confidence - confidence).abs() < 1e-6);
+        // An untrained model is never that confident
+        assert!(number.rejected && first.rejected);
+    }
+
+    #[test]
+    fn test_http_status() {
+        assert_eq!(http_status(Code::InvalidArgument), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            http_status(Code::FailedPrecondition),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(http_status(Code::NotFound), StatusCode::NOT_FOUND);
+        assert_eq!(
+            http_status(Code::Unimplemented),
+            StatusCode::NOT_IMPLEMENTED
+        );
+        assert_eq!(
+            http_status(Code::Unknown),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(http_status(Code::Cancelled).as_u16(), 499);
+    }
+}
@@ -0,0 +1,213 @@
+//! Test-time augmentation
+//!
+//! A prediction on a slightly rotated, shifted or scaled scan can flip on a single pass.
+//! With augmentation the model also sees transformed copies of the preprocessed 28x28
+//! image in the same batch, and the class probabilities are averaged over all views.
+use serde::{Deserialize, Serialize};
+
+use super::INPUT_SIZE;
+use crate::{Error, Result};
+
+/// Width and height of the images being transformed
+const SIDE: usize = 28;
+
+/// Views of an image averaged by test-time augmentation
+///
+/// Every rotation, shift and scale is a view of its own, next to the original image, so
+/// the default runs 9 views per image. Set in the `augmentation` section of the model
+/// manifest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Augmentation {
+    /// Augment requests that do not ask for it, requests can still turn it off
+    pub enabled: bool,
+    /// Rotations around the image center in degrees, clockwise
+    pub rotations: Vec<f32>,
+    /// Translations in pixels, `[dx, dy]` with y pointing down
+    pub shifts: Vec<[f32; 2]>,
+    /// Zoom factors around the image center, above 1 enlarges the digit
+    pub scales: Vec<f32>,
+}
+
+impl Default for Augmentation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rotations: vec![-10.0, 10.0],
+            shifts: vec![[-2.0, 0.0], [2.0, 0.0], [0.0, -2.0], [0.0, 2.0]],
+            scales: vec![0.9, 1.1],
+        }
+    }
+}
+
+impl Augmentation {
+    /// Check that every transform keeps the image finite
+    pub fn validate(&self) -> Result<()> {
+        let finite = self
+            .rotations
+            .iter()
+            .chain(self.shifts.iter().flatten())
+            .all(|value| value.is_finite());
+        if !finite {
+            return Err(Error::custom("Augmentation transforms must be finite"));
+        }
+        if let Some(scale) = self
+            .scales
+            .iter()
+            .find(|scale| !scale.is_finite() || **scale <= 0.0)
+        {
+            return Err(Error::custom(format!(
+                "Augmentation scales must be positive, got {}",
+                scale
+            )));
+        }
+        Ok(())
+    }
+
+    /// Number of views per image, including the original
+    pub fn view_count(&self) -> usize {
+        1 + self.rotations.len() + self.shifts.len() + self.scales.len()
+    }
+
+    /// The image followed by its rotations, shifts and scalings, in that order
+    pub fn views(&self, image: &[f32]) -> Result<Vec<Vec<f32>>> {
+        if image.len() != INPUT_SIZE {
+            return Err(Error::custom(format!(
+                "Expected {} input values, got {}",
+                INPUT_SIZE,
+                image.len()
+            )));
+        }
+        let mut views = Vec::with_capacity(self.view_count());
+        views.push(image.to_vec());
+        views.extend(
+            self.rotations
+                .iter()
+                .map(|degrees| transform(image, *degrees, [0.0, 0.0], 1.0)),
+        );
+        views.extend(
+            self.shifts
+                .iter()
+                .map(|shift| transform(image, 0.0, *shift, 1.0)),
+        );
+        views.extend(
+            self.scales
+                .iter()
+                .map(|scale| transform(image, 0.0, [0.0, 0.0], *scale)),
+        );
+        Ok(views)
+    }
+}
+
+/// Rotate, scale and then shift a 28x28 image around its center
+///
+/// Every output pixel is sampled bilinearly from the source, pixels mapped from outside
+/// the image are background (0).
+fn transform(image: &[f32], degrees: f32, shift: [f32; 2], scale: f32) -> Vec<f32> {
+    let center = (SIDE as f32 - 1.0) / 2.0;
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let mut output = vec![0.0; INPUT_SIZE];
+    for y in 0..SIDE {
+        for x in 0..SIDE {
+            // Inverse mapping from the output pixel to the source image
+            let u = (x as f32 - center - shift[0]) / scale;
+            let v = (y as f32 - center - shift[1]) / scale;
+            let source_x = cos * u + sin * v + center;
+            let source_y = -sin * u + cos * v + center;
+            output[y * SIDE + x] = sample(image, source_x, source_y);
+        }
+    }
+    output
+}
+
+/// Bilinear interpolation of the pixels around `(x, y)`
+fn sample(image: &[f32], x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let pixel = |x: f32, y: f32| {
+        if x < 0.0 || y < 0.0 || x >= SIDE as f32 || y >= SIDE as f32 {
+            0.0
+        } else {
+            image[y as usize * SIDE + x as usize]
+        }
+    };
+    pixel(x0, y0) * (1.0 - fx) * (1.0 - fy)
+        + pixel(x0 + 1.0, y0) * fx * (1.0 - fy)
+        + pixel(x0, y0 + 1.0) * (1.0 - fx) * fy
+        + pixel(x0 + 1.0, y0 + 1.0) * fx * fy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A vertical bar two pixels wide in columns 13 and 14, rows 4 to 23
+    fn bar() -> Vec<f32> {
+        let mut image = vec![0.0; INPUT_SIZE];
+        for y in 4..24 {
+            image[y * SIDE + 13] = 1.0;
+            image[y * SIDE + 14] = 1.0;
+        }
+        image
+    }
+
+    fn ink(image: &[f32]) -> f32 {
+        image.iter().sum()
+    }
+
+    #[test]
+    fn test_views() {
+        let augmentation = Augmentation::default();
+        assert_eq!(augmentation.view_count(), 9);
+        let image = bar();
+        let views = augmentation.views(&image).unwrap();
+        assert_eq!(views.len(), 9);
+        assert_eq!(views[0], image);
+        assert!(augmentation.views(&[0.0; 10]).is_err());
+
+        // Shifting moves the bar two columns to the right without losing ink
+        let shifted = &views[4];
+        assert_eq!(shifted[10 * SIDE + 15], 1.0);
+        assert_eq!(shifted[10 * SIDE + 16], 1.0);
+        assert_eq!(shifted[10 * SIDE + 13], 0.0);
+        assert!((ink(shifted) - ink(&image)).abs() < 1e-4);
+
+        // A rotated bar leans, so its ends move to other columns
+        let rotated = &views[2];
+        assert!(rotated[5 * SIDE + 13] + rotated[5 * SIDE + 14] < 1.5);
+        assert!((ink(rotated) - ink(&image)).abs() / ink(&image) < 0.05);
+
+        // Scaling shortens or lengthens the bar
+        assert!(ink(&views[7]) < ink(&image));
+        assert!(ink(&views[8]) > ink(&image));
+    }
+
+    #[test]
+    fn test_identity_transform() {
+        let image: Vec<f32> = (0..INPUT_SIZE).map(|i| (i % 7) as f32 / 7.0).collect();
+        let output = transform(&image, 0.0, [0.0, 0.0], 1.0);
+        for (a, b) in image.iter().zip(&output) {
+            assert!((a - b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Augmentation::default().validate().is_ok());
+        let augmentation = Augmentation {
+            scales: vec![0.0],
+            ..Default::default()
+        };
+        assert!(augmentation.validate().is_err());
+        let augmentation = Augmentation {
+            rotations: vec![f32::NAN],
+            ..Default::default()
+        };
+        assert!(augmentation.validate().is_err());
+
+        let augmentation: Augmentation =
+            toml::from_str("enabled = true\nrotations = [-5.0, 5.0]").unwrap();
+        assert!(augmentation.enabled);
+        assert_eq!(augmentation.view_count(), 9);
+    }
+}
@@ -0,0 +1,172 @@
+//! Temperature scaling of the model outputs
+//!
+//! A single temperature `T` divides the logits before the softmax, which leaves the
+//! predicted digit unchanged but makes the probabilities match the observed accuracy.
+//! `T` is fitted by minimizing the negative log-likelihood on a labeled validation set
+//! (Guo et al., "On Calibration of Modern Neural Networks", 2017).
+use serde::Serialize;
+
+use super::decision::top_k;
+
+/// Temperatures are searched in `[MIN_TEMPERATURE, MAX_TEMPERATURE]`
+const MIN_TEMPERATURE: f32 = 0.05;
+const MAX_TEMPERATURE: f32 = 20.0;
+
+/// Fit the temperature minimizing the negative log-likelihood of `labels`
+///
+/// The likelihood is unimodal in `log T`, so a golden-section search is enough.
+pub fn fit_temperature(logits: &[Vec<f32>], labels: &[u32]) -> f32 {
+    let ratio = (5f32.sqrt() - 1.0) / 2.0;
+    let loss = |log_t: f32| negative_log_likelihood(logits, labels, log_t.exp());
+    let (mut a, mut b) = (MIN_TEMPERATURE.ln(), MAX_TEMPERATURE.ln());
+    let mut c = b - ratio * (b - a);
+    let mut d = a + ratio * (b - a);
+    let (mut loss_c, mut loss_d) = (loss(c), loss(d));
+    while b - a > 1e-4 {
+        if loss_c < loss_d {
+            b = d;
+            d = c;
+            loss_d = loss_c;
+            c = b - ratio * (b - a);
+            loss_c = loss(c);
+        } else {
+            a = c;
+            c = d;
+            loss_c = loss_d;
+            d = a + ratio * (b - a);
+            loss_d = loss(d);
+        }
+    }
+    ((a + b) / 2.0).exp()
+}
+
+/// Mean negative log-likelihood of the labels under the temperature-scaled softmax
+pub fn negative_log_likelihood(logits: &[Vec<f32>], labels: &[u32], temperature: f32) -> f32 {
+    let total: f64 = logits
+        .iter()
+        .zip(labels)
+        .map(|(logits, &label)| -log_softmax(logits, temperature)[label as usize] as f64)
+        .sum();
+    (total / logits.len().max(1) as f64) as f32
+}
+
+/// Temperature-scaled softmax of a single sample
+pub fn softmax(logits: &[f32], temperature: f32) -> Vec<f32> {
+    log_softmax(logits, temperature)
+        .into_iter()
+        .map(f32::exp)
+        .collect()
+}
+
+fn log_softmax(logits: &[f32], temperature: f32) -> Vec<f32> {
+    let scaled: Vec<f32> = logits.iter().map(|x| x / temperature).collect();
+    let max = scaled.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let log_sum = scaled.iter().map(|x| (x - max).exp()).sum::<f32>().ln() + max;
+    scaled.into_iter().map(|x| x - log_sum).collect()
+}
+
+/// Confidence against accuracy of the predictions, grouped into equal-width bins
+#[derive(Debug, Clone, Serialize)]
+pub struct ReliabilityDiagram {
+    pub bins: Vec<ReliabilityBin>,
+    /// Expected calibration error: the sample-weighted mean of `|accuracy - confidence|`
+    pub ece: f32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReliabilityBin {
+    pub lower: f32,
+    pub upper: f32,
+    pub count: usize,
+    /// Mean probability of the predicted digit, 0 for empty bins
+    pub confidence: f32,
+    /// Fraction of correct predictions, 0 for empty bins
+    pub accuracy: f32,
+}
+
+impl ReliabilityDiagram {
+    /// Bin the predictions of the given class probabilities by their confidence
+    pub fn new(probabilities: &[Vec<f32>], labels: &[u32], num_bins: usize) -> Self {
+        let num_bins = num_bins.max(1);
+        let mut confidence = vec![0f64; num_bins];
+        let mut correct = vec![0usize; num_bins];
+        let mut count = vec![0usize; num_bins];
+        for (probabilities, &label) in probabilities.iter().zip(labels) {
+            let Some(&(digit, p)) = top_k(probabilities, 1).first() else {
+                continue;
+            };
+            let bin = ((p * num_bins as f32) as usize).min(num_bins - 1);
+            confidence[bin] += p as f64;
+            correct[bin] += (digit == label) as usize;
+            count[bin] += 1;
+        }
+
+        let total = count.iter().sum::<usize>().max(1) as f32;
+        let bins: Vec<ReliabilityBin> = (0..num_bins)
+            .map(|bin| {
+                let n = count[bin].max(1) as f32;
+                ReliabilityBin {
+                    lower: bin as f32 / num_bins as f32,
+                    upper: (bin + 1) as f32 / num_bins as f32,
+                    count: count[bin],
+                    confidence: (confidence[bin] as f32) / n,
+                    accuracy: correct[bin] as f32 / n,
+                }
+            })
+            .collect();
+        let ece = bins
+            .iter()
+            .map(|bin| bin.count as f32 / total * (bin.accuracy - bin.confidence).abs())
+            .sum();
+        Self { bins, ece }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Logits that predict the right digit 80% of the time, but with ~99% confidence
+    fn overconfident() -> (Vec<Vec<f32>>, Vec<u32>) {
+        let mut logits = Vec::new();
+        let mut labels = Vec::new();
+        for i in 0..100u32 {
+            let mut sample = vec![0.0; 10];
+            sample[(i % 10) as usize] = 7.0;
+            logits.push(sample);
+            labels.push(if i % 5 == 0 { (i + 1) % 10 } else { i % 10 });
+        }
+        (logits, labels)
+    }
+
+    #[test]
+    fn test_fit_temperature() {
+        let (logits, labels) = overconfident();
+        let temperature = fit_temperature(&logits, &labels);
+        assert!(temperature > 1.0, "{}", temperature);
+        assert!(
+            negative_log_likelihood(&logits, &labels, temperature)
+                < negative_log_likelihood(&logits, &labels, 1.0)
+        );
+
+        // The calibrated confidence matches the 80% accuracy
+        let confidence = softmax(&logits[0], temperature)[0];
+        assert!((confidence - 0.8).abs() < 0.01, "{}", confidence);
+    }
+
+    #[test]
+    fn test_reliability_diagram() {
+        let (logits, labels) = overconfident();
+        let probabilities: Vec<Vec<f32>> = logits.iter().map(|l| softmax(l, 1.0)).collect();
+        let diagram = ReliabilityDiagram::new(&probabilities, &labels, 10);
+        assert_eq!(diagram.bins.len(), 10);
+        assert_eq!(diagram.bins[9].count, 100);
+        assert!((diagram.bins[9].accuracy - 0.8).abs() < 1e-6);
+        assert!((diagram.ece - (diagram.bins[9].confidence - 0.8)).abs() < 1e-6);
+
+        let temperature = fit_temperature(&logits, &labels);
+        let probabilities: Vec<Vec<f32>> = logits.iter().map(|l| softmax(l, temperature)).collect();
+        let calibrated = ReliabilityDiagram::new(&probabilities, &labels, 10);
+        assert!(calibrated.ece < 0.01, "{}", calibrated.ece);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{Error, Result};
+
+/// Thresholds below which a prediction is rejected as too uncertain
+///
+/// A threshold of 0 disables the corresponding check.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RejectionPolicy {
+    /// Minimum probability of the predicted class
+    pub min_confidence: f32,
+    /// Minimum difference between the two most probable classes
+    pub min_margin: f32,
+}
+
+impl RejectionPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_min_confidence(mut self, min_confidence: f32) -> Result<Self> {
+        self.min_confidence = probability("min_confidence", min_confidence)?;
+        Ok(self)
+    }
+
+    pub fn with_min_margin(mut self, min_margin: f32) -> Result<Self> {
+        self.min_margin = probability("min_margin", min_margin)?;
+        Ok(self)
+    }
+
+    /// Check the class probabilities of a prediction, `None` if it is accepted
+    pub fn evaluate(&self, probabilities: &[f32]) -> Option<Rejection> {
+        let ranked = top_k(probabilities, 2);
+        let confidence = ranked.first().map_or(0.0, |(_, p)| *p);
+        if confidence < self.min_confidence {
+            return Some(Rejection::LowConfidence {
+                confidence,
+                min_confidence: self.min_confidence,
+            });
+        }
+        let margin = confidence - ranked.get(1).map_or(0.0, |(_, p)| *p);
+        if margin < self.min_margin {
+            return Some(Rejection::SmallMargin {
+                margin,
+                min_margin: self.min_margin,
+            });
+        }
+        None
+    }
+}
+
+fn probability(name: &str, value: f32) -> Result<f32> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(Error::invalid_argument(format!(
+            "{} must be between 0 and 1, got {}",
+            name, value
+        )));
+    }
+    Ok(value)
+}
+
+/// Reason a prediction was rejected by a [`RejectionPolicy`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum Rejection {
+    LowConfidence {
+        confidence: f32,
+        min_confidence: f32,
+    },
+    SmallMargin {
+        margin: f32,
+        min_margin: f32,
+    },
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::LowConfidence {
+                confidence,
+                min_confidence,
+            } => write!(
+                f,
+                "confidence {:.3} is below the minimum of {:.3}",
+                confidence, min_confidence
+            ),
+            Rejection::SmallMargin { margin, min_margin } => write!(
+                f,
+                "margin {:.3} between the two most likely digits is below the minimum of {:.3}",
+                margin, min_margin
+            ),
+        }
+    }
+}
+
+/// Indices and values of the `k` largest probabilities, most probable first
+pub fn top_k(probabilities: &[f32], k: usize) -> Vec<(u32, f32)> {
+    let mut ranked: Vec<(u32, f32)> = probabilities
+        .iter()
+        .enumerate()
+        .map(|(class, p)| (class as u32, *p))
+        .collect();
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.truncate(k);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_top_k() {
+        let ranked = top_k(&[0.1, 0.6, 0.05, 0.25], 3);
+        assert_eq!(ranked, vec![(1, 0.6), (3, 0.25), (0, 0.1)]);
+        assert_eq!(top_k(&[0.5, 0.5], 5).len(), 2);
+    }
+
+    #[test]
+    fn test_rejection() {
+        let policy = RejectionPolicy::new()
+            .with_min_confidence(0.5)
+            .unwrap()
+            .with_min_margin(0.2)
+            .unwrap();
+
+        assert_eq!(policy.evaluate(&[0.1, 0.8, 0.1]), None);
+        assert!(matches!(
+            policy.evaluate(&[0.3, 0.4, 0.3]),
+            Some(Rejection::LowConfidence { .. })
+        ));
+        let rejection = policy.evaluate(&[0.4, 0.55, 0.05]).unwrap();
+        assert!(matches!(rejection, Rejection::SmallMargin { .. }));
+        assert!(rejection.to_string().contains("margin 0.150"));
+
+        assert_eq!(RejectionPolicy::default().evaluate(&[0.5, 0.5]), None);
+        assert!(RejectionPolicy::new().with_min_confidence(1.5).is_err());
+    }
+}
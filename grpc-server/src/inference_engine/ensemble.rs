@@ -0,0 +1,396 @@
+//! Several models served as one
+//!
+//! An ensemble is described by a JSON or TOML file passed as the model weights together
+//! with `--model-architecture ensemble`:
+//!
+//! ```toml
+//! name = "mnist-ensemble"
+//! strategy = "weighted_mean"
+//!
+//! [[members]]
+//! name = "convnet-a"
+//! weights = "convnet_a.safetensors"
+//! architecture = "conv"
+//! weight = 2.0
+//!
+//! [[members]]
+//! weights = "mlp.safetensors"
+//! sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! ```
+//!
+//! Member paths are relative to the ensemble file. Every member is a complete
+//! [`InferenceEngine`] with its own manifest, normalization and calibrated temperature;
+//! the ensemble combines their probabilities.
+use std::path::{Path, PathBuf};
+
+use candle_core::{DType, Device, Tensor};
+use serde::{Deserialize, Serialize};
+
+use super::decision::top_k;
+use super::integrity::IntegrityPolicy;
+use super::postprocess::PostProcessing;
+use super::weights_provider::{LocalFileProvider, WeightsSource};
+use super::{InferenceEngine, ModelArchitecture};
+use crate::{Error, Result};
+
+/// How the member probabilities are combined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EnsembleStrategy {
+    /// Average of the member probabilities
+    #[default]
+    Mean,
+    /// Average of the member probabilities weighted by the member `weight`
+    WeightedMean,
+    /// Share of the (weighted) member votes for each digit, ties go to the higher mean
+    /// probability
+    MajorityVote,
+}
+
+/// Contents of an ensemble file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnsembleConfig {
+    /// Name the ensemble is served under
+    pub name: Option<String>,
+    #[serde(default)]
+    pub strategy: EnsembleStrategy,
+    pub members: Vec<MemberConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemberConfig {
+    /// Name reported in per-member predictions, defaults to the weights file name
+    pub name: Option<String>,
+    /// Weights of the member, relative to the ensemble file
+    pub weights: PathBuf,
+    /// Architecture of the member, read from its manifest if omitted
+    pub architecture: Option<ModelArchitecture>,
+    /// Manifest of the member, defaults to the sidecar next to its weights
+    pub manifest: Option<PathBuf>,
+    /// Expected SHA-256 digest of the member weights (hex)
+    pub sha256: Option<String>,
+    /// Weight of the member for the `weighted_mean` and `majority_vote` strategies
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+impl EnsembleConfig {
+    /// Parse an ensemble file, JSON or TOML
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let contents = std::str::from_utf8(data)
+            .map_err(|e| Error::custom(format!("Ensemble file is not UTF-8: {}", e)))?;
+        let config: Self = match serde_json::from_str(contents) {
+            Ok(config) => config,
+            Err(json_error) => toml::from_str(contents).map_err(|toml_error| {
+                Error::custom(format!(
+                    "Invalid ensemble file, neither JSON ({}) nor TOML ({})",
+                    json_error, toml_error
+                ))
+            })?,
+        };
+        if config.members.is_empty() {
+            return Err(Error::custom("Ensemble has no members"));
+        }
+        if let Some(member) = config
+            .members
+            .iter()
+            .find(|member| !member.weight.is_finite() || member.weight <= 0.0)
+        {
+            return Err(Error::custom(format!(
+                "Ensemble member {} must have a positive weight",
+                member.weights.display()
+            )));
+        }
+        Ok(config)
+    }
+
+    /// Read the ensemble file handed out by a weights provider
+    ///
+    /// Returns the config and the directory member paths are resolved against.
+    pub fn from_source(source: WeightsSource) -> Result<(Self, PathBuf)> {
+        match source.single_file() {
+            Some((data, path)) => {
+                let dir = path
+                    .and_then(Path::parent)
+                    .unwrap_or(Path::new("."))
+                    .to_path_buf();
+                Ok((Self::from_bytes(data)?, dir))
+            }
+            None => Err(Error::custom("Ensemble files cannot be sharded")),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Member {
+    name: String,
+    weight: f32,
+    engine: InferenceEngine,
+}
+
+/// Prediction of a single ensemble member
+#[derive(Debug, Clone, PartialEq)]
+pub struct MemberPrediction {
+    pub name: String,
+    pub digit: u32,
+    pub probabilities: Vec<f32>,
+}
+
+/// Members of an ensemble together with the strategy combining them
+#[derive(Debug)]
+pub struct Ensemble {
+    strategy: EnsembleStrategy,
+    members: Vec<Member>,
+}
+
+impl Ensemble {
+    /// Build every member of the ensemble on the given device
+    ///
+    /// Members are verified with the `integrity` policy of the ensemble file, see
+    /// [`IntegrityPolicy::for_reference`].
+    pub fn new(
+        config: &EnsembleConfig,
+        dir: &Path,
+        integrity: &IntegrityPolicy,
+        device: &Device,
+        dtype: DType,
+    ) -> Result<Self> {
+        let members = config
+            .members
+            .iter()
+            .map(|member| {
+                if member.architecture == Some(ModelArchitecture::Ensemble) {
+                    return Err(Error::custom("Ensembles cannot be nested"));
+                }
+                let path = dir.join(&member.weights);
+                let policy = integrity.for_reference(&path, member.sha256.as_deref())?;
+                let mut provider = LocalFileProvider::new(path.clone()).with_integrity(policy);
+                if let Some(manifest) = &member.manifest {
+                    provider = provider.with_manifest(dir.join(manifest));
+                }
+                let mut builder = InferenceEngine::builder()
+                    .device(device.clone())
+                    .dtype(dtype);
+                if let Some(arch) = member.architecture {
+                    builder = builder.model_architecture(arch);
+                }
+                let engine = builder.build(provider).map_err(|e| {
+                    Error::custom(format!(
+                        "Failed to load ensemble member {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                if engine.architecture() == ModelArchitecture::Ensemble {
+                    return Err(Error::custom("Ensembles cannot be nested"));
+                }
+
+                let name = member.name.clone().unwrap_or_else(|| {
+                    member
+                        .weights
+                        .file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default()
+                });
+                Ok(Member {
+                    name,
+                    weight: member.weight,
+                    engine,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            strategy: config.strategy,
+            members,
+        })
+    }
+
+    pub fn strategy(&self) -> EnsembleStrategy {
+        self.strategy
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    /// Labels of the first member, used for the ensemble predictions
+    pub fn labels(&self) -> Vec<String> {
+        self.members
+            .first()
+            .map(|member| member.engine.manifest().labels.clone())
+            .unwrap_or_default()
+    }
+
+    /// Run every member and combine their probabilities
+    ///
+    /// Returns the log of the combined probabilities as `[N, 10]` logits, which post-process
+    /// like the logits of a single model, and the predictions of every member per image.
+    pub fn forward(&self, inputs: &[Vec<f32>]) -> Result<(Tensor, Vec<Vec<MemberPrediction>>)> {
+        let post = PostProcessing::default();
+        let mut member_probabilities = Vec::with_capacity(self.members.len());
+        for member in &self.members {
+            let logits = member.engine.forward(inputs.to_vec())?;
+            let probabilities: Vec<Vec<f32>> = member
+                .engine
+                .post_processing(&post)
+                .probabilities(&logits)?
+                .to_vec2()?;
+            member_probabilities.push(probabilities);
+        }
+
+        let combined: Vec<Vec<f32>> = (0..inputs.len())
+            .map(|i| {
+                let samples: Vec<&[f32]> = member_probabilities
+                    .iter()
+                    .map(|probabilities| probabilities[i].as_slice())
+                    .collect();
+                self.combine(&samples)
+            })
+            .collect();
+        let members = (0..inputs.len())
+            .map(|i| {
+                self.members
+                    .iter()
+                    .zip(&member_probabilities)
+                    .map(|(member, probabilities)| MemberPrediction {
+                        name: member.name.clone(),
+                        digit: argmax(&probabilities[i]),
+                        probabilities: probabilities[i].clone(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let logits = Tensor::new(combined, &Device::Cpu)?.log()?;
+        Ok((logits, members))
+    }
+
+    /// Combine the probabilities the members assigned to one image
+    fn combine(&self, samples: &[&[f32]]) -> Vec<f32> {
+        let classes = samples.first().map_or(0, |sample| sample.len());
+        let weights: Vec<f32> = match self.strategy {
+            EnsembleStrategy::Mean => vec![1.0; samples.len()],
+            EnsembleStrategy::WeightedMean | EnsembleStrategy::MajorityVote => {
+                self.members.iter().map(|member| member.weight).collect()
+            }
+        };
+        let total: f32 = weights.iter().sum();
+
+        let mut mean = vec![0.0; classes];
+        for (sample, weight) in samples.iter().zip(&weights) {
+            for (mean, p) in mean.iter_mut().zip(sample.iter()) {
+                *mean += weight * p / total;
+            }
+        }
+        if self.strategy != EnsembleStrategy::MajorityVote {
+            return mean;
+        }
+
+        let mut votes = vec![0.0; classes];
+        for (sample, weight) in samples.iter().zip(&weights) {
+            votes[argmax(sample) as usize] += weight / total;
+        }
+        // Break ties between equally voted digits by their mean probability, the nudge is
+        // far below any vote share so it never changes a decided vote
+        let nudge = 1e-4 / classes as f32;
+        let votes: Vec<f32> = votes
+            .iter()
+            .zip(&mean)
+            .map(|(vote, mean)| vote + nudge * mean)
+            .collect();
+        let sum: f32 = votes.iter().sum();
+        votes.into_iter().map(|vote| vote / sum).collect()
+    }
+}
+
+fn argmax(probabilities: &[f32]) -> u32 {
+    top_k(probabilities, 1)
+        .first()
+        .map_or(0, |(class, _)| *class)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::random_weights;
+
+    fn ensemble(strategy: EnsembleStrategy, weights: &[f32]) -> Ensemble {
+        // The members are only used for their weights when combining probabilities
+        let (dir, _) = random_weights(ModelArchitecture::MLP);
+
+        let config = EnsembleConfig {
+            name: None,
+            strategy,
+            members: weights
+                .iter()
+                .map(|&weight| MemberConfig {
+                    name: None,
+                    weights: PathBuf::from("model.safetensors"),
+                    architecture: Some(ModelArchitecture::MLP),
+                    manifest: None,
+                    sha256: None,
+                    weight,
+                })
+                .collect(),
+        };
+        Ensemble::new(
+            &config,
+            &dir,
+            &IntegrityPolicy::new(),
+            &Device::Cpu,
+            DType::F32,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_combine() {
+        let samples: [&[f32]; 3] = [&[0.6, 0.4], &[0.45, 0.55], &[0.4, 0.6]];
+
+        let mean = ensemble(EnsembleStrategy::Mean, &[1.0, 1.0, 4.0]).combine(&samples);
+        assert!((mean[0] - 1.45 / 3.0).abs() < 1e-6);
+
+        let weighted = ensemble(EnsembleStrategy::WeightedMean, &[1.0, 1.0, 2.0]).combine(&samples);
+        assert!((weighted[0] - (0.6 + 0.45 + 0.8) / 4.0).abs() < 1e-6);
+
+        let votes = ensemble(EnsembleStrategy::MajorityVote, &[1.0, 1.0, 1.0]).combine(&samples);
+        assert!((votes[1] - 2.0 / 3.0).abs() < 1e-3);
+        let votes = ensemble(EnsembleStrategy::MajorityVote, &[3.0, 1.0, 1.0]).combine(&samples);
+        assert!((votes[0] - 0.6).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let config = EnsembleConfig::from_bytes(
+            br#"
+            strategy = "majority_vote"
+
+            [[members]]
+            weights = "a.safetensors"
+            architecture = "conv"
+
+            [[members]]
+            weights = "b.safetensors"
+            weight = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.strategy, EnsembleStrategy::MajorityVote);
+        assert_eq!(config.members[0].weight, 1.0);
+        assert_eq!(config.members[1].weight, 0.5);
+
+        assert!(EnsembleConfig::from_bytes(br#"{"members": []}"#).is_err());
+        assert!(
+            EnsembleConfig::from_bytes(br#"{"members": [{"weights": "a", "weight": 0}]}"#).is_err()
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! Attribution maps showing which pixels a prediction relies on
+//!
+//! Occlusion sensitivity works for every model, since it only runs predictions: a patch of
+//! the image is hidden at a time, and the drop in probability of the class is spread over
+//! the hidden pixels. Gradient saliency backpropagates the class logit to the input
+//! pixels through candle's autograd, so it needs a model made of candle operations.
+use candle_core::{DType, Tensor, Var};
+use image::{ImageFormat, Rgb, RgbImage};
+
+use super::{INPUT_SIZE, InferenceEngine, MnistModel, PredictOptions};
+use crate::{Error, Result};
+
+/// Width and height of the explained images
+const SIDE: usize = 28;
+
+/// Side of the square hidden by occlusion, in pixels
+const OCCLUSION_PATCH: usize = 4;
+
+/// Step between two occluded patches, every pixel is hidden by several patches
+const OCCLUSION_STRIDE: usize = 2;
+
+/// Scale of the rendered heatmap, a 28x28 map becomes 280x280 pixels
+const HEATMAP_SCALE: u32 = 10;
+
+/// How attributions are computed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExplainMethod {
+    /// Drop in probability of the class when the pixel is hidden, negative if hiding it
+    /// makes the class more likely
+    #[default]
+    Occlusion,
+    /// Magnitude of the gradient of the class logit with respect to the pixel
+    Gradient,
+}
+
+/// Attributions of a prediction, one per pixel of the 28x28 input
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    /// Class the attributions are computed for
+    pub class: u32,
+    /// Digit the model predicts for the image
+    pub predicted: u32,
+    /// Probability of `class`
+    pub probability: f32,
+    /// Row-major attribution of every pixel
+    pub attributions: Vec<f32>,
+}
+
+impl InferenceEngine {
+    /// Explain the prediction of `class` for an image, the predicted digit if `None`
+    ///
+    /// Test-time augmentation is not applied, so the attributions refer to the image as
+    /// sent.
+    pub fn explain(
+        &self,
+        input: Vec<f32>,
+        class: Option<u32>,
+        method: ExplainMethod,
+    ) -> Result<Explanation> {
+        if input.len() != INPUT_SIZE {
+            return Err(Error::custom(format!(
+                "Expected {} input values, got {}",
+                INPUT_SIZE,
+                input.len()
+            )));
+        }
+        let options = PredictOptions {
+            test_time_augmentation: Some(false),
+            ..Default::default()
+        };
+        let prediction = self.predict_with(input.clone(), &options)?;
+        let class = class.unwrap_or(prediction.digit);
+        let Some(&probability) = prediction.probabilities.get(class as usize) else {
+            return Err(Error::invalid_argument(format!(
+                "Class {} is not an output of the model, expected 0 to {}",
+                class,
+                prediction.probabilities.len() - 1
+            )));
+        };
+
+        let attributions = match method {
+            ExplainMethod::Occlusion => self.occlusion(&input, class, &options)?,
+            ExplainMethod::Gradient => self.gradient(input, class)?,
+        };
+        Ok(Explanation {
+            class,
+            predicted: prediction.digit,
+            probability,
+            attributions,
+        })
+    }
+
+    /// Average drop in probability of `class` over the patches hiding each pixel
+    ///
+    /// All occluded copies are predicted in a single batch, after the image itself.
+    fn occlusion(&self, input: &[f32], class: u32, options: &PredictOptions) -> Result<Vec<f32>> {
+        let offsets: Vec<usize> = (0..=SIDE - OCCLUSION_PATCH)
+            .step_by(OCCLUSION_STRIDE)
+            .collect();
+        let patches: Vec<(usize, usize)> = offsets
+            .iter()
+            .flat_map(|&y| offsets.iter().map(move |&x| (x, y)))
+            .collect();
+        let occluded = patches.iter().map(|&(x, y)| {
+            let mut image = input.to_vec();
+            for row in y..y + OCCLUSION_PATCH {
+                image[row * SIDE + x..row * SIDE + x + OCCLUSION_PATCH].fill(0.0);
+            }
+            image
+        });
+        let inputs = std::iter::once(input.to_vec()).chain(occluded).collect();
+        let predictions = self.predict_batch(inputs, options)?;
+        let probability = predictions[0].probabilities[class as usize];
+
+        let mut total = vec![0.0; INPUT_SIZE];
+        let mut count = vec![0u32; INPUT_SIZE];
+        for (&(x, y), prediction) in patches.iter().zip(&predictions[1..]) {
+            let drop = probability - prediction.probabilities[class as usize];
+            for row in y..y + OCCLUSION_PATCH {
+                for pixel in row * SIDE + x..row * SIDE + x + OCCLUSION_PATCH {
+                    total[pixel] += drop;
+                    count[pixel] += 1;
+                }
+            }
+        }
+        Ok(total
+            .into_iter()
+            .zip(count)
+            .map(|(total, count)| total / count.max(1) as f32)
+            .collect())
+    }
+
+    /// Absolute gradient of the `class` logit with respect to the [0, 1] scaled pixels
+    fn gradient(&self, input: Vec<f32>, class: u32) -> Result<Vec<f32>> {
+        if !matches!(
+            self.model,
+            MnistModel::MLP(_) | MnistModel::Conv(_) | MnistModel::Sequential(_)
+        ) {
+            return Err(Error::invalid_argument(format!(
+                "Gradient saliency needs an unquantized mlp, conv or sequential model, \
+                 use occlusion for {:?} models",
+                self.architecture
+            )));
+        }
+        let pixels = Var::from_tensor(&Tensor::from_vec(input, INPUT_SIZE, &self.device)?)?;
+        let normalization = self.manifest.normalization;
+        let x = pixels.as_tensor().affine(
+            1.0 / normalization.std as f64,
+            -normalization.mean as f64 / normalization.std as f64,
+        )?;
+        let mut shape = vec![1];
+        shape.extend(&self.input_shape);
+        let x = x.reshape(shape)?.to_dtype(self.dtype)?;
+        let logits = self
+            .model
+            .forward(&x)?
+            .reshape((1, ()))?
+            .to_dtype(DType::F32)?;
+        let logit = logits.get(0)?.get(class as usize)?;
+        let gradients = logit.backward()?;
+        let gradient = gradients
+            .get(pixels.as_tensor())
+            .ok_or_else(|| Error::custom("The model does not propagate gradients to its input"))?;
+        Ok(gradient.abs()?.to_vec1()?)
+    }
+}
+
+/// Render attributions as a PNG heatmap over the 28x28 input image
+///
+/// The image is drawn in gray, pixels supporting the class in red and pixels speaking
+/// against it in blue, with an opacity proportional to the largest attribution.
+pub fn render_heatmap(input: &[f32], attributions: &[f32]) -> Result<Vec<u8>> {
+    if input.len() != INPUT_SIZE || attributions.len() != INPUT_SIZE {
+        return Err(Error::custom(format!(
+            "Expected {} pixels and attributions, got {} and {}",
+            INPUT_SIZE,
+            input.len(),
+            attributions.len()
+        )));
+    }
+    let max = attributions
+        .iter()
+        .fold(0.0f32, |max, attribution| max.max(attribution.abs()));
+    let overlay = RgbImage::from_fn(SIDE as u32, SIDE as u32, |x, y| {
+        let pixel = y as usize * SIDE + x as usize;
+        let gray = input[pixel].clamp(0.0, 1.0) * 255.0;
+        let attribution = attributions[pixel];
+        let alpha = if max > 0.0 {
+            attribution.abs() / max
+        } else {
+            0.0
+        };
+        let color = if attribution >= 0.0 {
+            [255.0, 0.0, 0.0]
+        } else {
+            [0.0, 64.0, 255.0]
+        };
+        Rgb(color.map(|channel| (gray * (1.0 - alpha) + channel * alpha).round() as u8))
+    });
+    let side = SIDE as u32 * HEATMAP_SCALE;
+    let heatmap =
+        image::imageops::resize(&overlay, side, side, image::imageops::FilterType::Nearest);
+    let mut png = std::io::Cursor::new(Vec::new());
+    heatmap
+        .write_to(&mut png, ImageFormat::Png)
+        .map_err(|e| Error::custom(format!("Failed to encode the heatmap: {}", e)))?;
+    Ok(png.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference_engine::ModelArchitecture;
+    use crate::inference_engine::weights_provider::LocalFileProvider;
+    use crate::test_util::random_weights;
+
+    fn engine(arch: ModelArchitecture) -> InferenceEngine {
+        let (_dir, path) = random_weights(arch);
+        InferenceEngine::builder()
+            .model_architecture(arch)
+            .build(LocalFileProvider::new(path))
+            .unwrap()
+    }
+
+    /// A bright square in the top left corner
+    fn corner() -> Vec<f32> {
+        let mut image = vec![0.0; INPUT_SIZE];
+        for y in 2..8 {
+            image[y * SIDE + 2..y * SIDE + 8].fill(1.0);
+        }
+        image
+    }
+
+    #[test]
+    fn test_occlusion() {
+        let engine = engine(ModelArchitecture::Conv);
+        let explanation = engine
+            .explain(corner(), None, ExplainMethod::Occlusion)
+            .unwrap();
+        assert_eq!(explanation.class, explanation.predicted);
+        assert_eq!(explanation.attributions.len(), INPUT_SIZE);
+        // Hiding background pixels changes nothing
+        assert!(explanation.attributions[27 * SIDE + 27].abs() < 1e-6);
+        assert!(explanation.attributions[4 * SIDE + 4] != 0.0);
+
+        let explanation = engine
+            .explain(corner(), Some(3), ExplainMethod::Occlusion)
+            .unwrap();
+        assert_eq!(explanation.class, 3);
+        assert!(
+            engine
+                .explain(corner(), Some(10), ExplainMethod::Occlusion)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_gradient() {
+        for arch in [ModelArchitecture::MLP, ModelArchitecture::Conv] {
+            let engine = engine(arch);
+            let explanation = engine
+                .explain(corner(), Some(7), ExplainMethod::Gradient)
+                .unwrap();
+            assert_eq!(explanation.attributions.len(), INPUT_SIZE);
+            assert!(explanation.attributions.iter().all(|a| *a >= 0.0));
+            assert!(explanation.attributions.iter().any(|a| *a > 0.0));
+        }
+    }
+
+    #[test]
+    fn test_render_heatmap() {
+        let image = corner();
+        let mut attributions = vec![0.0; INPUT_SIZE];
+        attributions[4 * SIDE + 4] = 0.5;
+        attributions[20 * SIDE + 20] = -0.25;
+        let png = render_heatmap(&image, &attributions).unwrap();
+        let heatmap = image::load_from_memory(&png).unwrap().to_rgb8();
+        assert_eq!(heatmap.dimensions(), (280, 280));
+        assert_eq!(heatmap.get_pixel(45, 45), &Rgb([255, 0, 0]));
+        assert_eq!(heatmap.get_pixel(25, 25), &Rgb([255, 255, 255]));
+        assert_eq!(heatmap.get_pixel(205, 205), &Rgb([0, 32, 128]));
+        assert_eq!(heatmap.get_pixel(275, 5), &Rgb([0, 0, 0]));
+        assert!(render_heatmap(&image, &attributions[1..]).is_err());
+    }
+}
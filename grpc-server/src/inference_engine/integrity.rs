@@ -0,0 +1,342 @@
+use std::path::{Path, PathBuf};
+
+use ed25519_dalek::pkcs8::DecodePublicKey;
+use ed25519_dalek::{Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Result};
+
+/// Integrity requirements a weights artifact must satisfy before it is served
+///
+/// Every configured check has to pass; an empty policy accepts any file.
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityPolicy {
+    /// Expected SHA-256 digest of the artifact, hex encoded
+    pub sha256: Option<String>,
+    /// Read the expected digest from a `<weights>.sha256` sidecar file
+    pub sha256_sidecar: bool,
+    /// Ed25519 key the artifact must be signed with
+    pub public_key: Option<VerifyingKey>,
+    /// Detached signature of the artifact, defaults to `<weights>.sig`
+    pub signature_path: Option<PathBuf>,
+}
+
+impl IntegrityPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sha256<S: Into<String>>(mut self, digest: S) -> Self {
+        self.sha256 = Some(digest.into());
+        self
+    }
+
+    pub fn with_sha256_sidecar(mut self, enabled: bool) -> Self {
+        self.sha256_sidecar = enabled;
+        self
+    }
+
+    pub fn with_public_key(mut self, key: VerifyingKey) -> Self {
+        self.public_key = Some(key);
+        self
+    }
+
+    pub fn with_signature_path(mut self, path: PathBuf) -> Self {
+        self.signature_path = Some(path);
+        self
+    }
+
+    /// Returns true if no checks are configured
+    pub fn is_empty(&self) -> bool {
+        self.sha256.is_none() && !self.sha256_sidecar && self.public_key.is_none()
+    }
+
+    /// Verify `data`, the contents of the artifact at `path`, against the policy
+    pub fn verify(&self, path: &Path, data: &[u8]) -> Result<()> {
+        self.verify_with(
+            path,
+            data,
+            self.sha256.as_deref(),
+            self.signature_path.as_deref(),
+        )
+    }
+
+    /// Verify one shard of a sharded checkpoint
+    ///
+    /// The explicit digest and signature path describe the artifact the operator pointed
+    /// at, so shards are checked against their own `.sha256` and `.sig` sidecars only. A
+    /// policy that only pins the digest of the index cannot vouch for them, and refuses them.
+    pub fn verify_shard(&self, path: &Path, data: &[u8]) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if !self.sha256_sidecar && self.public_key.is_none() {
+            return Err(Error::integrity(format!(
+                "Shard {} is not covered by the integrity checks, verify the shards with \
+                 sidecar digests or signatures",
+                path.display()
+            )));
+        }
+        self.verify_with(path, data, None, None)
+    }
+
+    /// Policy of a file referenced by the verified artifact, e.g. an ensemble member
+    ///
+    /// Like shards, the file is checked against its own `.sha256` and `.sig` sidecars, and
+    /// against `sha256` if the referencing artifact pins its digest. Errors if this policy
+    /// has checks but none of them can apply to the referenced file.
+    pub fn for_reference(&self, path: &Path, sha256: Option<&str>) -> Result<IntegrityPolicy> {
+        let policy = IntegrityPolicy {
+            sha256: sha256.map(str::to_string),
+            sha256_sidecar: self.sha256_sidecar,
+            public_key: self.public_key,
+            signature_path: None,
+        };
+        if !self.is_empty() && policy.is_empty() {
+            return Err(Error::integrity(format!(
+                "{} needs a pinned `sha256` to pass the integrity checks",
+                path.display()
+            )));
+        }
+        Ok(policy)
+    }
+
+    /// Verify a manifest file describing the artifact
+    ///
+    /// The manifest decides what is served as much as the weights do, so it is checked
+    /// against its own `.sha256` and `.sig` sidecars. A policy that only pins the digest of
+    /// the weights cannot vouch for a separate file, and refuses it.
+    pub fn verify_manifest(&self, path: &Path, data: &[u8]) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if !self.sha256_sidecar && self.public_key.is_none() {
+            return Err(Error::integrity(format!(
+                "Manifest {} is not covered by the integrity checks, verify it with a sidecar \
+                 digest or signature, or embed it in the weights",
+                path.display()
+            )));
+        }
+        self.verify_with(path, data, None, None)
+    }
+
+    fn verify_with(
+        &self,
+        path: &Path,
+        data: &[u8],
+        expected_sha256: Option<&str>,
+        signature_path: Option<&Path>,
+    ) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        let digest = hex::encode(Sha256::digest(data));
+
+        if let Some(expected) = expected_sha256 {
+            check_digest(path, &digest, expected)?;
+        }
+
+        if self.sha256_sidecar {
+            let sidecar = sidecar_path(path, "sha256");
+            let contents = std::fs::read_to_string(&sidecar).map_err(|e| {
+                Error::integrity(format!(
+                    "Failed to read digest file {}: {}",
+                    sidecar.display(),
+                    e
+                ))
+            })?;
+            // Accepts both a bare digest and the `sha256sum` output format
+            let expected = contents.split_whitespace().next().ok_or_else(|| {
+                Error::integrity(format!("Digest file {} is empty", sidecar.display()))
+            })?;
+            check_digest(path, &digest, expected)?;
+        }
+
+        if let Some(key) = &self.public_key {
+            let signature_path = signature_path
+                .map(Path::to_path_buf)
+                .unwrap_or_else(|| sidecar_path(path, "sig"));
+            let signature = read_signature(&signature_path)?;
+            key.verify_strict(data, &signature).map_err(|_| {
+                Error::integrity(format!(
+                    "Signature {} does not match {}",
+                    signature_path.display(),
+                    path.display()
+                ))
+            })?;
+        }
+
+        tracing::info!(path = %path.display(), sha256 = %digest, "Verified model artifact");
+        Ok(())
+    }
+}
+
+/// Load an Ed25519 public key from a PEM, hex or raw 32 byte file
+pub fn load_public_key(path: &Path) -> Result<VerifyingKey> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        Error::integrity(format!(
+            "Failed to read public key {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    if let Ok(text) = std::str::from_utf8(&bytes) {
+        let text = text.trim();
+        if text.starts_with("-----BEGIN") {
+            return VerifyingKey::from_public_key_pem(text).map_err(|e| {
+                Error::integrity(format!("Invalid PEM public key {}: {}", path.display(), e))
+            });
+        }
+        if let Ok(decoded) = hex::decode(text) {
+            return verifying_key_from_bytes(path, &decoded);
+        }
+    }
+    verifying_key_from_bytes(path, &bytes)
+}
+
+fn verifying_key_from_bytes(path: &Path, bytes: &[u8]) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| {
+        Error::integrity(format!(
+            "Public key {} must be 32 bytes, got {}",
+            path.display(),
+            bytes.len()
+        ))
+    })?;
+    VerifyingKey::from_bytes(&bytes)
+        .map_err(|e| Error::integrity(format!("Invalid public key {}: {}", path.display(), e)))
+}
+
+/// Read a detached signature stored either as raw 64 bytes or as hex
+fn read_signature(path: &Path) -> Result<Signature> {
+    let bytes = std::fs::read(path).map_err(|e| {
+        Error::integrity(format!(
+            "Failed to read signature {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+    let bytes = match std::str::from_utf8(&bytes).ok().map(str::trim) {
+        Some(text) if text.len() == 128 => hex::decode(text).unwrap_or(bytes),
+        _ => bytes,
+    };
+    Signature::from_slice(&bytes)
+        .map_err(|e| Error::integrity(format!("Invalid signature {}: {}", path.display(), e)))
+}
+
+fn check_digest(path: &Path, actual: &str, expected: &str) -> Result<()> {
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        return Err(Error::integrity(format!(
+            "SHA-256 mismatch for {}: expected {}, got {}",
+            path.display(),
+            expected.trim(),
+            actual
+        )));
+    }
+    Ok(())
+}
+
+/// Path of a file next to `path` with `extension` appended, e.g. `model.safetensors.sig`
+fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let mut file = path.as_os_str().to_owned();
+    file.push(".");
+    file.push(extension);
+    PathBuf::from(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    fn temp_artifact(name: &str, data: &[u8]) -> (TempDir, PathBuf) {
+        let dir = TempDir::new("integrity");
+        let path = dir.join(name);
+        std::fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_empty_policy_accepts_anything() {
+        let policy = IntegrityPolicy::new();
+        assert!(policy.verify(Path::new("missing"), b"weights").is_ok());
+    }
+
+    #[test]
+    fn test_sha256_digest() {
+        let (_dir, path) = temp_artifact("model.safetensors", b"weights");
+        let digest = hex::encode(Sha256::digest(b"weights"));
+
+        let policy = IntegrityPolicy::new().with_sha256(digest.to_uppercase());
+        assert!(policy.verify(&path, b"weights").is_ok());
+
+        let policy = IntegrityPolicy::new().with_sha256(digest);
+        assert!(policy.verify(&path, b"tampered").is_err());
+    }
+
+    #[test]
+    fn test_reference_policy() {
+        let path = Path::new("member.safetensors");
+        let policy = IntegrityPolicy::new().with_sha256("ab".repeat(32));
+        assert!(policy.for_reference(path, None).is_err());
+        let reference = policy.for_reference(path, Some("cd")).unwrap();
+        assert_eq!(reference.sha256.as_deref(), Some("cd"));
+
+        let policy = IntegrityPolicy::new()
+            .with_sha256_sidecar(true)
+            .with_signature_path(PathBuf::from("ensemble.sig"));
+        let reference = policy.for_reference(path, None).unwrap();
+        assert!(reference.sha256_sidecar);
+        assert_eq!(reference.signature_path, None);
+        assert!(
+            IntegrityPolicy::new()
+                .for_reference(path, None)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_sha256_sidecar() {
+        let (_dir, path) = temp_artifact("model.safetensors", b"weights");
+        let policy = IntegrityPolicy::new().with_sha256_sidecar(true);
+
+        // Missing sidecar is a failure, not a skipped check
+        assert!(policy.verify(&path, b"weights").is_err());
+
+        let digest = hex::encode(Sha256::digest(b"weights"));
+        std::fs::write(
+            sidecar_path(&path, "sha256"),
+            format!("{}  model.safetensors\n", digest),
+        )
+        .unwrap();
+        assert!(policy.verify(&path, b"weights").is_ok());
+        assert!(policy.verify(&path, b"tampered").is_err());
+    }
+
+    #[test]
+    fn test_ed25519_signature() {
+        let (_dir, path) = temp_artifact("model.safetensors", b"weights");
+        let signing_key = SigningKey::from_bytes(&[7u8; 32]);
+        let signature = signing_key.sign(b"weights");
+        std::fs::write(sidecar_path(&path, "sig"), signature.to_bytes()).unwrap();
+
+        let key_path = path.with_file_name("key.pub");
+        std::fs::write(
+            &key_path,
+            hex::encode(signing_key.verifying_key().as_bytes()),
+        )
+        .unwrap();
+        let public_key = load_public_key(&key_path).unwrap();
+
+        let policy = IntegrityPolicy::new().with_public_key(public_key);
+        assert!(policy.verify(&path, b"weights").is_ok());
+        assert!(policy.verify(&path, b"tampered").is_err());
+
+        let other_key = SigningKey::from_bytes(&[8u8; 32]).verifying_key();
+        let policy = IntegrityPolicy::new().with_public_key(other_key);
+        assert!(policy.verify(&path, b"weights").is_err());
+    }
+}
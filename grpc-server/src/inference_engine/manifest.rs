@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use mnist::LayerSpec;
+use serde::{Deserialize, Serialize};
+
+use super::ModelArchitecture;
+use super::augmentation::Augmentation;
+use super::ood::OodConfig;
+use crate::{Error, Result};
+
+/// Description of a model stored alongside its weights
+///
+/// The manifest lets the server configure itself from the model artifact instead of
+/// relying on the operator to pair CLI flags with the right weights file.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModelManifest {
+    /// Architecture the weights were trained for
+    pub architecture: Option<ModelArchitecture>,
+    /// Shape of a single input sample, excluding the batch dimension
+    pub input_shape: Option<Vec<usize>>,
+    /// Normalization applied to the [0, 1] scaled pixels before inference
+    pub normalization: Normalization,
+    /// Human readable names of the output classes, indexed by class id
+    pub labels: Vec<String>,
+    /// Metrics recorded at training time, e.g. test accuracy
+    pub metrics: BTreeMap<String, f64>,
+    /// Softmax temperature fitted by the `calibrate` command, applied to every prediction
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    /// Layers of a sequential model, see [`mnist::spec`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<LayerSpec>,
+    /// Views averaged by test-time augmentation, the defaults if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub augmentation: Option<Augmentation>,
+    /// Out-of-distribution scoring, written by the `fit-ood` command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ood: Option<OodConfig>,
+}
+
+/// Per-pixel normalization `(x - mean) / std`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Normalization {
+    pub mean: f32,
+    pub std: f32,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Self {
+            mean: 0.0,
+            std: 1.0,
+        }
+    }
+}
+
+impl Normalization {
+    pub fn is_identity(&self) -> bool {
+        self.mean == 0.0 && self.std == 1.0
+    }
+
+    /// Check that the inputs stay finite once normalized
+    pub fn validate(&self) -> Result<()> {
+        if !self.mean.is_finite() {
+            return Err(Error::custom(format!(
+                "Normalization mean must be finite, got {}",
+                self.mean
+            )));
+        }
+        if !self.std.is_finite() || self.std <= 0.0 {
+            return Err(Error::custom(format!(
+                "Normalization std must be positive, got {}",
+                self.std
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ModelManifest {
+    /// Read a manifest from a `.json` or `.toml` file
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let contents = std::fs::read(path).map_err(|e| {
+            Error::custom(format!("Failed to read manifest {}: {}", path.display(), e))
+        })?;
+        Self::from_bytes(path, &contents)
+    }
+
+    /// Parse the contents of the manifest file at `path`, as JSON or TOML depending on the
+    /// file extension
+    pub fn from_bytes(path: &Path, contents: &[u8]) -> Result<Self> {
+        let manifest = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => std::str::from_utf8(contents)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| toml::from_str(contents).map_err(|e| e.to_string())),
+            _ => serde_json::from_slice(contents).map_err(|e| e.to_string()),
+        };
+        manifest.map_err(|e| Error::custom(format!("Invalid manifest {}: {}", path.display(), e)))
+    }
+
+    /// Write the manifest as JSON or TOML depending on the file extension
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let contents = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => toml::to_string_pretty(self).map_err(|e| e.to_string()),
+            _ => serde_json::to_string_pretty(self).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| Error::custom(format!("Failed to serialize manifest: {}", e)))?;
+        std::fs::write(path, contents).map_err(|e| {
+            Error::custom(format!(
+                "Failed to write manifest {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Build a manifest from the string map stored in a safetensors `__metadata__` header
+    ///
+    /// Values that are not plain strings (input shape, labels, metrics) are JSON encoded,
+    /// since safetensors metadata only holds strings. Returns `None` if no known keys are set.
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Result<Option<Self>> {
+        fn parse<T: serde::de::DeserializeOwned>(key: &str, value: &str) -> Result<T> {
+            serde_json::from_str(value).map_err(|e| {
+                Error::custom(format!("Invalid `{}` in safetensors metadata: {}", key, e))
+            })
+        }
+
+        let mut manifest = ModelManifest::default();
+        let mut found = false;
+        for (key, value) in metadata {
+            match key.as_str() {
+                "architecture" => {
+                    let value = serde_json::Value::String(value.clone());
+                    manifest.architecture = Some(serde_json::from_value(value).map_err(|e| {
+                        Error::custom(format!("Invalid `architecture` in metadata: {}", e))
+                    })?)
+                }
+                "input_shape" => manifest.input_shape = Some(parse(key, value)?),
+                "normalization_mean" => manifest.normalization.mean = parse(key, value)?,
+                "normalization_std" => manifest.normalization.std = parse(key, value)?,
+                "labels" => manifest.labels = parse(key, value)?,
+                "metrics" => manifest.metrics = parse(key, value)?,
+                "temperature" => manifest.temperature = Some(parse(key, value)?),
+                "layers" => manifest.layers = parse(key, value)?,
+                _ => continue,
+            }
+            found = true;
+        }
+        Ok(found.then_some(manifest))
+    }
+
+    /// Look for a `<model>.manifest.json` or `<model>.manifest.toml` next to the weights
+    pub fn sidecar_paths(weights: &Path) -> Vec<PathBuf> {
+        let name = weights
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let stem = [
+            ".safetensors.index.json",
+            ".safetensors",
+            ".gguf",
+            ".onnx",
+            ".json",
+            ".toml",
+        ]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name);
+        ["json", "toml"]
+            .iter()
+            .map(|ext| weights.with_file_name(format!("{}.manifest.{}", stem, ext)))
+            .collect()
+    }
+}
+
+/// Read the `__metadata__` section from the contents of the safetensors file at `path`
+pub fn safetensors_metadata(path: &Path, data: &[u8]) -> Result<HashMap<String, String>> {
+    let invalid = |reason: String| {
+        Error::custom(format!(
+            "Invalid safetensors header in {}: {}",
+            path.display(),
+            reason
+        ))
+    };
+    let len = data
+        .first_chunk::<8>()
+        .map(|len| u64::from_le_bytes(*len))
+        .ok_or_else(|| invalid("file too short".to_string()))?;
+    // The format caps the header at 100MB, anything larger is not a safetensors file
+    let header = usize::try_from(len)
+        .ok()
+        .filter(|len| *len <= 100_000_000)
+        .and_then(|len| data.get(8..8 + len))
+        .ok_or_else(|| invalid(format!("header of {} bytes", len)))?;
+
+    #[derive(Deserialize)]
+    struct Header {
+        #[serde(rename = "__metadata__", default)]
+        metadata: HashMap<String, String>,
+    }
+    let header: Header = serde_json::from_slice(header).map_err(|e| invalid(e.to_string()))?;
+    Ok(header.metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_sidecar_paths() {
+        let paths = ModelManifest::sidecar_paths(Path::new("models/convnet.safetensors"));
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("models/convnet.manifest.json"),
+                PathBuf::from("models/convnet.manifest.toml")
+            ]
+        );
+
+        let paths = ModelManifest::sidecar_paths(Path::new("models/model.safetensors.index.json"));
+        assert_eq!(paths[0], PathBuf::from("models/model.manifest.json"));
+    }
+
+    #[test]
+    fn test_parse_json_and_toml() {
+        let dir = TempDir::new("manifest");
+
+        let json = dir.join("model.manifest.json");
+        std::fs::write(
+            &json,
+            r#"{"architecture": "conv", "labels": ["zero", "one"], "metrics": {"accuracy": 0.99}}"#,
+        )
+        .unwrap();
+        let manifest = ModelManifest::from_file(&json).unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::Conv));
+        assert_eq!(manifest.labels, vec!["zero", "one"]);
+        assert_eq!(manifest.metrics["accuracy"], 0.99);
+        assert!(manifest.normalization.is_identity());
+
+        let toml = dir.join("model.manifest.toml");
+        manifest.save(&toml).unwrap();
+        assert_eq!(ModelManifest::from_file(&toml).unwrap(), manifest);
+    }
+
+    #[test]
+    fn test_from_metadata() {
+        let metadata = HashMap::from([
+            ("architecture".to_string(), "mlp".to_string()),
+            ("input_shape".to_string(), "[784]".to_string()),
+            ("normalization_mean".to_string(), "0.1307".to_string()),
+            ("format".to_string(), "pt".to_string()),
+        ]);
+        let manifest = ModelManifest::from_metadata(&metadata).unwrap().unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::MLP));
+        assert_eq!(manifest.input_shape, Some(vec![784]));
+        assert_eq!(manifest.normalization.mean, 0.1307);
+
+        let metadata = HashMap::from([("format".to_string(), "pt".to_string())]);
+        assert!(ModelManifest::from_metadata(&metadata).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_normalization_validate() {
+        assert!(Normalization::default().validate().is_ok());
+        let normalization = Normalization {
+            mean: 0.1307,
+            std: 0.3081,
+        };
+        assert!(normalization.validate().is_ok());
+        for (mean, std) in [
+            (0.0, 0.0),
+            (0.0, -1.0),
+            (0.0, f32::NAN),
+            (0.0, f32::INFINITY),
+            (f32::NAN, 1.0),
+        ] {
+            assert!(Normalization { mean, std }.validate().is_err());
+        }
+    }
+}
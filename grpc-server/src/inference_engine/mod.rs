@@ -1,14 +1,39 @@
 use crate::Error;
 use crate::Result;
+use augmentation::Augmentation;
 use candle_core::Tensor;
 use candle_core::{DType, Device};
-use candle_nn::{VarBuilder, VarMap};
+use candle_nn::{Module, VarBuilder, VarMap};
+use decision::{Rejection, RejectionPolicy};
+use ensemble::{Ensemble, EnsembleConfig, MemberPrediction};
+use manifest::ModelManifest;
 use mnist::ConvNet;
 use mnist::MnistMLP;
-use weights_provider::WeightsProvider;
+use mnist::{ModelSpec, SequentialModel};
+use mnist::{Quantization, QuantizedConvNet, QuantizedMLP, QuantizedWeights};
+use onnx::OnnxModel;
+use ood::OodConfig;
+use postprocess::{OutputMode, PostProcessing};
+use serde::{Deserialize, Serialize};
+use validation::ValidationReport;
+use weights_provider::{WeightsProvider, WeightsSource};
 
+pub mod augmentation;
+pub mod calibration;
+pub mod decision;
+pub mod ensemble;
+pub mod explain;
+pub mod integrity;
+pub mod manifest;
+pub mod onnx;
+pub mod ood;
+pub mod postprocess;
+pub mod validation;
 pub mod weights_provider;
 
+/// Number of pixels in a 28x28 MNIST image
+pub const INPUT_SIZE: usize = 28 * 28;
+
 /// InferenceEngine struct to encapsulate the model and device
 ///
 /// It is responsible for loading the model and performing inference
@@ -17,6 +42,17 @@ pub struct InferenceEngine {
     device: Device,
     dtype: DType,
     model: MnistModel,
+    architecture: ModelArchitecture,
+    input_shape: Vec<usize>,
+    manifest: ModelManifest,
+    /// Name declared by the model artifact, e.g. in an ensemble file
+    name: Option<String>,
+    /// Views of test-time augmentation, from the manifest or the defaults
+    augmentation: Augmentation,
+    /// Out-of-distribution scoring from the manifest, if the model has any
+    ood: Option<OodConfig>,
+    /// Number of class scores the model returns per image
+    num_classes: usize,
 }
 
 impl InferenceEngine {
@@ -30,37 +66,309 @@ impl InferenceEngine {
     /// - `input` -  vector of f32 representing the input image data, should be of size 784 (28x28 pixels flattened)
     ///
     pub fn predict(&self, input: Vec<f32>) -> Result<Prediction> {
-        let tensor = Tensor::from_vec(input, (1, 1, 28, 28), &self.device)?;
-        let output = self.model.forward(&tensor)?;
-        let output = output.flatten_all()?;
-        let class_pred = output.argmax(0)?.to_scalar::<u32>()?;
-        let probabilities = output.to_vec1()?;
-        Ok(Prediction {
-            digit: class_pred,
-            probabilities,
-        })
+        self.predict_with(input, &PredictOptions::default())
+    }
+
+    /// Predict with the post-processing, ranking and rejection given in `options`
+    pub fn predict_with(&self, input: Vec<f32>, options: &PredictOptions) -> Result<Prediction> {
+        let mut predictions = self.predict_batch(vec![input], options)?;
+        Ok(predictions.remove(0))
+    }
+
+    /// Predict several images in a single forward pass
+    ///
+    /// With test-time augmentation every image is expanded into its views, all views run
+    /// in the same batch, and the probabilities and logits are averaged per image. If the
+    /// manifest configures out-of-distribution scoring, every prediction is scored too.
+    pub fn predict_batch(
+        &self,
+        inputs: Vec<Vec<f32>>,
+        options: &PredictOptions,
+    ) -> Result<Vec<Prediction>> {
+        let views = if self.augments(options) {
+            self.augmentation.view_count()
+        } else {
+            1
+        };
+        let inputs = if views > 1 {
+            let mut augmented = Vec::with_capacity(inputs.len() * views);
+            for input in &inputs {
+                augmented.extend(self.augmentation.views(input)?);
+            }
+            augmented
+        } else {
+            inputs
+        };
+
+        let needs_features = self.ood.as_ref().is_some_and(OodConfig::needs_features);
+        let (logits, mut members, features) = if needs_features {
+            let (logits, features) = self.forward_with_features(inputs)?;
+            // Features of the images as sent, even when the prediction is augmented
+            let features: Vec<Vec<f32>> = features.to_vec2()?;
+            let features: Vec<Vec<f32>> = features.into_iter().step_by(views).collect();
+            (logits, Vec::new(), Some(features))
+        } else {
+            let (logits, members) = self.forward_with_members(inputs)?;
+            (logits, members, None)
+        };
+        let post = self.post_processing(&options.post_processing);
+        let (probabilities, scores, logits) = if views > 1 {
+            let batch_size = logits.dim(0)? / views;
+            let probabilities = post
+                .probabilities(&logits)?
+                .reshape((batch_size, views, ()))?
+                .mean(1)?;
+            let logits = logits.reshape((batch_size, views, ()))?.mean(1)?;
+            let scores = match post.mode {
+                OutputMode::Softmax => probabilities.clone(),
+                OutputMode::LogSoftmax => probabilities.log()?,
+                OutputMode::Logits => post.apply(&logits)?,
+            };
+            // Members are reported for the original images
+            members = members.into_iter().step_by(views).collect();
+            (probabilities, scores, logits)
+        } else {
+            (post.probabilities(&logits)?, post.apply(&logits)?, logits)
+        };
+        let probabilities: Vec<Vec<f32>> = probabilities.to_vec2()?;
+        let scores: Vec<Vec<f32>> = scores.to_vec2()?;
+        let logits: Vec<Vec<f32>> = logits.to_vec2()?;
+
+        if !options.include_members {
+            members.clear();
+        }
+        members.resize_with(logits.len(), Vec::new);
+
+        let mut predictions: Vec<Prediction> = logits
+            .into_iter()
+            .zip(members)
+            .zip(scores)
+            .zip(probabilities)
+            .map(|(((logits, members), scores), probabilities)| {
+                let top_k: Vec<ClassScore> = decision::top_k(&probabilities, options.top_k.max(1))
+                    .into_iter()
+                    .map(|(class, probability)| ClassScore {
+                        class,
+                        label: self.label(class),
+                        probability,
+                    })
+                    .collect();
+                let digit = top_k[0].class;
+                Prediction {
+                    digit,
+                    label: self.label(digit),
+                    rejection: options.rejection.evaluate(&probabilities),
+                    top_k: top_k.into_iter().take(options.top_k).collect(),
+                    probabilities,
+                    scores,
+                    logits,
+                    members,
+                    views,
+                    ood_score: None,
+                    out_of_distribution: false,
+                }
+            })
+            .collect();
+        if let Some(ood) = &self.ood {
+            for (i, prediction) in predictions.iter_mut().enumerate() {
+                let features = features.as_ref().map(|features| features[i].as_slice());
+                let score = ood.score(&prediction.logits, features)?;
+                prediction.ood_score = Some(score);
+                prediction.out_of_distribution = ood.flags(score);
+            }
+        }
+        Ok(predictions)
+    }
+
+    /// Raw logits of several images, one row of class scores per image
+    pub fn logits_batch(&self, inputs: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>> {
+        Ok(self.forward(inputs)?.to_vec2()?)
+    }
+
+    /// Run the model on a batch of images, returning the `[N, 10]` logits as f32
+    fn forward(&self, inputs: Vec<Vec<f32>>) -> Result<Tensor> {
+        Ok(self.forward_with_members(inputs)?.0)
+    }
+
+    /// Like [`Self::forward`], also returning the member predictions of an ensemble per image
+    fn forward_with_members(
+        &self,
+        inputs: Vec<Vec<f32>>,
+    ) -> Result<(Tensor, Vec<Vec<MemberPrediction>>)> {
+        if inputs.is_empty() {
+            return Err(Error::invalid_argument("No images to predict"));
+        }
+        if let MnistModel::Ensemble(ensemble) = &self.model {
+            // Every member normalizes the images itself
+            return ensemble.forward(&inputs);
+        }
+        let batch_size = inputs.len();
+        let logits = self.model.forward(&self.input_tensor(inputs)?)?;
+        let logits = logits.reshape((batch_size, ()))?.to_dtype(DType::F32)?;
+        Ok((logits, Vec::new()))
+    }
+
+    /// Logits and penultimate activations of a batch of images in a single forward pass
+    fn forward_with_features(&self, inputs: Vec<Vec<f32>>) -> Result<(Tensor, Tensor)> {
+        if inputs.is_empty() {
+            return Err(Error::invalid_argument("No images to predict"));
+        }
+        let batch_size = inputs.len();
+        let (logits, features) = self
+            .model
+            .forward_with_features(&self.input_tensor(inputs)?)?;
+        let logits = logits.reshape((batch_size, ()))?.to_dtype(DType::F32)?;
+        let features = features.reshape((batch_size, ()))?.to_dtype(DType::F32)?;
+        Ok((logits, features))
+    }
+
+    /// Penultimate activations of several images in a single forward pass, one row each
+    ///
+    /// Ensembles and ONNX graphs do not expose their features.
+    pub fn embed_batch(&self, inputs: Vec<Vec<f32>>) -> Result<Vec<Vec<f32>>> {
+        if inputs.is_empty() {
+            return Err(Error::invalid_argument("No images to embed"));
+        }
+        let batch_size = inputs.len();
+        let features = self.model.forward_features(&self.input_tensor(inputs)?)?;
+        let features = features.reshape((batch_size, ()))?.to_dtype(DType::F32)?;
+        Ok(features.to_vec2()?)
+    }
+
+    /// Normalize a batch of images into the input shape of the model
+    fn input_tensor(&self, inputs: Vec<Vec<f32>>) -> Result<Tensor> {
+        let batch_size = inputs.len();
+        let mut data = Vec::with_capacity(batch_size * INPUT_SIZE);
+        for input in inputs {
+            if input.len() != INPUT_SIZE {
+                return Err(Error::custom(format!(
+                    "Expected {} input values, got {}",
+                    INPUT_SIZE,
+                    input.len()
+                )));
+            }
+            data.extend(self.normalize(input));
+        }
+
+        let mut shape = vec![batch_size];
+        shape.extend(&self.input_shape);
+        Ok(Tensor::from_vec(data, shape, &self.device)?.to_dtype(self.dtype)?)
+    }
+
+    /// Whether a request with `options` is answered with test-time augmentation
+    fn augments(&self, options: &PredictOptions) -> bool {
+        options
+            .test_time_augmentation
+            .unwrap_or(self.augmentation.enabled)
+    }
+
+    /// Views of test-time augmentation
+    pub fn augmentation(&self) -> &Augmentation {
+        &self.augmentation
+    }
+
+    /// Out-of-distribution scoring of the model, `None` if the manifest has none
+    pub fn ood(&self) -> Option<&OodConfig> {
+        self.ood.as_ref()
+    }
+
+    /// Apply the calibrated temperature from the manifest on top of the requested one
+    fn post_processing(&self, post: &PostProcessing) -> PostProcessing {
+        match self.manifest.temperature {
+            Some(temperature) => post.calibrated(temperature),
+            None => *post,
+        }
+    }
+
+    /// Name of a class from the manifest labels
+    fn label(&self, class: u32) -> Option<String> {
+        self.manifest.labels.get(class as usize).cloned()
+    }
+
+    /// Architecture of the loaded model
+    pub fn architecture(&self) -> ModelArchitecture {
+        self.architecture
+    }
+
+    /// Shape of a single input sample, excluding the batch dimension
+    pub fn input_shape(&self) -> &[usize] {
+        &self.input_shape
+    }
+
+    /// Number of class scores the model returns per image
+    pub fn num_classes(&self) -> usize {
+        self.num_classes
+    }
+
+    /// Count the classes of the model by running it on a blank image
+    fn with_num_classes(mut self) -> Result<Self> {
+        self.num_classes = self.forward(vec![vec![0.0; INPUT_SIZE]])?.dim(1)?;
+        Ok(self)
+    }
+
+    /// Name declared by the model artifact, if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Manifest the model was loaded with, empty if the weights had none
+    pub fn manifest(&self) -> &ModelManifest {
+        &self.manifest
+    }
+
+    fn normalize(&self, mut input: Vec<f32>) -> Vec<f32> {
+        let normalization = self.manifest.normalization;
+        if !normalization.is_identity() {
+            input
+                .iter_mut()
+                .for_each(|x| *x = (*x - normalization.mean) / normalization.std);
+        }
+        input
     }
 }
 
-#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ModelArchitecture {
     MLP,
     Conv,
+    /// Layers declared in a model spec, see [`mnist::spec`]
+    Sequential,
+    /// Graph exported to ONNX, loaded from a `.onnx` file instead of safetensors
+    Onnx,
+    /// Several models combined into one, see [`ensemble`]
+    Ensemble,
+}
+
+impl ModelArchitecture {
+    /// Shape of a single input sample expected by the architecture
+    pub fn input_shape(&self) -> Vec<usize> {
+        match self {
+            ModelArchitecture::MLP | ModelArchitecture::Ensemble => vec![INPUT_SIZE],
+            ModelArchitecture::Conv | ModelArchitecture::Sequential | ModelArchitecture::Onnx => {
+                vec![1, 28, 28]
+            }
+        }
+    }
 }
 
 /// Builder class for the InferenceEngine
 pub struct InferenceEngineBuilder {
     model_architecture: Option<ModelArchitecture>,
+    model_spec: Option<ModelSpec>,
     device: Option<Device>,
     dtype: Option<DType>,
+    quantization: Option<Quantization>,
 }
 
 impl InferenceEngineBuilder {
     pub fn new() -> Self {
         Self {
             model_architecture: None,
+            model_spec: None,
             device: None,
             dtype: None,
+            quantization: None,
         }
     }
 
@@ -68,11 +376,19 @@ impl InferenceEngineBuilder {
         self.model_architecture = Some(arch);
         InferenceEngineBuilder {
             model_architecture: self.model_architecture,
+            model_spec: self.model_spec,
             device: self.device,
             dtype: self.dtype,
+            quantization: self.quantization,
         }
     }
 
+    /// Layers of a [`ModelArchitecture::Sequential`] model, overriding the manifest
+    pub fn model_spec(mut self, spec: ModelSpec) -> Self {
+        self.model_spec = Some(spec);
+        self
+    }
+
     pub fn device(mut self, device: Device) -> Self {
         self.device = Some(device);
         self
@@ -83,44 +399,395 @@ impl InferenceEngineBuilder {
         self
     }
 
+    /// Quantize the linear layers of an MLP or ConvNet when loading safetensors weights
+    ///
+    /// GGUF weights are always served as quantized in the file.
+    pub fn quantization(mut self, quantization: Quantization) -> Self {
+        self.quantization = Some(quantization);
+        self
+    }
+
     /// Build the InferenceEngine with the specified weights provider that loads the model weights
+    ///
+    /// The architecture is taken from the builder or the model manifest; if both are
+    /// given they must agree. The weights are validated against the architecture before
+    /// the model is initialized. ONNX models are checked against the MNIST input/output
+    /// contract instead, and GGUF weights by building the quantized model.
     pub fn build<P: WeightsProvider>(self, provider: P) -> Result<InferenceEngine> {
+        let manifest = provider.load_manifest()?.unwrap_or_default();
+        manifest.normalization.validate()?;
+        let arch = self.resolve_architecture(&manifest)?;
+        let spec = self.resolve_spec(arch, &manifest)?;
         let device = self.device.unwrap_or(Device::Cpu);
-        let dtype = self.dtype.unwrap_or(DType::F32);
+        let mut dtype = self.dtype.unwrap_or(DType::F32);
+
+        match arch {
+            ModelArchitecture::Onnx => return Self::build_onnx(provider, manifest, device, dtype),
+            ModelArchitecture::Ensemble => {
+                return Self::build_ensemble(provider, manifest, device, dtype);
+            }
+            _ => {}
+        }
+
+        let input_shape = manifest
+            .input_shape
+            .clone()
+            .or_else(|| spec.as_ref().map(|spec| spec.input_shape.clone()))
+            .unwrap_or_else(|| arch.input_shape());
+        if input_shape.iter().product::<usize>() != INPUT_SIZE {
+            return Err(Error::custom(format!(
+                "Input shape {:?} from the model manifest does not hold a 28x28 image",
+                input_shape
+            )));
+        }
 
         // Load the weights from the provider
         let weights = provider.load_weights()?;
-        let varbuilder = VarBuilder::from_buffered_safetensors(weights, dtype, &device)?;
+        let gguf = weights.is_gguf()?;
+        if !gguf {
+            let report = ValidationReport::new(arch, spec.as_ref(), &weights.tensor_shapes()?)?;
+            if !report.is_valid() {
+                return Err(Error::InvalidWeights(Box::new(report)));
+            }
+            for tensor in &report.unexpected {
+                tracing::warn!(tensor = %tensor.name, shape = ?tensor.shape, "Ignoring unexpected tensor in weights");
+            }
+        }
 
         // Initialize the model based on the architecture
-        // Errors if the architecture is not set
-        let model = match self.model_architecture {
-            Some(arch) => MnistModel::new(varbuilder, arch)?,
-            None => return Err(Error::custom("Model architecture not set")),
+        let model = if gguf || self.quantization.is_some() {
+            if dtype != DType::F32 {
+                tracing::warn!(dtype = ?dtype, "Quantized models are evaluated in f32");
+                dtype = DType::F32;
+            }
+            let weights = Self::quantized_weights(weights, self.quantization, &device)?;
+            tracing::info!(
+                quantization = ?self.quantization,
+                gguf,
+                bytes = weights.storage_size(),
+                "Quantized linear layers"
+            );
+            MnistModel::quantized(&weights, arch)?
+        } else {
+            let varbuilder = weights.var_builder(dtype, &device)?;
+            MnistModel::new(varbuilder, arch, spec.as_ref())?
         };
-        Ok(InferenceEngine {
+        tracing::info!(
+            architecture = ?arch,
+            labels = manifest.labels.len(),
+            metrics = ?manifest.metrics,
+            "Loaded model"
+        );
+        InferenceEngine {
             device,
             dtype,
             model,
-        })
+            architecture: arch,
+            input_shape,
+            augmentation: augmentation(&manifest)?,
+            ood: ood(&manifest, arch)?,
+            manifest,
+            name: None,
+            num_classes: 0,
+        }
+        .with_num_classes()
+    }
+
+    /// Read GGUF weights as they are, or quantize safetensors weights
+    fn quantized_weights(
+        weights: WeightsSource,
+        quantization: Option<Quantization>,
+        device: &Device,
+    ) -> Result<QuantizedWeights> {
+        if weights.is_gguf()? {
+            if let Some(quantization) = quantization {
+                tracing::warn!(%quantization, "Ignoring the quantization of GGUF weights");
+            }
+            return weights.gguf_weights(device);
+        }
+        let quantization = quantization.unwrap_or(Quantization::Q8_0);
+        let tensors = weights.tensors(&Device::Cpu)?;
+        Ok(QuantizedWeights::quantize(&tensors, quantization, device)?)
+    }
+
+    fn build_onnx<P: WeightsProvider>(
+        provider: P,
+        manifest: ModelManifest,
+        device: Device,
+        dtype: DType,
+    ) -> Result<InferenceEngine> {
+        let model = OnnxModel::from_source(provider.load_weights()?)?;
+        let input_shape = model.input_shape().to_vec();
+        if let Some(declared) = &manifest.input_shape
+            && declared != &input_shape
+        {
+            return Err(Error::custom(format!(
+                "Input shape {:?} from the model manifest does not match {:?} of the ONNX graph",
+                declared, input_shape
+            )));
+        }
+        if dtype != DType::F32 {
+            tracing::warn!(dtype = ?dtype, "ONNX models are evaluated in f32");
+        }
+
+        tracing::info!(
+            architecture = ?ModelArchitecture::Onnx,
+            input_shape = ?input_shape,
+            labels = manifest.labels.len(),
+            metrics = ?manifest.metrics,
+            "Loaded model"
+        );
+        InferenceEngine {
+            device,
+            dtype: DType::F32,
+            model: MnistModel::Onnx(Box::new(model)),
+            architecture: ModelArchitecture::Onnx,
+            input_shape,
+            augmentation: augmentation(&manifest)?,
+            ood: ood(&manifest, ModelArchitecture::Onnx)?,
+            manifest,
+            name: None,
+            num_classes: 0,
+        }
+        .with_num_classes()
+    }
+
+    fn build_ensemble<P: WeightsProvider>(
+        provider: P,
+        mut manifest: ModelManifest,
+        device: Device,
+        dtype: DType,
+    ) -> Result<InferenceEngine> {
+        let (config, dir) = EnsembleConfig::from_source(provider.load_weights()?)?;
+        let ensemble = Ensemble::new(&config, &dir, &provider.integrity(), &device, dtype)?;
+        if manifest.labels.is_empty() {
+            manifest.labels = ensemble.labels();
+        }
+        // The members normalize their own inputs
+        manifest.normalization = Default::default();
+
+        tracing::info!(
+            architecture = ?ModelArchitecture::Ensemble,
+            name = ?config.name,
+            strategy = ?ensemble.strategy(),
+            members = ensemble.len(),
+            "Loaded model"
+        );
+        InferenceEngine {
+            device,
+            dtype,
+            model: MnistModel::Ensemble(Box::new(ensemble)),
+            architecture: ModelArchitecture::Ensemble,
+            input_shape: vec![INPUT_SIZE],
+            augmentation: augmentation(&manifest)?,
+            ood: ood(&manifest, ModelArchitecture::Ensemble)?,
+            manifest,
+            name: config.name,
+            num_classes: 0,
+        }
+        .with_num_classes()
+    }
+
+    /// Compare the weights of `provider` against the architecture without building the model
+    ///
+    /// ONNX graphs carry their own weights, so they are only checked against the MNIST
+    /// input/output contract and an error is returned if they do not satisfy it. Ensembles
+    /// are checked by building every member. GGUF weights are compared with their padding
+    /// removed, and a matching file is also checked by building the quantized model.
+    pub fn validate<P: WeightsProvider>(&self, provider: &P) -> Result<ValidationReport> {
+        let manifest = provider.load_manifest()?.unwrap_or_default();
+        let arch = self.resolve_architecture(&manifest)?;
+        match arch {
+            ModelArchitecture::Onnx => {
+                OnnxModel::from_source(provider.load_weights()?)?;
+                return ValidationReport::new(arch, None, &Default::default());
+            }
+            ModelArchitecture::Ensemble => {
+                let (config, dir) = EnsembleConfig::from_source(provider.load_weights()?)?;
+                Ensemble::new(
+                    &config,
+                    &dir,
+                    &provider.integrity(),
+                    &Device::Cpu,
+                    DType::F32,
+                )?;
+                return ValidationReport::new(arch, None, &Default::default());
+            }
+            _ => {}
+        }
+        let spec = self.resolve_spec(arch, &manifest)?;
+        let weights = provider.load_weights()?;
+        if weights.is_gguf()? {
+            let quantized = weights.gguf_weights(&Device::Cpu)?;
+            let expected = validation::expected_tensors(arch, spec.as_ref())?;
+            let report = ValidationReport::new(
+                arch,
+                spec.as_ref(),
+                &validation::quantized_shapes(&quantized, &expected),
+            )?;
+            if report.is_valid() {
+                MnistModel::quantized(&quantized, arch)?;
+            }
+            return Ok(report);
+        }
+        ValidationReport::new(arch, spec.as_ref(), &weights.tensor_shapes()?)
+    }
+
+    /// Pick the architecture set on the builder or declared in the manifest
+    ///
+    /// A model spec without an explicit architecture implies a sequential model.
+    /// Errors if neither is set or if both are set and disagree.
+    fn resolve_architecture(&self, manifest: &ModelManifest) -> Result<ModelArchitecture> {
+        let declared = manifest.architecture.or_else(|| {
+            (self.model_spec.is_some() || !manifest.layers.is_empty())
+                .then_some(ModelArchitecture::Sequential)
+        });
+        match (self.model_architecture, declared) {
+            (Some(arch), Some(declared)) if arch != declared => Err(Error::custom(format!(
+                "Model architecture {:?} does not match {:?} declared in the model manifest",
+                arch, declared
+            ))),
+            (Some(arch), _) | (None, Some(arch)) => Ok(arch),
+            (None, None) => Err(Error::custom("Model architecture not set")),
+        }
+    }
+
+    /// Layers of a sequential model from the builder or the manifest, `None` for builtin ones
+    fn resolve_spec(
+        &self,
+        arch: ModelArchitecture,
+        manifest: &ModelManifest,
+    ) -> Result<Option<ModelSpec>> {
+        if arch != ModelArchitecture::Sequential {
+            return Ok(None);
+        }
+        if let Some(spec) = &self.model_spec {
+            return Ok(Some(spec.clone()));
+        }
+        if manifest.layers.is_empty() {
+            return Err(Error::custom(
+                "Sequential architecture requires a model spec or layers in the model manifest",
+            ));
+        }
+        Ok(Some(ModelSpec {
+            input_shape: manifest
+                .input_shape
+                .clone()
+                .unwrap_or_else(|| arch.input_shape()),
+            layers: manifest.layers.clone(),
+        }))
+    }
+}
+
+/// Views of test-time augmentation declared in the manifest, or the defaults
+fn augmentation(manifest: &ModelManifest) -> Result<Augmentation> {
+    let augmentation = manifest.augmentation.clone().unwrap_or_default();
+    augmentation.validate()?;
+    Ok(augmentation)
+}
+
+/// Out-of-distribution scoring declared in the manifest
+///
+/// The Mahalanobis method reads penultimate features, which ONNX graphs and ensembles do
+/// not expose.
+fn ood(manifest: &ModelManifest, arch: ModelArchitecture) -> Result<Option<OodConfig>> {
+    let Some(ood) = &manifest.ood else {
+        return Ok(None);
+    };
+    ood.validate()?;
+    if ood.needs_features() && matches!(arch, ModelArchitecture::Onnx | ModelArchitecture::Ensemble)
+    {
+        return Err(Error::custom(format!(
+            "The mahalanobis OOD method needs the features of the model, {:?} models do not \
+             expose them",
+            arch
+        )));
+    }
+    Ok(Some(ood.clone()))
+}
+
+impl Default for InferenceEngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-request options of [`InferenceEngine::predict_with`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PredictOptions {
+    pub post_processing: PostProcessing,
+    /// Number of most probable classes to return
+    pub top_k: usize,
+    pub rejection: RejectionPolicy,
+    /// Return the predictions of every ensemble member, for debugging
+    pub include_members: bool,
+    /// Average the prediction over augmented views, `None` follows the model manifest
+    pub test_time_augmentation: Option<bool>,
+}
+
+impl Default for PredictOptions {
+    fn default() -> Self {
+        Self {
+            post_processing: PostProcessing::default(),
+            top_k: 1,
+            rejection: RejectionPolicy::default(),
+            include_members: false,
+            test_time_augmentation: None,
+        }
     }
 }
 
 /// Prediction struct to hold the result of the inference
 pub struct Prediction {
     pub digit: u32,
+    /// Name of the predicted class, if the manifest defines labels
+    pub label: Option<String>,
+    /// Temperature-scaled softmax of the logits, calibrated if the manifest has a temperature
     pub probabilities: Vec<f32>,
+    /// Scores in the requested [`postprocess::OutputMode`]
+    pub scores: Vec<f32>,
+    pub logits: Vec<f32>,
+    /// Most probable classes, in descending order of probability
+    pub top_k: Vec<ClassScore>,
+    /// Set if the prediction is too uncertain according to the rejection policy
+    pub rejection: Option<Rejection>,
+    /// Predictions of the ensemble members, if requested and the model is an ensemble
+    pub members: Vec<MemberPrediction>,
+    /// Number of views the prediction is averaged over, 1 without test-time augmentation
+    pub views: usize,
+    /// Out-of-distribution score, `None` if the manifest configures no scoring
+    pub ood_score: Option<f32>,
+    /// Set if the OOD score is above the threshold of the model
+    pub out_of_distribution: bool,
+}
+
+/// Probability of a single class
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassScore {
+    pub class: u32,
+    pub label: Option<String>,
+    pub probability: f32,
 }
 
 /// Model enum to encapsulate different architectures
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 enum MnistModel {
     MLP(MnistMLP),
     Conv(ConvNet),
+    Sequential(SequentialModel),
+    Onnx(Box<OnnxModel>),
+    Ensemble(Box<Ensemble>),
+    QuantizedMLP(QuantizedMLP),
+    QuantizedConv(QuantizedConvNet),
 }
 
 impl MnistModel {
-    pub fn new(varbuilder: VarBuilder, arch: ModelArchitecture) -> Result<Self> {
+    pub fn new(
+        varbuilder: VarBuilder,
+        arch: ModelArchitecture,
+        spec: Option<&ModelSpec>,
+    ) -> Result<Self> {
         match arch {
             ModelArchitecture::MLP => {
                 let model = MnistMLP::new(varbuilder)?;
@@ -130,14 +797,647 @@ impl MnistModel {
                 let model = ConvNet::new(varbuilder)?;
                 Ok(MnistModel::Conv(model))
             }
+            ModelArchitecture::Sequential => {
+                let spec = spec.ok_or_else(|| Error::custom("Model spec not set"))?;
+                let model = SequentialModel::new(spec, varbuilder)?;
+                Ok(MnistModel::Sequential(model))
+            }
+            ModelArchitecture::Onnx => Err(Error::custom(
+                "ONNX models are loaded from their graph, not from safetensors weights",
+            )),
+            ModelArchitecture::Ensemble => Err(Error::custom(
+                "Ensembles are loaded from an ensemble file, not from safetensors weights",
+            )),
         }
     }
 
+    /// Build the quantized variant of an MLP or ConvNet
+    pub fn quantized(weights: &QuantizedWeights, arch: ModelArchitecture) -> Result<Self> {
+        match arch {
+            ModelArchitecture::MLP => Ok(MnistModel::QuantizedMLP(QuantizedMLP::new(weights)?)),
+            ModelArchitecture::Conv => {
+                Ok(MnistModel::QuantizedConv(QuantizedConvNet::new(weights)?))
+            }
+            other => Err(Error::custom(format!(
+                "Quantization is only supported for the mlp and conv architectures, got {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// Returns the logits of the model
     pub fn forward(&self, input: &Tensor) -> Result<Tensor> {
         // Map the candle error to our internal error type
         match self {
             MnistModel::MLP(model) => model.forward(input).map_err(|e| e.into()),
             MnistModel::Conv(model) => model.forward(input).map_err(|e| e.into()),
+            // Log-probabilities only differ from the logits by a constant
+            MnistModel::Sequential(model) if model.outputs_probabilities() => {
+                Ok(model.forward(input)?.log()?)
+            }
+            MnistModel::Sequential(model) => model.forward(input).map_err(|e| e.into()),
+            MnistModel::Onnx(model) => model.forward(input),
+            MnistModel::Ensemble(_) => Err(Error::custom(
+                "Ensembles run their members on the raw images",
+            )),
+            MnistModel::QuantizedMLP(model) => model.forward(input).map_err(|e| e.into()),
+            MnistModel::QuantizedConv(model) => model.forward(input).map_err(|e| e.into()),
+        }
+    }
+
+    /// Returns the logits and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, input: &Tensor) -> Result<(Tensor, Tensor)> {
+        match self {
+            MnistModel::MLP(model) => Ok(model.forward_with_features(input)?),
+            MnistModel::Conv(model) => Ok(model.forward_with_features(input)?),
+            MnistModel::Sequential(model) => {
+                let (outputs, features) = model.forward_with_features(input)?;
+                // Log-probabilities only differ from the logits by a constant
+                if model.outputs_probabilities() {
+                    Ok((outputs.log()?, features))
+                } else {
+                    Ok((outputs, features))
+                }
+            }
+            MnistModel::QuantizedMLP(model) => Ok(model.forward_with_features(input)?),
+            MnistModel::QuantizedConv(model) => Ok(model.forward_with_features(input)?),
+            MnistModel::Onnx(_) | MnistModel::Ensemble(_) => {
+                let features = self.forward_features(input)?;
+                Ok((self.forward(input)?, features))
+            }
+        }
+    }
+
+    /// Returns the penultimate activations of the model
+    pub fn forward_features(&self, input: &Tensor) -> Result<Tensor> {
+        match self {
+            MnistModel::MLP(model) => Ok(model.forward_features(input)?),
+            MnistModel::Conv(model) => Ok(model.forward_features(input)?),
+            MnistModel::Sequential(model) => Ok(model.forward_features(input)?),
+            MnistModel::QuantizedMLP(model) => Ok(model.forward_features(input)?),
+            MnistModel::QuantizedConv(model) => Ok(model.forward_features(input)?),
+            MnistModel::Onnx(_) => Err(Error::invalid_argument(
+                "ONNX models do not expose their features, only their outputs",
+            )),
+            MnistModel::Ensemble(_) => Err(Error::invalid_argument(
+                "Ensembles have no features of their own, embed with one of the members",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::weights_provider::LocalFileProvider;
+    use super::*;
+    use crate::test_util::{TempDir, random_spec_weights, random_weights};
+
+    #[test]
+    fn test_predict() {
+        for arch in [ModelArchitecture::MLP, ModelArchitecture::Conv] {
+            let (_dir, path) = random_weights(arch);
+            let engine = InferenceEngine::builder()
+                .model_architecture(arch)
+                .build(LocalFileProvider::new(path))
+                .unwrap();
+
+            assert_eq!(engine.num_classes(), 10);
+            let prediction = engine.predict(vec![0.5; INPUT_SIZE]).unwrap();
+            assert!(prediction.digit < 10);
+            assert!(prediction.label.is_none());
+            assert_eq!(prediction.probabilities.len(), 10);
+            let total: f32 = prediction.probabilities.iter().sum();
+            assert!((total - 1.0).abs() < 1e-4);
+            assert_eq!(prediction.top_k.len(), 1);
+            assert_eq!(prediction.top_k[0].class, prediction.digit);
+            assert!(prediction.rejection.is_none());
+
+            // Random weights are far from confident
+            let options = PredictOptions {
+                top_k: 3,
+                rejection: RejectionPolicy::new().with_min_confidence(0.99).unwrap(),
+                ..Default::default()
+            };
+            let prediction = engine
+                .predict_with(vec![0.5; INPUT_SIZE], &options)
+                .unwrap();
+            assert_eq!(prediction.top_k.len(), 3);
+            assert!(prediction.top_k[0].probability >= prediction.top_k[1].probability);
+            assert!(matches!(
+                prediction.rejection,
+                Some(Rejection::LowConfidence { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_test_time_augmentation() {
+        let (_dir, path) = random_weights(ModelArchitecture::Conv);
+        let provider = LocalFileProvider::new(path);
+        let engine = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::Conv)
+            .build(provider.clone())
+            .unwrap();
+        let inputs = vec![vec![0.5; INPUT_SIZE], vec![0.1; INPUT_SIZE]];
+        let options = PredictOptions {
+            test_time_augmentation: Some(true),
+            post_processing: PostProcessing::new(OutputMode::LogSoftmax),
+            ..Default::default()
+        };
+        let predictions = engine.predict_batch(inputs.clone(), &options).unwrap();
+        assert_eq!(predictions.len(), 2);
+        for prediction in &predictions {
+            assert_eq!(prediction.views, 9);
+            let total: f32 = prediction.probabilities.iter().sum();
+            assert!((total - 1.0).abs() < 1e-4);
+            for (p, log_p) in prediction.probabilities.iter().zip(&prediction.scores) {
+                assert!((p.ln() - log_p).abs() < 1e-4);
+            }
+        }
+
+        // The averaged probabilities are the mean over the views
+        let views = engine.augmentation().views(&inputs[1]).unwrap();
+        let single = engine
+            .predict_batch(views, &PredictOptions::default())
+            .unwrap();
+        for class in 0..10 {
+            let mean = single.iter().map(|p| p.probabilities[class]).sum::<f32>() / 9.0;
+            assert!((predictions[1].probabilities[class] - mean).abs() < 1e-5);
+        }
+
+        // Enabled in the manifest, requests can still opt out
+        let manifest = ModelManifest {
+            augmentation: Some(augmentation::Augmentation {
+                enabled: true,
+                rotations: vec![15.0],
+                shifts: Vec::new(),
+                scales: Vec::new(),
+            }),
+            ..Default::default()
+        };
+        manifest.save(&provider.manifest_path()).unwrap();
+        let engine = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::Conv)
+            .build(provider)
+            .unwrap();
+        assert_eq!(engine.predict(inputs[0].clone()).unwrap().views, 2);
+        let options = PredictOptions {
+            test_time_augmentation: Some(false),
+            ..Default::default()
+        };
+        let prediction = engine.predict_with(inputs[0].clone(), &options).unwrap();
+        assert_eq!(prediction.views, 1);
+    }
+
+    #[test]
+    fn test_embed_batch() {
+        for (arch, dimensions) in [(ModelArchitecture::MLP, 64), (ModelArchitecture::Conv, 128)] {
+            let (_dir, path) = random_weights(arch);
+            let inputs = vec![vec![0.5; INPUT_SIZE], vec![0.1; INPUT_SIZE]];
+            let engine = InferenceEngine::builder()
+                .model_architecture(arch)
+                .build(LocalFileProvider::new(path.clone()))
+                .unwrap();
+            let embeddings = engine.embed_batch(inputs.clone()).unwrap();
+            assert_eq!(embeddings.len(), 2);
+            assert!(embeddings.iter().all(|e| e.len() == dimensions));
+            // Activations after the ReLU
+            assert!(embeddings.iter().flatten().all(|x| *x >= 0.0));
+            assert_ne!(embeddings[0], embeddings[1]);
+
+            let quantized = InferenceEngine::builder()
+                .model_architecture(arch)
+                .quantization(Quantization::Q8_0)
+                .build(LocalFileProvider::new(path))
+                .unwrap()
+                .embed_batch(inputs)
+                .unwrap();
+            for (a, b) in embeddings[0].iter().zip(&quantized[0]) {
+                assert!((a - b).abs() < 0.05, "{} vs {}", a, b);
+            }
+            assert!(engine.embed_batch(Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_out_of_distribution() {
+        let (_dir, path) = random_weights(ModelArchitecture::Conv);
+        let provider = LocalFileProvider::new(path);
+        let build = || {
+            InferenceEngine::builder()
+                .model_architecture(ModelArchitecture::Conv)
+                .build(provider.clone())
+                .unwrap()
+        };
+        let training: Vec<Vec<f32>> = (1..=3).map(|i| vec![i as f32 / 10.0; INPUT_SIZE]).collect();
+        let unseen = vec![0.9; INPUT_SIZE];
+        let engine = build();
+        let prediction = engine.predict(unseen.clone()).unwrap();
+        assert_eq!(prediction.ood_score, None);
+        assert!(!prediction.out_of_distribution);
+
+        let manifest = ModelManifest {
+            ood: Some(OodConfig {
+                method: ood::OodMethod::Energy,
+                threshold: Some(f32::MAX),
+                statistics: None,
+            }),
+            ..Default::default()
+        };
+        manifest.save(&provider.manifest_path()).unwrap();
+        let prediction = build().predict(unseen.clone()).unwrap();
+        let max = prediction.logits.iter().copied().fold(f32::MIN, f32::max);
+        let energy = -(max
+            + prediction
+                .logits
+                .iter()
+                .map(|l| (l - max).exp())
+                .sum::<f32>()
+                .ln());
+        assert!((prediction.ood_score.unwrap() - energy).abs() < 1e-5);
+        assert!(!prediction.out_of_distribution);
+
+        // Every training image is its own class, so only unseen features are far off
+        let features = engine.embed_batch(training.clone()).unwrap();
+        let statistics = ood::FeatureStatistics::fit(&features, &[0, 1, 2]).unwrap();
+        let manifest = ModelManifest {
+            ood: Some(OodConfig {
+                method: ood::OodMethod::Mahalanobis,
+                threshold: Some(1.0),
+                statistics: Some(statistics),
+            }),
+            ..Default::default()
+        };
+        manifest.save(&provider.manifest_path()).unwrap();
+        let engine = build();
+        let mut inputs = training;
+        inputs.push(unseen);
+        let predictions = engine
+            .predict_batch(inputs.clone(), &PredictOptions::default())
+            .unwrap();
+        let flagged: Vec<bool> = predictions.iter().map(|p| p.out_of_distribution).collect();
+        assert_eq!(flagged, [false, false, false, true]);
+        assert!(predictions[0].ood_score.unwrap() < 1e-3);
+        // The features come from the same forward pass as the logits
+        let logits = engine.logits_batch(inputs.clone()).unwrap();
+        for (prediction, logits) in predictions.iter().zip(logits) {
+            assert_eq!(prediction.logits, logits);
+        }
+
+        // Augmented predictions are scored on the features of the image as sent
+        let options = PredictOptions {
+            test_time_augmentation: Some(true),
+            ..Default::default()
+        };
+        let augmented = engine.predict_batch(inputs, &options).unwrap();
+        for (augmented, prediction) in augmented.iter().zip(&predictions) {
+            assert_eq!(augmented.views, 9);
+            assert_eq!(augmented.ood_score, prediction.ood_score);
+        }
+    }
+
+    #[test]
+    fn test_architecture_from_manifest() {
+        let (_dir, path) = random_weights(ModelArchitecture::Conv);
+        std::fs::write(
+            path.with_file_name("model.manifest.json"),
+            r#"{"architecture": "conv", "labels": ["0","1","2","3","4","5","6","7","8","9"]}"#,
+        )
+        .unwrap();
+
+        let engine = InferenceEngine::builder()
+            .build(LocalFileProvider::new(path.clone()))
+            .unwrap();
+        assert_eq!(engine.architecture(), ModelArchitecture::Conv);
+        let prediction = engine.predict(vec![0.5; INPUT_SIZE]).unwrap();
+        assert_eq!(prediction.label, Some(prediction.digit.to_string()));
+
+        // Conflicting architecture on the builder is rejected
+        let result = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::MLP)
+            .build(LocalFileProvider::new(path));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_calibrated_temperature() {
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        let provider = LocalFileProvider::new(path.clone());
+        let engine = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::MLP)
+            .build(provider.clone())
+            .unwrap();
+        let inputs = vec![vec![0.5; INPUT_SIZE], vec![0.1; INPUT_SIZE]];
+        let raw = engine
+            .predict_batch(inputs.clone(), &PredictOptions::default())
+            .unwrap();
+        assert_eq!(raw.len(), 2);
+
+        let manifest = ModelManifest {
+            temperature: Some(1000.0),
+            ..Default::default()
+        };
+        manifest.save(&provider.manifest_path()).unwrap();
+        let engine = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::MLP)
+            .build(provider)
+            .unwrap();
+        let calibrated = engine
+            .predict_batch(inputs, &PredictOptions::default())
+            .unwrap();
+        for (raw, calibrated) in raw.iter().zip(&calibrated) {
+            // A large temperature flattens the distribution without changing the prediction
+            assert_eq!(raw.digit, calibrated.digit);
+            assert_eq!(raw.logits, calibrated.logits);
+            assert!(
+                calibrated
+                    .probabilities
+                    .iter()
+                    .all(|p| (p - 0.1).abs() < 1e-3)
+            );
+        }
+    }
+
+    #[test]
+    fn test_ensemble() {
+        let (_mlp_dir, mlp) = random_weights(ModelArchitecture::MLP);
+        let (_conv_dir, conv) = random_weights(ModelArchitecture::Conv);
+        let path = mlp.with_file_name("ensemble.toml");
+        std::fs::write(
+            &path,
+            format!(
+                r#"
+                name = "digits"
+                strategy = "weighted_mean"
+
+                [[members]]
+                name = "mlp"
+                weights = "model.safetensors"
+                architecture = "mlp"
+
+                [[members]]
+                weights = "{}"
+                architecture = "conv"
+                weight = 3.0
+                "#,
+                conv.display()
+            ),
+        )
+        .unwrap();
+
+        let engine = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::Ensemble)
+            .build(LocalFileProvider::new(path))
+            .unwrap();
+        assert_eq!(engine.name(), Some("digits"));
+
+        let options = PredictOptions {
+            include_members: true,
+            ..Default::default()
+        };
+        let prediction = engine
+            .predict_with(vec![0.5; INPUT_SIZE], &options)
+            .unwrap();
+        assert_eq!(prediction.members.len(), 2);
+        assert_eq!(prediction.members[0].name, "mlp");
+        assert_eq!(prediction.members[1].name, "model.safetensors");
+        let total: f32 = prediction.probabilities.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4);
+        for class in 0..10 {
+            let expected = (prediction.members[0].probabilities[class]
+                + 3.0 * prediction.members[1].probabilities[class])
+                / 4.0;
+            assert!((prediction.probabilities[class] - expected).abs() < 1e-4);
+        }
+
+        let prediction = engine.predict(vec![0.5; INPUT_SIZE]).unwrap();
+        assert!(prediction.members.is_empty());
+    }
+
+    #[test]
+    fn test_ensemble_member_integrity() {
+        use integrity::IntegrityPolicy;
+        let sha256 = |path: &std::path::Path| {
+            let digest = <sha2::Sha256 as sha2::Digest>::digest(std::fs::read(path).unwrap());
+            hex::encode(digest)
+        };
+        let (_member_dir, member) = random_weights(ModelArchitecture::MLP);
+        let path = member.with_file_name("ensemble.toml");
+        let ensemble = |digest: Option<String>| {
+            let pinned = digest.map_or(String::new(), |digest| format!("sha256 = \"{}\"", digest));
+            std::fs::write(
+                &path,
+                format!(
+                    "[[members]]\nweights = \"model.safetensors\"\narchitecture = \"mlp\"\n{}",
+                    pinned
+                ),
+            )
+            .unwrap();
+            std::fs::write(format!("{}.sha256", path.display()), sha256(&path)).unwrap();
+            LocalFileProvider::new(path.clone())
+                .with_integrity(IntegrityPolicy::new().with_sha256_sidecar(true))
+        };
+        let build = |provider| {
+            InferenceEngine::builder()
+                .model_architecture(ModelArchitecture::Ensemble)
+                .build(provider)
+        };
+
+        // The sidecar check of the ensemble file applies to its members
+        assert!(build(ensemble(None)).is_err());
+        std::fs::write(format!("{}.sha256", member.display()), sha256(&member)).unwrap();
+        assert!(build(ensemble(None)).is_ok());
+
+        // Under a digest-only policy the members must be pinned by the ensemble file
+        let digest_only = || {
+            LocalFileProvider::new(path.clone())
+                .with_integrity(IntegrityPolicy::new().with_sha256(sha256(&path)))
+        };
+        ensemble(Some(sha256(&member)));
+        assert!(build(digest_only()).is_ok());
+        ensemble(None);
+        assert!(build(digest_only()).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_weights() {
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        let result = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::Conv)
+            .build(LocalFileProvider::new(path));
+        let error = result.unwrap_err();
+        assert!(matches!(&error, Error::InvalidWeights(report) if report.missing.len() == 8));
+        assert!(error.to_string().contains("conv2d_1.weight"), "{}", error);
+    }
+
+    #[test]
+    fn test_sequential_model_from_manifest() {
+        let manifest = r#"
+            architecture = "sequential"
+            input_shape = [1, 28, 28]
+
+            [[layers]]
+            type = "conv2d"
+            name = "features"
+            in_channels = 1
+            out_channels = 4
+            kernel_size = 3
+            padding = 1
+
+            [[layers]]
+            type = "batch_norm"
+            num_features = 4
+
+            [[layers]]
+            type = "relu"
+
+            [[layers]]
+            type = "maxpool2d"
+            kernel_size = 2
+
+            [[layers]]
+            type = "dropout"
+            p = 0.25
+
+            [[layers]]
+            type = "flatten"
+
+            [[layers]]
+            type = "linear"
+            in_features = 784
+            out_features = 10
+
+            [[layers]]
+            type = "softmax"
+        "#;
+        let spec = ModelSpec::from_toml(manifest).unwrap();
+        assert_eq!(spec.layers.len(), 8);
+
+        let (_dir, path) = random_spec_weights(ModelArchitecture::Sequential, Some(&spec));
+        std::fs::write(path.with_file_name("model.manifest.toml"), manifest).unwrap();
+
+        let engine = InferenceEngine::builder()
+            .build(LocalFileProvider::new(path.clone()))
+            .unwrap();
+        assert_eq!(engine.architecture(), ModelArchitecture::Sequential);
+        let prediction = engine.predict(vec![0.5; INPUT_SIZE]).unwrap();
+        assert_eq!(prediction.probabilities.len(), 10);
+        // Features are the flattened input of the last linear layer
+        let embeddings = engine.embed_batch(vec![vec![0.5; INPUT_SIZE]]).unwrap();
+        assert_eq!(embeddings[0].len(), 784);
+
+        let shapes =
+            validation::expected_tensors(ModelArchitecture::Sequential, Some(&spec)).unwrap();
+        assert_eq!(shapes["features.weight"], vec![4, 1, 3, 3]);
+        assert_eq!(shapes["1.running_var"], vec![4]);
+        assert_eq!(shapes["6.weight"], vec![10, 784]);
+    }
+
+    #[test]
+    fn test_quantized_model() {
+        for arch in [ModelArchitecture::MLP, ModelArchitecture::Conv] {
+            let (_dir, path) = random_weights(arch);
+            let input = vec![0.5; INPUT_SIZE];
+            let reference = InferenceEngine::builder()
+                .model_architecture(arch)
+                .build(LocalFileProvider::new(path.clone()))
+                .unwrap()
+                .predict(input.clone())
+                .unwrap();
+
+            let engine = InferenceEngine::builder()
+                .model_architecture(arch)
+                .quantization(Quantization::Q8_0)
+                .build(LocalFileProvider::new(path.clone()))
+                .unwrap();
+            let quantized = engine.predict(input.clone()).unwrap();
+            for (a, b) in reference.logits.iter().zip(&quantized.logits) {
+                assert!((a - b).abs() < 0.05, "{} vs {}", a, b);
+            }
+
+            // Served from a GGUF file, which keeps the padded fc1/linear_1 matrices
+            let tensors = LocalFileProvider::new(path.clone())
+                .load_weights()
+                .unwrap()
+                .tensors(&Device::Cpu)
+                .unwrap();
+            let weights =
+                QuantizedWeights::quantize(&tensors, Quantization::Q4_0, &Device::Cpu).unwrap();
+            let gguf = path.with_file_name("model.gguf");
+            weights
+                .write_gguf(&mut std::fs::File::create(&gguf).unwrap(), &[])
+                .unwrap();
+            let builder = InferenceEngine::builder().model_architecture(arch);
+            let report = builder
+                .validate(&LocalFileProvider::new(gguf.clone()))
+                .unwrap();
+            assert!(report.is_exact());
+            let engine = builder.build(LocalFileProvider::new(gguf.clone())).unwrap();
+            let prediction = engine.predict(input).unwrap();
+            assert_eq!(prediction.logits.len(), 10);
+
+            // The GGUF file of the other architecture is reported, and refused by the model
+            let other = match arch {
+                ModelArchitecture::MLP => ModelArchitecture::Conv,
+                _ => ModelArchitecture::MLP,
+            };
+            let builder = InferenceEngine::builder().model_architecture(other);
+            let report = builder
+                .validate(&LocalFileProvider::new(gguf.clone()))
+                .unwrap();
+            assert!(!report.is_valid());
+            assert!(builder.build(LocalFileProvider::new(gguf)).is_err());
         }
+
+        // A matrix with the right number of rows but too many columns is refused
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        let mut tensors = LocalFileProvider::new(path.clone())
+            .load_weights()
+            .unwrap()
+            .tensors(&Device::Cpu)
+            .unwrap();
+        tensors.insert(
+            "fc2.weight".to_string(),
+            Tensor::zeros((64, 256), DType::F32, &Device::Cpu).unwrap(),
+        );
+        let gguf = path.with_file_name("model.gguf");
+        QuantizedWeights::quantize(&tensors, Quantization::Q8_0, &Device::Cpu)
+            .unwrap()
+            .write_gguf(&mut std::fs::File::create(&gguf).unwrap(), &[])
+            .unwrap();
+        let builder = InferenceEngine::builder().model_architecture(ModelArchitecture::MLP);
+        let report = builder
+            .validate(&LocalFileProvider::new(gguf.clone()))
+            .unwrap();
+        assert_eq!(report.mismatched[0].actual, vec![64, 256]);
+        assert!(builder.build(LocalFileProvider::new(gguf)).is_err());
+
+        let (_mlp_dir, mlp) = random_weights(ModelArchitecture::MLP);
+        let result = InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::Ensemble)
+            .quantization(Quantization::Q8_0)
+            .build(LocalFileProvider::new(mlp));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_onnx_model() {
+        let dir = TempDir::new("engine");
+        let path = dir.join("model.onnx");
+        std::fs::write(&path, onnx::tests::linear_onnx(&[784], &[10])).unwrap();
+
+        let builder = InferenceEngine::builder().model_architecture(ModelArchitecture::Onnx);
+        let report = builder
+            .validate(&LocalFileProvider::new(path.clone()))
+            .unwrap();
+        assert!(report.is_exact());
+
+        let engine = builder.build(LocalFileProvider::new(path)).unwrap();
+        assert_eq!(engine.architecture(), ModelArchitecture::Onnx);
+        let prediction = engine.predict(vec![0.5; INPUT_SIZE]).unwrap();
+        assert_eq!(prediction.probabilities.len(), 10);
+        let total: f32 = prediction.probabilities.iter().sum();
+        assert!((total - 1.0).abs() < 1e-4);
+        assert!(engine.embed_batch(vec![vec![0.5; INPUT_SIZE]]).is_err());
     }
 }
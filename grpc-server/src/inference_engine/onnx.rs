@@ -0,0 +1,302 @@
+use std::collections::{HashMap, HashSet};
+
+use candle_core::{Device, Tensor};
+use candle_onnx::onnx::tensor_proto::DataType;
+use candle_onnx::onnx::tensor_shape_proto::dimension::Value as Dimension;
+use candle_onnx::onnx::type_proto::Value as TypeValue;
+use candle_onnx::onnx::{ModelProto, ValueInfoProto};
+use onnx_prost::Message;
+
+use super::INPUT_SIZE;
+use super::weights_provider::WeightsSource;
+use crate::{Error, Result};
+
+/// Number of classes the model output must have
+const NUM_CLASSES: usize = 10;
+
+/// Model exported to ONNX, evaluated with candle-onnx
+///
+/// The graph must take a single float tensor holding one 28x28 image per batch entry,
+/// e.g. `[N, 1, 28, 28]` or `[N, 784]`, and return the scores of the 10 digits as `[N, 10]`.
+/// The batch dimension may be symbolic or fixed to 1.
+#[derive(Debug)]
+pub struct OnnxModel {
+    proto: ModelProto,
+    input: String,
+    output: String,
+    input_shape: Vec<usize>,
+    /// Whether the graph ends with a softmax and outputs probabilities instead of logits
+    softmax: bool,
+    /// Whether the graph accepts any batch size, otherwise images are evaluated one by one
+    dynamic_batch: bool,
+}
+
+impl OnnxModel {
+    /// Decode an ONNX model and check it against the MNIST input/output contract
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let proto = ModelProto::decode(data)
+            .map_err(|e| Error::custom(format!("Invalid ONNX model: {}", e)))?;
+        Self::new(proto)
+    }
+
+    /// Read the ONNX model handed out by a weights provider
+    pub fn from_source(source: WeightsSource) -> Result<Self> {
+        match source.single_file() {
+            Some((data, _)) => Self::from_bytes(data),
+            None => Err(Error::custom("ONNX models cannot be sharded")),
+        }
+    }
+
+    pub fn new(proto: ModelProto) -> Result<Self> {
+        let graph = proto
+            .graph
+            .as_ref()
+            .ok_or_else(|| Error::custom("ONNX model has no graph"))?;
+
+        // Some exporters also list the initializers as graph inputs
+        let initializers: HashSet<&str> =
+            graph.initializer.iter().map(|t| t.name.as_str()).collect();
+        let inputs: Vec<&ValueInfoProto> = graph
+            .input
+            .iter()
+            .filter(|input| !initializers.contains(input.name.as_str()))
+            .collect();
+        let [input] = inputs.as_slice() else {
+            return Err(Error::custom(format!(
+                "ONNX model must have exactly one input, found {}",
+                inputs.len()
+            )));
+        };
+        let [output] = graph.output.as_slice() else {
+            return Err(Error::custom(format!(
+                "ONNX model must have exactly one output, found {}",
+                graph.output.len()
+            )));
+        };
+
+        let input_shape = sample_shape(input)?;
+        if input_shape.iter().product::<usize>() != INPUT_SIZE {
+            return Err(Error::custom(format!(
+                "ONNX input {} has shape {:?}, which does not hold a 28x28 image",
+                input.name, input_shape
+            )));
+        }
+        let output_shape = sample_shape(output)?;
+        if output_shape != [NUM_CLASSES] {
+            return Err(Error::custom(format!(
+                "ONNX output {} has shape {:?}, expected [{}]",
+                output.name, output_shape, NUM_CLASSES
+            )));
+        }
+
+        let softmax = graph
+            .node
+            .iter()
+            .any(|node| node.op_type == "Softmax" && node.output.contains(&output.name));
+
+        Ok(Self {
+            input: input.name.clone(),
+            output: output.name.clone(),
+            input_shape,
+            softmax,
+            dynamic_batch: !matches!(batch_dim(input), Some(Dimension::DimValue(_))),
+            proto,
+        })
+    }
+
+    /// Shape of a single input sample, excluding the batch dimension
+    pub fn input_shape(&self) -> &[usize] {
+        &self.input_shape
+    }
+
+    /// Returns the logits of the model
+    ///
+    /// Probabilities from a trailing softmax are turned back into log-probabilities, which
+    /// differ from the original logits only by a constant and post-process identically.
+    pub fn forward(&self, input: &Tensor) -> Result<Tensor> {
+        let batch_size = input.dim(0)?;
+        if !self.dynamic_batch && batch_size > 1 {
+            let outputs = (0..batch_size)
+                .map(|i| self.forward(&input.narrow(0, i, 1)?))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Tensor::cat(&outputs, 0)?);
+        }
+
+        // candle-onnx keeps the initializers on the CPU, so the graph is evaluated there
+        let input = input.to_device(&Device::Cpu)?;
+        let inputs = HashMap::from([(self.input.clone(), input)]);
+        let mut outputs = candle_onnx::simple_eval(&self.proto, inputs)?;
+        let output = outputs
+            .remove(&self.output)
+            .ok_or_else(|| Error::custom(format!("ONNX model did not produce {}", self.output)))?;
+        if self.softmax {
+            Ok(output.log()?)
+        } else {
+            Ok(output)
+        }
+    }
+}
+
+/// Leading dimension of a graph input or output
+fn batch_dim(value: &ValueInfoProto) -> Option<&Dimension> {
+    match value.r#type.as_ref()?.value.as_ref()? {
+        TypeValue::TensorType(tensor_type) => {
+            tensor_type.shape.as_ref()?.dim.first()?.value.as_ref()
+        }
+        _ => None,
+    }
+}
+
+/// Static shape of a float graph input or output without its leading batch dimension
+fn sample_shape(value: &ValueInfoProto) -> Result<Vec<usize>> {
+    let tensor_type = match value.r#type.as_ref().and_then(|t| t.value.as_ref()) {
+        Some(TypeValue::TensorType(tensor_type)) => tensor_type,
+        _ => {
+            return Err(Error::custom(format!(
+                "ONNX value {} is not a tensor",
+                value.name
+            )));
+        }
+    };
+    if tensor_type.elem_type != DataType::Float as i32 {
+        return Err(Error::custom(format!(
+            "ONNX value {} must be float32",
+            value.name
+        )));
+    }
+    let dims = tensor_type
+        .shape
+        .as_ref()
+        .map(|shape| shape.dim.as_slice())
+        .unwrap_or_default();
+    let Some((batch, dims)) = dims.split_first() else {
+        return Err(Error::custom(format!(
+            "ONNX value {} must have a batch dimension",
+            value.name
+        )));
+    };
+    if let Some(Dimension::DimValue(size)) = batch.value
+        && size != 1
+    {
+        return Err(Error::custom(format!(
+            "ONNX value {} has a fixed batch size of {}",
+            value.name, size
+        )));
+    }
+    dims.iter()
+        .map(|dim| match dim.value {
+            Some(Dimension::DimValue(size)) if size > 0 => Ok(size as usize),
+            _ => Err(Error::custom(format!(
+                "ONNX value {} must have a static shape apart from the batch dimension",
+                value.name
+            ))),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use candle_onnx::onnx::{
+        AttributeProto, GraphProto, NodeProto, TensorProto, TensorShapeProto, TypeProto,
+        attribute_proto::AttributeType, tensor_shape_proto, type_proto,
+    };
+
+    fn value_info(name: &str, dims: &[i64]) -> ValueInfoProto {
+        let dim = std::iter::once(Dimension::DimParam("batch".to_string()))
+            .chain(dims.iter().map(|&size| Dimension::DimValue(size)))
+            .map(|value| tensor_shape_proto::Dimension {
+                value: Some(value),
+                ..Default::default()
+            })
+            .collect();
+        ValueInfoProto {
+            name: name.to_string(),
+            r#type: Some(TypeProto {
+                value: Some(TypeValue::TensorType(type_proto::Tensor {
+                    elem_type: DataType::Float as i32,
+                    shape: Some(TensorShapeProto { dim }),
+                })),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn initializer(name: &str, dims: &[i64], value: f32) -> TensorProto {
+        TensorProto {
+            name: name.to_string(),
+            dims: dims.to_vec(),
+            data_type: DataType::Float as i32,
+            float_data: vec![value; dims.iter().product::<i64>() as usize],
+            ..Default::default()
+        }
+    }
+
+    /// Encode a linear classifier `Gemm(input, weight^T) + bias` over `input_dims`
+    pub(crate) fn linear_onnx(input_dims: &[i64], output_dims: &[i64]) -> Vec<u8> {
+        let features: i64 = input_dims.iter().product();
+        let mut nodes = Vec::new();
+        let mut gemm_input = "input".to_string();
+        if input_dims.len() > 1 {
+            nodes.push(NodeProto {
+                op_type: "Flatten".to_string(),
+                input: vec!["input".to_string()],
+                output: vec!["flat".to_string()],
+                ..Default::default()
+            });
+            gemm_input = "flat".to_string();
+        }
+        nodes.push(NodeProto {
+            op_type: "Gemm".to_string(),
+            input: vec![gemm_input, "weight".to_string(), "bias".to_string()],
+            output: vec!["logits".to_string()],
+            attribute: vec![AttributeProto {
+                name: "transB".to_string(),
+                r#type: AttributeType::Int as i32,
+                i: 1,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let proto = ModelProto {
+            graph: Some(GraphProto {
+                node: nodes,
+                initializer: vec![
+                    initializer("weight", &[10, features], 0.01),
+                    initializer("bias", &[10], 0.0),
+                ],
+                input: vec![value_info("input", input_dims)],
+                output: vec![value_info("logits", output_dims)],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        proto.encode_to_vec()
+    }
+
+    #[test]
+    fn test_contract() {
+        let model = OnnxModel::from_bytes(&linear_onnx(&[1, 28, 28], &[10])).unwrap();
+        assert_eq!(model.input_shape(), &[1, 28, 28]);
+        assert!(!model.softmax);
+
+        let input = Tensor::ones((1, 1, 28, 28), candle_core::DType::F32, &Device::Cpu).unwrap();
+        let output = model.forward(&input).unwrap();
+        assert_eq!(output.dims(), &[1, 10]);
+        // 784 inputs of 1.0 times the 0.01 weights
+        let logits: Vec<f32> = output.flatten_all().unwrap().to_vec1().unwrap();
+        assert!(logits.iter().all(|logit| (logit - 7.84).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_contract_violations() {
+        let error = OnnxModel::from_bytes(&linear_onnx(&[32, 32], &[10])).unwrap_err();
+        assert!(error.to_string().contains("28x28"), "{}", error);
+
+        let error = OnnxModel::from_bytes(&linear_onnx(&[784], &[5])).unwrap_err();
+        assert!(error.to_string().contains("expected [10]"), "{}", error);
+
+        assert!(OnnxModel::from_bytes(b"not an onnx model").is_err());
+    }
+}
@@ -0,0 +1,378 @@
+//! Out-of-distribution detection
+//!
+//! A classifier always answers with one of its classes, so a photo that is not a digit at
+//! all still gets a label, often a confident one. The OOD score measures how unlike the
+//! training data an input is, and inputs scoring above the threshold of the model are
+//! flagged. Scores are computed from the raw logits and features, so neither the
+//! calibrated temperature nor the post-processing of a request moves them.
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Ridge added to the diagonal of the covariance, relative to the average variance
+///
+/// Features of ReLU networks have dead units with no variance at all, so the covariance
+/// is singular without it.
+const RIDGE: f64 = 1e-3;
+
+/// How the out-of-distribution score is computed, higher is always less like the training data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OodMethod {
+    /// One minus the largest softmax probability
+    #[default]
+    MaxSoftmax,
+    /// Negative log-sum-exp of the logits, the free energy of the input
+    Energy,
+    /// Squared Mahalanobis distance of the penultimate features to the closest class
+    /// mean, needs statistics fitted on training data
+    Mahalanobis,
+}
+
+/// Out-of-distribution detection of a model, the `ood` section of the manifest
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OodConfig {
+    pub method: OodMethod,
+    /// Inputs scoring above are flagged, scores are only reported if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<f32>,
+    /// Feature statistics of the training data, required by the Mahalanobis method
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub statistics: Option<FeatureStatistics>,
+}
+
+impl OodConfig {
+    /// Check that the threshold is finite and the method has the statistics it needs
+    pub fn validate(&self) -> Result<()> {
+        if let Some(threshold) = self.threshold
+            && !threshold.is_finite()
+        {
+            return Err(Error::custom(format!(
+                "OOD threshold must be finite, got {}",
+                threshold
+            )));
+        }
+        match &self.statistics {
+            Some(statistics) => statistics.validate(),
+            None if self.method == OodMethod::Mahalanobis => Err(Error::custom(
+                "The mahalanobis OOD method needs feature statistics, fit them with `fit-ood`",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether scoring needs the penultimate features next to the logits
+    pub fn needs_features(&self) -> bool {
+        self.method == OodMethod::Mahalanobis
+    }
+
+    /// Score of an input from its logits, and its features for the Mahalanobis method
+    pub fn score(&self, logits: &[f32], features: Option<&[f32]>) -> Result<f32> {
+        match self.method {
+            OodMethod::MaxSoftmax => {
+                let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+                Ok(1.0 - 1.0 / logits.iter().map(|l| (l - max).exp()).sum::<f32>())
+            }
+            OodMethod::Energy => Ok(-log_sum_exp(logits)),
+            OodMethod::Mahalanobis => {
+                let statistics = self.statistics.as_ref().ok_or_else(|| {
+                    Error::custom("The mahalanobis OOD method needs feature statistics")
+                })?;
+                let features = features.ok_or_else(|| {
+                    Error::custom("The mahalanobis OOD method needs the features of the input")
+                })?;
+                statistics.distance(features)
+            }
+        }
+    }
+
+    /// Whether a score is above the threshold
+    pub fn flags(&self, score: f32) -> bool {
+        self.threshold.is_some_and(|threshold| score > threshold)
+    }
+}
+
+fn log_sum_exp(values: &[f32]) -> f32 {
+    let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    max + values.iter().map(|v| (v - max).exp()).sum::<f32>().ln()
+}
+
+/// Gaussian of the penultimate features per class, with one covariance shared by all
+/// classes
+///
+/// Stored in the manifest, so the precision matrix makes it grow with the square of the
+/// feature size: about 300KB of JSON for the 128 features of the ConvNet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureStatistics {
+    /// Mean features of every class seen in the training data
+    pub means: Vec<Vec<f32>>,
+    /// Inverse of the shared covariance matrix, one row per feature
+    pub precision: Vec<Vec<f32>>,
+}
+
+impl FeatureStatistics {
+    /// Fit the class means and shared covariance of labeled features
+    pub fn fit(features: &[Vec<f32>], labels: &[u32]) -> Result<Self> {
+        if features.is_empty() || features.len() != labels.len() {
+            return Err(Error::custom(format!(
+                "Expected features for every label, got {} features and {} labels",
+                features.len(),
+                labels.len()
+            )));
+        }
+        let dimensions = features[0].len();
+        if features.iter().any(|f| f.len() != dimensions) {
+            return Err(Error::custom("Features differ in size"));
+        }
+
+        let classes = labels.iter().max().map_or(0, |&max| max as usize + 1);
+        let mut sums = vec![vec![0.0f64; dimensions]; classes];
+        let mut counts = vec![0usize; classes];
+        for (features, &label) in features.iter().zip(labels) {
+            counts[label as usize] += 1;
+            for (sum, &value) in sums[label as usize].iter_mut().zip(features) {
+                *sum += value as f64;
+            }
+        }
+        let means: Vec<Vec<f64>> = sums
+            .into_iter()
+            .zip(&counts)
+            .map(|(sum, &count)| sum.into_iter().map(|s| s / count.max(1) as f64).collect())
+            .collect();
+
+        let mut covariance = vec![vec![0.0f64; dimensions]; dimensions];
+        for (features, &label) in features.iter().zip(labels) {
+            let centered: Vec<f64> = features
+                .iter()
+                .zip(&means[label as usize])
+                .map(|(&value, mean)| value as f64 - mean)
+                .collect();
+            for (row, &a) in covariance.iter_mut().zip(&centered) {
+                for (cell, &b) in row.iter_mut().zip(&centered) {
+                    *cell += a * b;
+                }
+            }
+        }
+        let trace: f64 = (0..dimensions).map(|i| covariance[i][i]).sum();
+        let ridge = (RIDGE * trace / (features.len() * dimensions) as f64).max(1e-6);
+        for (i, row) in covariance.iter_mut().enumerate() {
+            for cell in row.iter_mut() {
+                *cell /= features.len() as f64;
+            }
+            row[i] += ridge;
+        }
+
+        let precision = invert(covariance)?;
+        Ok(Self {
+            means: means
+                .into_iter()
+                .zip(counts)
+                .filter(|(_, count)| *count > 0)
+                .map(|(mean, _)| mean.into_iter().map(|m| m as f32).collect())
+                .collect(),
+            precision: precision
+                .into_iter()
+                .map(|row| row.into_iter().map(|p| p as f32).collect())
+                .collect(),
+        })
+    }
+
+    /// Number of features the statistics were fitted on
+    pub fn dimensions(&self) -> usize {
+        self.precision.len()
+    }
+
+    fn validate(&self) -> Result<()> {
+        let dimensions = self.dimensions();
+        if self.means.is_empty() || dimensions == 0 {
+            return Err(Error::custom("OOD feature statistics are empty"));
+        }
+        if self.precision.iter().any(|row| row.len() != dimensions)
+            || self.means.iter().any(|mean| mean.len() != dimensions)
+        {
+            return Err(Error::custom(format!(
+                "OOD feature statistics must have {0} values per mean and a {0}x{0} precision",
+                dimensions
+            )));
+        }
+        Ok(())
+    }
+
+    /// Smallest squared Mahalanobis distance of `features` to a class mean
+    pub fn distance(&self, features: &[f32]) -> Result<f32> {
+        if features.len() != self.dimensions() {
+            return Err(Error::custom(format!(
+                "The model has {} features, the OOD statistics were fitted on {}",
+                features.len(),
+                self.dimensions()
+            )));
+        }
+        let distance = self
+            .means
+            .iter()
+            .map(|mean| {
+                let centered: Vec<f32> = features.iter().zip(mean).map(|(f, m)| f - m).collect();
+                self.precision
+                    .iter()
+                    .zip(&centered)
+                    .map(|(row, a)| a * row.iter().zip(&centered).map(|(p, b)| p * b).sum::<f32>())
+                    .sum::<f32>()
+            })
+            .fold(f32::INFINITY, f32::min);
+        Ok(distance)
+    }
+}
+
+/// Invert a symmetric positive definite matrix by Gauss-Jordan elimination
+fn invert(mut matrix: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>> {
+    let n = matrix.len();
+    let mut inverse: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect())
+        .collect();
+    for column in 0..n {
+        let pivot = (column..n)
+            .max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))
+            .unwrap_or(column);
+        if matrix[pivot][column].abs() < f64::EPSILON {
+            return Err(Error::custom("Feature covariance is singular"));
+        }
+        matrix.swap(column, pivot);
+        inverse.swap(column, pivot);
+
+        let scale = 1.0 / matrix[column][column];
+        matrix[column].iter_mut().for_each(|v| *v *= scale);
+        inverse[column].iter_mut().for_each(|v| *v *= scale);
+        for row in 0..n {
+            let factor = matrix[row][column];
+            if row == column || factor == 0.0 {
+                continue;
+            }
+            for j in 0..n {
+                matrix[row][j] -= factor * matrix[column][j];
+                inverse[row][j] -= factor * inverse[column][j];
+            }
+        }
+    }
+    Ok(inverse)
+}
+
+/// Score below which a `quantile` of the scores fall, e.g. 0.95 flags 5% of them
+pub fn quantile_threshold(scores: &[f32], quantile: f32) -> Result<f32> {
+    if scores.is_empty() {
+        return Err(Error::custom("No scores to pick a threshold from"));
+    }
+    if !(0.0..=1.0).contains(&quantile) {
+        return Err(Error::custom(format!(
+            "Quantile must be between 0 and 1, got {}",
+            quantile
+        )));
+    }
+    let mut sorted = scores.to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let index = ((quantile * sorted.len() as f32).ceil() as usize).clamp(1, sorted.len()) - 1;
+    Ok(sorted[index])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_logit_scores() {
+        let config = OodConfig::default();
+        let confident = config.score(&[10.0, 0.0, 0.0], None).unwrap();
+        let uniform = config.score(&[1.0, 1.0, 1.0], None).unwrap();
+        assert!(confident < 1e-3);
+        assert!((uniform - 2.0 / 3.0).abs() < 1e-6);
+
+        let config = OodConfig {
+            method: OodMethod::Energy,
+            threshold: Some(-5.0),
+            statistics: None,
+        };
+        let energy = config.score(&[10.0, 0.0, 0.0], None).unwrap();
+        assert!((energy + 10.0).abs() < 1e-3);
+        assert!(!config.flags(energy));
+        let energy = config.score(&[0.0, 0.0, 0.0], None).unwrap();
+        assert!((energy + 3f32.ln()).abs() < 1e-6);
+        assert!(config.flags(energy));
+    }
+
+    #[test]
+    fn test_mahalanobis() {
+        // Two classes spread along the first feature, tight along the second
+        let features: Vec<Vec<f32>> = (0..40)
+            .map(|i| {
+                let class = (i % 2) as f32;
+                let spread = (i / 2) as f32 / 10.0 - 1.0;
+                vec![class * 10.0 + spread, class * 10.0 + spread * 0.01]
+            })
+            .collect();
+        let labels: Vec<u32> = (0..40).map(|i| i % 2).collect();
+        let statistics = FeatureStatistics::fit(&features, &labels).unwrap();
+        assert_eq!(statistics.dimensions(), 2);
+        assert_eq!(statistics.means.len(), 2);
+        assert!((statistics.means[1][0] - 9.95).abs() < 1e-4);
+
+        // The same offset is typical along the first feature but not along the second
+        let along = statistics.distance(&[11.0, 10.0]).unwrap();
+        let across = statistics.distance(&[10.0, 11.0]).unwrap();
+        assert!(along < 10.0, "{}", along);
+        assert!(across > 100.0 * along, "{} vs {}", across, along);
+        assert!(statistics.distance(&[1.0]).is_err());
+
+        let config = OodConfig {
+            method: OodMethod::Mahalanobis,
+            threshold: Some(10.0),
+            statistics: Some(statistics),
+        };
+        assert!(config.validate().is_ok());
+        assert!(config.score(&[0.0; 2], None).is_err());
+        let score = config.score(&[0.0; 2], Some(&[10.0, 11.0])).unwrap();
+        assert!(config.flags(score));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(OodConfig::default().validate().is_ok());
+        let config: OodConfig = toml::from_str("method = \"mahalanobis\"").unwrap();
+        assert!(config.validate().is_err());
+        let config = OodConfig {
+            threshold: Some(f32::NAN),
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        let config = OodConfig {
+            method: OodMethod::Mahalanobis,
+            threshold: None,
+            statistics: Some(FeatureStatistics {
+                means: vec![vec![0.0; 3]],
+                precision: vec![vec![1.0; 2]; 2],
+            }),
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_invert() {
+        let inverse = invert(vec![vec![4.0, 1.0], vec![1.0, 3.0]]).unwrap();
+        let expected = [[3.0 / 11.0, -1.0 / 11.0], [-1.0 / 11.0, 4.0 / 11.0]];
+        for (row, expected) in inverse.iter().zip(expected) {
+            for (value, expected) in row.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-12);
+            }
+        }
+        assert!(invert(vec![vec![1.0, 2.0], vec![2.0, 4.0]]).is_err());
+    }
+
+    #[test]
+    fn test_quantile_threshold() {
+        let scores: Vec<f32> = (1..=100).map(|i| i as f32).collect();
+        assert_eq!(quantile_threshold(&scores, 0.95).unwrap(), 95.0);
+        assert_eq!(quantile_threshold(&scores, 1.0).unwrap(), 100.0);
+        assert_eq!(quantile_threshold(&scores, 0.0).unwrap(), 1.0);
+        assert!(quantile_threshold(&scores, 1.5).is_err());
+        assert!(quantile_threshold(&[], 0.5).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use candle_core::Tensor;
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Form of the scores returned for a prediction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputMode {
+    /// Probabilities, optionally temperature scaled
+    #[default]
+    Softmax,
+    /// Log-probabilities, optionally temperature scaled
+    LogSoftmax,
+    /// Raw model outputs
+    Logits,
+}
+
+/// Turns model logits into the scores requested by the caller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PostProcessing {
+    pub mode: OutputMode,
+    /// Logits are divided by the temperature before the (log-)softmax, values above 1
+    /// soften the distribution and values below 1 sharpen it
+    pub temperature: f32,
+}
+
+impl Default for PostProcessing {
+    fn default() -> Self {
+        Self {
+            mode: OutputMode::Softmax,
+            temperature: 1.0,
+        }
+    }
+}
+
+impl PostProcessing {
+    pub fn new(mode: OutputMode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Result<Self> {
+        if !temperature.is_finite() || temperature <= 0.0 {
+            return Err(Error::invalid_argument(format!(
+                "Temperature must be a positive number, got {}",
+                temperature
+            )));
+        }
+        self.temperature = temperature;
+        Ok(self)
+    }
+
+    /// Additionally scale the logits by a calibrated temperature
+    ///
+    /// Raw logits are left untouched so callers can still recalibrate them.
+    pub fn calibrated(mut self, temperature: f32) -> Self {
+        self.temperature *= temperature;
+        self
+    }
+
+    /// Probabilities over the classes, temperature scaled
+    pub fn probabilities(&self, logits: &Tensor) -> Result<Tensor> {
+        Ok(candle_nn::ops::softmax_last_dim(&self.scale(logits)?)?)
+    }
+
+    /// Scores of the requested mode
+    pub fn apply(&self, logits: &Tensor) -> Result<Tensor> {
+        match self.mode {
+            OutputMode::Softmax => self.probabilities(logits),
+            OutputMode::LogSoftmax => Ok(candle_nn::ops::log_softmax(
+                &self.scale(logits)?,
+                candle_core::D::Minus1,
+            )?),
+            OutputMode::Logits => Ok(logits.clone()),
+        }
+    }
+
+    fn scale(&self, logits: &Tensor) -> Result<Tensor> {
+        if self.temperature == 1.0 {
+            Ok(logits.clone())
+        } else {
+            Ok((logits / self.temperature as f64)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Device;
+
+    fn scores(post: PostProcessing) -> Vec<f32> {
+        let logits = Tensor::new(&[1.0f32, 2.0, 3.0], &Device::Cpu).unwrap();
+        post.apply(&logits).unwrap().to_vec1().unwrap()
+    }
+
+    #[test]
+    fn test_modes() {
+        assert_eq!(
+            scores(PostProcessing::new(OutputMode::Logits)),
+            [1.0, 2.0, 3.0]
+        );
+
+        let probabilities = scores(PostProcessing::default());
+        assert!((probabilities.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+
+        let log_probabilities = scores(PostProcessing::new(OutputMode::LogSoftmax));
+        for (p, log_p) in probabilities.iter().zip(&log_probabilities) {
+            assert!((p.ln() - log_p).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_temperature() {
+        let sharp = scores(PostProcessing::default());
+        let soft = scores(PostProcessing::default().with_temperature(10.0).unwrap());
+        assert!(soft[2] < sharp[2]);
+        assert!(soft[0] > sharp[0]);
+
+        assert!(PostProcessing::default().with_temperature(0.0).is_err());
+        assert!(
+            PostProcessing::default()
+                .with_temperature(f32::NAN)
+                .is_err()
+        );
+    }
+}
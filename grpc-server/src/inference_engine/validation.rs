@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use serde::Serialize;
+
+use mnist::quantized::padded_columns;
+use mnist::{ModelSpec, QuantizedWeights};
+
+use super::{MnistModel, ModelArchitecture};
+use crate::Result;
+
+/// Tensor names mapped to their shapes
+pub type TensorShapes = BTreeMap<String, Vec<usize>>;
+
+/// Enumerate the tensors an architecture reads from its weights file
+///
+/// The model is instantiated against an empty VarMap, which records every variable
+/// the constructor asks for together with the requested shape. ONNX graphs embed their
+/// weights and ensembles load their members, so neither expects external tensors.
+pub fn expected_tensors(arch: ModelArchitecture, spec: Option<&ModelSpec>) -> Result<TensorShapes> {
+    if matches!(arch, ModelArchitecture::Onnx | ModelArchitecture::Ensemble) {
+        return Ok(TensorShapes::new());
+    }
+    let varmap = VarMap::new();
+    let varbuilder = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    MnistModel::new(varbuilder, arch, spec)?;
+
+    let data = varmap.data().lock().unwrap();
+    Ok(data
+        .iter()
+        .map(|(name, var)| (name.clone(), var.dims().to_vec()))
+        .collect())
+}
+
+/// Shapes of quantized weights as the architecture sees them
+///
+/// Quantized matrices have their columns padded, so a matrix with the expected rows and
+/// at most [`padded_columns`] of the expected columns is reported with the expected shape.
+pub fn quantized_shapes(weights: &QuantizedWeights, expected: &TensorShapes) -> TensorShapes {
+    weights
+        .tensor_infos()
+        .into_iter()
+        .map(|(name, shape, dtype)| {
+            let shape = match (expected.get(&name).map(Vec::as_slice), shape.as_slice()) {
+                (Some(&[rows, cols]), &[stored_rows, stored_cols])
+                    if stored_rows == rows
+                        && (cols..=padded_columns(cols, dtype)).contains(&stored_cols) =>
+                {
+                    vec![rows, cols]
+                }
+                _ => shape,
+            };
+            (name, shape)
+        })
+        .collect()
+}
+
+/// Comparison of the tensors in a weights file against an architecture
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    pub architecture: ModelArchitecture,
+    /// Tensors the architecture needs that are not in the weights
+    pub missing: Vec<TensorInfo>,
+    /// Tensors in the weights the architecture does not use
+    pub unexpected: Vec<TensorInfo>,
+    /// Tensors present in both but with a different shape
+    pub mismatched: Vec<ShapeMismatch>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TensorInfo {
+    pub name: String,
+    pub shape: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShapeMismatch {
+    pub name: String,
+    pub expected: Vec<usize>,
+    pub actual: Vec<usize>,
+}
+
+impl ValidationReport {
+    /// Compare the tensors found in a weights file against what `arch` expects
+    pub fn new(
+        arch: ModelArchitecture,
+        spec: Option<&ModelSpec>,
+        actual: &TensorShapes,
+    ) -> Result<Self> {
+        let expected = expected_tensors(arch, spec)?;
+
+        let mut missing = Vec::new();
+        let mut mismatched = Vec::new();
+        for (name, shape) in &expected {
+            match actual.get(name) {
+                None => missing.push(TensorInfo {
+                    name: name.clone(),
+                    shape: shape.clone(),
+                }),
+                Some(found) if found != shape => mismatched.push(ShapeMismatch {
+                    name: name.clone(),
+                    expected: shape.clone(),
+                    actual: found.clone(),
+                }),
+                Some(_) => {}
+            }
+        }
+        let unexpected = actual
+            .iter()
+            .filter(|(name, _)| !expected.contains_key(*name))
+            .map(|(name, shape)| TensorInfo {
+                name: name.clone(),
+                shape: shape.clone(),
+            })
+            .collect();
+
+        Ok(Self {
+            architecture: arch,
+            missing,
+            unexpected,
+            mismatched,
+        })
+    }
+
+    /// Returns true if the model can be built from the weights
+    ///
+    /// Unexpected tensors are ignored by the model, use [`Self::is_exact`] to reject them too.
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.mismatched.is_empty()
+    }
+
+    /// Returns true if the weights contain exactly the tensors of the architecture
+    pub fn is_exact(&self) -> bool {
+        self.is_valid() && self.unexpected.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_exact() {
+            return write!(f, "Weights match the {:?} architecture", self.architecture);
+        }
+
+        write!(
+            f,
+            "Weights do not match the {:?} architecture:",
+            self.architecture
+        )?;
+        for tensor in &self.missing {
+            write!(f, "\n  missing     {} {:?}", tensor.name, tensor.shape)?;
+        }
+        for tensor in &self.mismatched {
+            write!(
+                f,
+                "\n  wrong shape {} {:?}, expected {:?}",
+                tensor.name, tensor.actual, tensor.expected
+            )?;
+        }
+        for tensor in &self.unexpected {
+            write!(f, "\n  unexpected  {} {:?}", tensor.name, tensor.shape)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candle_core::Tensor;
+    use mnist::Quantization;
+
+    #[test]
+    fn test_expected_tensors() {
+        let expected = expected_tensors(ModelArchitecture::MLP, None).unwrap();
+        assert_eq!(expected["fc1.weight"], vec![128, 784]);
+        assert_eq!(expected["fc3.bias"], vec![10]);
+        assert_eq!(expected.len(), 6);
+    }
+
+    #[test]
+    fn test_report() {
+        let mut actual = expected_tensors(ModelArchitecture::MLP, None).unwrap();
+        let report = ValidationReport::new(ModelArchitecture::MLP, None, &actual).unwrap();
+        assert!(report.is_exact());
+
+        actual.remove("fc1.bias");
+        actual.insert("fc2.weight".to_string(), vec![64, 64]);
+        actual.insert("optimizer.step".to_string(), vec![1]);
+        let report = ValidationReport::new(ModelArchitecture::MLP, None, &actual).unwrap();
+        assert!(!report.is_valid());
+        assert_eq!(report.missing[0].name, "fc1.bias");
+        assert_eq!(report.mismatched[0].actual, vec![64, 64]);
+        assert_eq!(report.unexpected[0].name, "optimizer.step");
+
+        let text = report.to_string();
+        assert!(text.contains("missing     fc1.bias [128]"), "{}", text);
+        assert!(text.contains("wrong shape fc2.weight [64, 64], expected [64, 128]"));
+    }
+
+    #[test]
+    fn test_quantized_shapes() {
+        let tensors = [
+            (
+                "fc1.weight",
+                Tensor::zeros((128, 784), DType::F32, &Device::Cpu),
+            ),
+            (
+                "fc2.weight",
+                Tensor::zeros((64, 64), DType::F32, &Device::Cpu),
+            ),
+            ("fc3.bias", Tensor::zeros(10, DType::F32, &Device::Cpu)),
+        ]
+        .into_iter()
+        .map(|(name, tensor)| (name.to_string(), tensor.unwrap()))
+        .collect();
+        let weights =
+            QuantizedWeights::quantize(&tensors, Quantization::Q4_0, &Device::Cpu).unwrap();
+        let expected = expected_tensors(ModelArchitecture::MLP, None).unwrap();
+
+        // fc1 is padded to 832 columns, fc2 has too few columns to be padded
+        let shapes = quantized_shapes(&weights, &expected);
+        assert_eq!(shapes["fc1.weight"], vec![128, 784]);
+        assert_eq!(shapes["fc2.weight"], vec![64, 64]);
+        assert_eq!(shapes["fc3.bias"], vec![10]);
+    }
+
+    #[test]
+    fn test_unexpected_tensors_are_valid() {
+        let mut actual = expected_tensors(ModelArchitecture::Conv, None).unwrap();
+        actual.insert("extra".to_string(), vec![3]);
+        let report = ValidationReport::new(ModelArchitecture::Conv, None, &actual).unwrap();
+        assert!(report.is_valid());
+        assert!(!report.is_exact());
+    }
+}
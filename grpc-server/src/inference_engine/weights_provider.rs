@@ -1,32 +1,720 @@
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
 
+use candle_core::{DType, Device, Shape, Tensor};
+use candle_nn::VarBuilder;
+use candle_nn::var_builder::SimpleBackend;
+use mnist::QuantizedWeights;
+use safetensors::tensor::{TensorInfo, TensorView};
+use serde::Deserialize;
+
+use super::integrity::IntegrityPolicy;
+use super::manifest::{ModelManifest, safetensors_metadata};
+use super::validation::TensorShapes;
 use crate::{Error, Result};
 
 /// WeightsProvider trait defines a contract for providing model weights
 pub trait WeightsProvider {
-    fn load_weights(&self) -> Result<Vec<u8>>;
+    fn load_weights(&self) -> Result<WeightsSource>;
+
+    /// Load the manifest describing the model, if the provider has one
+    fn load_manifest(&self) -> Result<Option<ModelManifest>> {
+        Ok(None)
+    }
+
+    /// Checks the weights pass, also applied to the files they reference
+    fn integrity(&self) -> IntegrityPolicy {
+        IntegrityPolicy::default()
+    }
+}
+
+/// Location of the model weights handed out by a [`WeightsProvider`]
+#[derive(Debug)]
+pub enum WeightsSource {
+    /// Safetensors data already held in memory, e.g. fetched over the network
+    Buffer(Vec<u8>),
+    /// One or more files on disk, mapped once and read through the same mappings the
+    /// integrity checks ran on
+    Files(Vec<MappedFile>),
+}
+
+/// A read-only memory mapping of a weights file, kept alive while the weights are loaded
+#[derive(Debug)]
+pub struct MappedFile {
+    path: PathBuf,
+    mmap: memmap2::Mmap,
+}
+
+impl MappedFile {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path)
+            .map_err(|e| Error::custom(format!("Failed to open {}: {}", path.display(), e)))?;
+        // SAFETY: the mapping is read-only; a file replaced on disk keeps the mapped inode
+        let mmap = unsafe { memmap2::Mmap::map(&file) }
+            .map_err(|e| Error::custom(format!("Failed to map {}: {}", path.display(), e)))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            mmap,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+/// Safetensors files whose tensors are read on demand from their mappings
+struct MappedSafetensors {
+    files: Vec<MappedFile>,
+    /// File, start of the data in it and layout of every tensor
+    tensors: HashMap<String, (usize, usize, TensorInfo)>,
+}
+
+impl MappedSafetensors {
+    fn new(files: Vec<MappedFile>) -> Result<Self> {
+        let mut tensors = HashMap::new();
+        for (index, file) in files.iter().enumerate() {
+            let (header_len, metadata) = safetensors::SafeTensors::read_metadata(file.data())
+                .map_err(|e| {
+                    Error::custom(format!("Failed to read {}: {}", file.path().display(), e))
+                })?;
+            // The data follows the 8 byte header length and the header
+            for (name, info) in metadata.tensors() {
+                tensors.insert(name, (index, 8 + header_len, info.clone()));
+            }
+        }
+        Ok(Self { files, tensors })
+    }
+
+    fn load(&self, name: &str, device: &Device) -> candle_core::Result<Tensor> {
+        let Some((index, start, info)) = self.tensors.get(name) else {
+            return Err(candle_core::Error::CannotFindTensor {
+                path: name.to_string(),
+            }
+            .bt());
+        };
+        let (begin, end) = info.data_offsets;
+        let data = &self.files[*index].data()[start + begin..start + end];
+        let view = TensorView::new(info.dtype, info.shape.clone(), data)?;
+        candle_core::safetensors::Load::load(&view, device)
+    }
+}
+
+impl SimpleBackend for MappedSafetensors {
+    fn get(
+        &self,
+        shape: Shape,
+        name: &str,
+        _: candle_nn::Init,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let tensor = self.get_unchecked(name, dtype, device)?;
+        if tensor.shape() != &shape {
+            return Err(candle_core::Error::UnexpectedShape {
+                msg: format!("shape mismatch for {name}"),
+                expected: shape,
+                got: tensor.shape().clone(),
+            }
+            .bt());
+        }
+        Ok(tensor)
+    }
+
+    fn get_unchecked(
+        &self,
+        name: &str,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        self.load(name, device)?.to_dtype(dtype)
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.tensors.contains_key(name)
+    }
+}
+
+/// Magic bytes at the start of a GGUF file
+const GGUF_MAGIC: &[u8; 4] = b"GGUF";
+
+impl WeightsSource {
+    /// Create a VarBuilder reading the tensors from this source
+    pub fn var_builder(self, dtype: DType, device: &Device) -> Result<VarBuilder<'static>> {
+        let varbuilder = match self {
+            WeightsSource::Buffer(data) => {
+                VarBuilder::from_buffered_safetensors(data, dtype, device)?
+            }
+            // The VarBuilder keeps the verified mappings and reads the tensors from them
+            WeightsSource::Files(files) => VarBuilder::from_backend(
+                Box::new(MappedSafetensors::new(files)?),
+                dtype,
+                device.clone(),
+            ),
+        };
+        Ok(varbuilder)
+    }
+
+    /// Read the names and shapes of the stored tensors without loading their data
+    pub fn tensor_shapes(&self) -> Result<TensorShapes> {
+        let mut shapes = TensorShapes::new();
+        for data in self.contents() {
+            let tensors = candle_core::safetensors::SliceSafetensors::new(data)?;
+            shapes.extend(
+                tensors
+                    .tensors()
+                    .into_iter()
+                    .map(|(name, view)| (name, view.shape().to_vec())),
+            );
+        }
+        Ok(shapes)
+    }
+
+    /// Returns true if the source holds quantized GGUF weights rather than safetensors
+    pub fn is_gguf(&self) -> Result<bool> {
+        match self.contents().as_slice() {
+            [data] => Ok(data.starts_with(GGUF_MAGIC)),
+            _ => Ok(false),
+        }
+    }
+
+    /// Read every tensor of the safetensors weights
+    pub fn tensors(&self, device: &Device) -> Result<HashMap<String, Tensor>> {
+        let mut tensors = HashMap::new();
+        for data in self.contents() {
+            tensors.extend(candle_core::safetensors::load_buffer(data, device)?);
+        }
+        Ok(tensors)
+    }
+
+    /// Read the quantized tensors of a GGUF file
+    pub fn gguf_weights(&self, device: &Device) -> Result<QuantizedWeights> {
+        match self.contents().as_slice() {
+            [data] => Ok(QuantizedWeights::from_gguf(
+                &mut std::io::Cursor::new(data),
+                device,
+            )?),
+            _ => Err(Error::custom("GGUF weights cannot be sharded")),
+        }
+    }
+
+    /// Contents of the single artifact of the source, e.g. an ONNX graph or ensemble file
+    ///
+    /// Returns the artifact and the file it was read from, if any.
+    pub fn single_file(&self) -> Option<(&[u8], Option<&Path>)> {
+        match self {
+            WeightsSource::Buffer(data) => Some((data, None)),
+            WeightsSource::Files(files) => match files.as_slice() {
+                [file] => Some((file.data(), Some(file.path()))),
+                _ => None,
+            },
+        }
+    }
+
+    /// Bytes of every file of the source, in order
+    fn contents(&self) -> Vec<&[u8]> {
+        match self {
+            WeightsSource::Buffer(data) => vec![data],
+            WeightsSource::Files(files) => files.iter().map(MappedFile::data).collect(),
+        }
+    }
+}
+
+/// Index file describing a checkpoint sharded over several safetensors files,
+/// as written by `huggingface_hub` / `transformers` (`model.safetensors.index.json`)
+#[derive(Debug, Deserialize)]
+struct ShardIndex {
+    #[serde(default)]
+    metadata: HashMap<String, serde_json::Value>,
+    weight_map: HashMap<String, String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LocalFileProvider {
     path: PathBuf,
+    manifest: Option<PathBuf>,
+    integrity: IntegrityPolicy,
+    mmap: bool,
 }
 
 impl LocalFileProvider {
-    pub fn from_str(path: &str) -> Result<Self> {
-        let path = PathBuf::from(path);
-        if !path.exists() {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            manifest: None,
+            integrity: IntegrityPolicy::default(),
+            mmap: true,
+        }
+    }
+
+    /// Read the model manifest from the given file instead of looking next to the weights
+    pub fn with_manifest(mut self, manifest: PathBuf) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+
+    /// Require the weights to pass the given integrity checks before they are loaded
+    pub fn with_integrity(mut self, integrity: IntegrityPolicy) -> Self {
+        self.integrity = integrity;
+        self
+    }
+
+    /// Memory-map the weights (default) instead of reading them into a buffer
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// File the manifest is read from and written to: the configured one, an existing
+    /// sidecar, or else a new `<model>.manifest.json` sidecar
+    pub fn manifest_path(&self) -> PathBuf {
+        if let Some(path) = &self.manifest {
+            return path.clone();
+        }
+        let sidecars = ModelManifest::sidecar_paths(&self.path);
+        sidecars
+            .iter()
+            .find(|path| path.exists())
+            .unwrap_or(&sidecars[0])
+            .clone()
+    }
+
+    /// Returns true if the path points to a sharded checkpoint index
+    pub fn is_sharded(&self) -> bool {
+        self.path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".index.json"))
+    }
+
+    /// Returns true if the path points to an ONNX model rather than safetensors weights
+    pub fn is_onnx(&self) -> bool {
+        self.path.extension().is_some_and(|ext| ext == "onnx")
+    }
+
+    /// Returns true if the path points to quantized GGUF weights
+    pub fn is_gguf(&self) -> bool {
+        self.path.extension().is_some_and(|ext| ext == "gguf")
+    }
+
+    /// Returns true if the path points to a JSON or TOML file, e.g. an ensemble definition
+    fn is_config(&self) -> bool {
+        !self.is_sharded()
+            && self
+                .path
+                .extension()
+                .is_some_and(|ext| ext == "json" || ext == "toml")
+    }
+
+    fn shard_index(&self, index: &[u8]) -> Result<ShardIndex> {
+        serde_json::from_slice(index).map_err(|e| {
+            Error::custom(format!(
+                "Invalid shard index {}: {}",
+                self.path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Resolve the safetensors files referenced by the shard index, relative to the index
+    fn shard_paths(&self, index: &ShardIndex) -> Result<Vec<PathBuf>> {
+        let dir = self
+            .path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let shards: BTreeSet<&String> = index.weight_map.values().collect();
+        if shards.is_empty() {
             return Err(Error::custom(format!(
-                "Weights file does not exist: {}",
-                path.display()
+                "Shard index {} does not reference any files",
+                self.path.display()
             )));
         }
-        Ok(Self { path })
+        let root = dir
+            .canonicalize()
+            .map_err(|e| Error::custom(format!("Failed to resolve {}: {}", dir.display(), e)))?;
+        shards
+            .into_iter()
+            .map(|shard| {
+                let relative = Path::new(shard);
+                let escapes = relative.components().any(|component| {
+                    !matches!(component, Component::Normal(_) | Component::CurDir)
+                });
+                if escapes {
+                    return Err(Error::custom(format!(
+                        "Shard {} of {} must be a relative path inside its directory",
+                        shard,
+                        self.path.display()
+                    )));
+                }
+                let path = dir.join(relative);
+                // A symlink can still point elsewhere, missing files are reported on load
+                if let Ok(resolved) = path.canonicalize()
+                    && !resolved.starts_with(&root)
+                {
+                    return Err(Error::custom(format!(
+                        "Shard {} of {} resolves outside {}",
+                        shard,
+                        self.path.display(),
+                        root.display()
+                    )));
+                }
+                Ok(path)
+            })
+            .collect()
+    }
+}
+
+impl FromStr for LocalFileProvider {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<Self> {
+        Ok(Self::new(PathBuf::from(path)))
     }
 }
 
 impl WeightsProvider for LocalFileProvider {
-    fn load_weights(&self) -> Result<Vec<u8>> {
-        std::fs::read(&self.path).map_err(|e| Error::Custom(e.to_string()))
+    /// Manifests are looked up in order: the explicitly configured file, a
+    /// `<model>.manifest.{json,toml}` sidecar, then the metadata embedded in the
+    /// safetensors header (or the `metadata` section of a shard index). ONNX models, GGUF
+    /// weights and ensemble files only use manifest files.
+    ///
+    /// Under an integrity policy a manifest file must pass its own sidecar checks, see
+    /// [`IntegrityPolicy::verify_manifest`], and embedded metadata is verified as part of
+    /// the weights.
+    fn load_manifest(&self) -> Result<Option<ModelManifest>> {
+        let file = self.manifest.clone().or_else(|| {
+            ModelManifest::sidecar_paths(&self.path)
+                .into_iter()
+                .find(|path| path.exists())
+        });
+        if let Some(path) = file {
+            let contents = std::fs::read(&path).map_err(|e| {
+                Error::custom(format!("Failed to read manifest {}: {}", path.display(), e))
+            })?;
+            self.integrity.verify_manifest(&path, &contents)?;
+            return ModelManifest::from_bytes(&path, &contents).map(Some);
+        }
+        if !self.path.exists() || self.is_onnx() || self.is_gguf() || self.is_config() {
+            return Ok(None);
+        }
+
+        // Embedded metadata is part of the weights, and read from the bytes verified as such
+        let weights = MappedFile::open(&self.path)?;
+        self.integrity.verify(&self.path, weights.data())?;
+        let metadata = if self.is_sharded() {
+            self.shard_index(weights.data())?
+                .metadata
+                .into_iter()
+                .map(|(key, value)| match value {
+                    serde_json::Value::String(value) => (key, value),
+                    value => (key, value.to_string()),
+                })
+                .collect()
+        } else {
+            safetensors_metadata(&self.path, weights.data())?
+        };
+        ModelManifest::from_metadata(&metadata)
+    }
+
+    fn integrity(&self) -> IntegrityPolicy {
+        self.integrity.clone()
+    }
+
+    fn load_weights(&self) -> Result<WeightsSource> {
+        ensure_exists(&self.path)?;
+
+        if self.is_sharded() {
+            // The index is verified with the configured checks, every shard with its own
+            // sidecar digest and signature, so a sharded model is never partially verified.
+            let index = MappedFile::open(&self.path)?;
+            self.integrity.verify(&self.path, index.data())?;
+            let shards = self.shard_paths(&self.shard_index(index.data())?)?;
+            let mut files = Vec::with_capacity(shards.len());
+            for shard in &shards {
+                ensure_exists(shard)?;
+                let file = MappedFile::open(shard)?;
+                self.integrity.verify_shard(shard, file.data())?;
+                files.push(file);
+            }
+            tracing::info!(index = %self.path.display(), shards = shards.len(), "Loading sharded weights");
+            return Ok(WeightsSource::Files(files));
+        }
+
+        if self.mmap {
+            let file = MappedFile::open(&self.path)?;
+            self.integrity.verify(&self.path, file.data())?;
+            Ok(WeightsSource::Files(vec![file]))
+        } else {
+            let weights = std::fs::read(&self.path).map_err(|e| Error::Custom(e.to_string()))?;
+            self.integrity.verify(&self.path, &weights)?;
+            Ok(WeightsSource::Buffer(weights))
+        }
+    }
+}
+
+fn ensure_exists(path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Err(Error::custom(format!(
+            "Weights file does not exist: {}",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inference_engine::ModelArchitecture;
+    use crate::test_util::TempDir;
+    use candle_core::Tensor;
+
+    fn write_tensors(path: &Path, names: &[&str]) {
+        let tensors: HashMap<String, Tensor> = names
+            .iter()
+            .map(|name| {
+                let tensor = Tensor::ones((2, 3), DType::F32, &Device::Cpu).unwrap();
+                (name.to_string(), tensor)
+            })
+            .collect();
+        candle_core::safetensors::save(&tensors, path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let provider = LocalFileProvider::from_str("does-not-exist.safetensors").unwrap();
+        assert!(provider.load_weights().is_err());
+    }
+
+    #[test]
+    fn test_mmap_and_buffered_sources() {
+        let dir = TempDir::new("weights");
+        let path = dir.join("model.safetensors");
+        write_tensors(&path, &["a.weight"]);
+
+        let provider = LocalFileProvider::new(path.clone());
+        let source = provider.load_weights().unwrap();
+        assert!(
+            matches!(&source, WeightsSource::Files(files) if files.len() == 1 && files[0].path() == path)
+        );
+        let vb = source.var_builder(DType::F32, &Device::Cpu).unwrap();
+        assert!(vb.contains_tensor("a.weight"));
+        assert!(!vb.contains_tensor("b.weight"));
+        let tensor = vb.get((2, 3), "a.weight").unwrap();
+        assert_eq!(tensor.sum_all().unwrap().to_scalar::<f32>().unwrap(), 6.0);
+        assert!(vb.get((3, 2), "a.weight").is_err());
+        assert!(vb.get((2, 3), "b.weight").is_err());
+
+        let provider = LocalFileProvider::new(path).with_mmap(false);
+        let source = provider.load_weights().unwrap();
+        assert!(matches!(source, WeightsSource::Buffer(_)));
+        let vb = source.var_builder(DType::F32, &Device::Cpu).unwrap();
+        assert!(vb.contains_tensor("a.weight"));
+    }
+
+    #[test]
+    fn test_weights_replaced_after_verification() {
+        let dir = TempDir::new("weights");
+        let path = dir.join("model.safetensors");
+        write_tensors(&path, &["a.weight"]);
+        let digest = hex::encode(<sha2::Sha256 as sha2::Digest>::digest(
+            std::fs::read(&path).unwrap(),
+        ));
+        let provider = LocalFileProvider::new(path.clone())
+            .with_integrity(IntegrityPolicy::new().with_sha256(digest));
+        let source = provider.load_weights().unwrap();
+
+        // Swap in other weights between the check and the load
+        let replacement = dir.join("replacement.safetensors");
+        let tensors = HashMap::from([(
+            "a.weight".to_string(),
+            Tensor::zeros((2, 3), DType::F32, &Device::Cpu).unwrap(),
+        )]);
+        candle_core::safetensors::save(&tensors, &replacement).unwrap();
+        std::fs::rename(&replacement, &path).unwrap();
+
+        let vb = source.var_builder(DType::F32, &Device::Cpu).unwrap();
+        let tensor = vb.get((2, 3), "a.weight").unwrap();
+        assert_eq!(tensor.sum_all().unwrap().to_scalar::<f32>().unwrap(), 6.0);
+        assert!(provider.load_weights().is_err());
+    }
+
+    #[test]
+    fn test_sharded_weights() {
+        let dir = TempDir::new("weights");
+        write_tensors(&dir.join("model-00001-of-00002.safetensors"), &["a.weight"]);
+        write_tensors(&dir.join("model-00002-of-00002.safetensors"), &["b.weight"]);
+        let index = dir.join("model.safetensors.index.json");
+        std::fs::write(
+            &index,
+            r#"{"metadata": {}, "weight_map": {
+                "a.weight": "model-00001-of-00002.safetensors",
+                "b.weight": "model-00002-of-00002.safetensors"
+            }}"#,
+        )
+        .unwrap();
+
+        let provider = LocalFileProvider::new(index);
+        assert!(provider.is_sharded());
+        let source = provider.load_weights().unwrap();
+        assert!(matches!(&source, WeightsSource::Files(files) if files.len() == 2));
+        let vb = source.var_builder(DType::F32, &Device::Cpu).unwrap();
+        assert!(vb.contains_tensor("a.weight"));
+        assert!(vb.contains_tensor("b.weight"));
+    }
+
+    #[test]
+    fn test_sharded_integrity() {
+        let dir = TempDir::new("weights");
+        let shard = dir.join("model-00001-of-00001.safetensors");
+        write_tensors(&shard, &["a.weight"]);
+        let index = dir.join("model.safetensors.index.json");
+        std::fs::write(
+            &index,
+            r#"{"weight_map": {"a.weight": "model-00001-of-00001.safetensors"}}"#,
+        )
+        .unwrap();
+        let sha256 = |path: &Path| {
+            let digest = <sha2::Sha256 as sha2::Digest>::digest(std::fs::read(path).unwrap());
+            hex::encode(digest)
+        };
+        for path in [&index, &shard] {
+            std::fs::write(format!("{}.sha256", path.display()), sha256(path)).unwrap();
+        }
+
+        let sidecars = LocalFileProvider::new(index.clone())
+            .with_integrity(IntegrityPolicy::new().with_sha256_sidecar(true));
+        assert!(sidecars.load_weights().is_ok());
+
+        // A pinned digest of the index does not cover the shards, even untampered ones
+        let pinned = LocalFileProvider::new(index.clone())
+            .with_integrity(IntegrityPolicy::new().with_sha256(sha256(&index)));
+        let error = pinned.load_weights().unwrap_err();
+        assert!(matches!(error, Error::Integrity(_)), "{}", error);
+
+        write_tensors(&shard, &["a.weight", "b.weight"]);
+        assert!(sidecars.load_weights().is_err());
+        assert!(pinned.load_weights().is_err());
+    }
+
+    #[test]
+    fn test_manifest_lookup() {
+        let dir = TempDir::new("weights");
+        let path = dir.join("model.safetensors");
+        let tensors = HashMap::from([(
+            "a.weight".to_string(),
+            Tensor::ones((2, 3), DType::F32, &Device::Cpu).unwrap(),
+        )]);
+        let metadata = HashMap::from([("architecture".to_string(), "mlp".to_string())]);
+        safetensors::serialize_to_file(&tensors, Some(metadata), &path).unwrap();
+
+        // Embedded metadata is used when there is no manifest file
+        let provider = LocalFileProvider::new(path.clone());
+        let manifest = provider.load_manifest().unwrap().unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::MLP));
+
+        // A sidecar manifest takes precedence over the metadata
+        std::fs::write(
+            dir.join("model.manifest.json"),
+            r#"{"architecture": "conv"}"#,
+        )
+        .unwrap();
+        let manifest = provider.load_manifest().unwrap().unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::Conv));
+
+        let shapes = provider.load_weights().unwrap().tensor_shapes().unwrap();
+        assert_eq!(shapes["a.weight"], vec![2, 3]);
+    }
+
+    #[test]
+    fn test_shards_outside_the_checkpoint() {
+        let dir = TempDir::new("weights");
+        let checkpoint = dir.join("checkpoint");
+        std::fs::create_dir_all(&checkpoint).unwrap();
+        let outside = dir.join("outside.safetensors");
+        write_tensors(&outside, &["a.weight"]);
+
+        let index = checkpoint.join("model.safetensors.index.json");
+        #[allow(unused_mut)]
+        let mut shards = vec![
+            "../outside.safetensors".to_string(),
+            outside.display().to_string(),
+        ];
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&outside, checkpoint.join("link.safetensors")).unwrap();
+            shards.push("link.safetensors".to_string());
+        }
+        for shard in shards {
+            let weight_map = serde_json::json!({"weight_map": {"a.weight": shard}});
+            std::fs::write(&index, weight_map.to_string()).unwrap();
+            let error = LocalFileProvider::new(index.clone())
+                .load_weights()
+                .unwrap_err();
+            assert!(error.to_string().contains("outside") || error.to_string().contains("inside"));
+        }
+    }
+
+    #[test]
+    fn test_manifest_integrity() {
+        let dir = TempDir::new("weights");
+        let path = dir.join("model.safetensors");
+        let tensors = HashMap::from([(
+            "a.weight".to_string(),
+            Tensor::ones((2, 3), DType::F32, &Device::Cpu).unwrap(),
+        )]);
+        let metadata = HashMap::from([("architecture".to_string(), "mlp".to_string())]);
+        safetensors::serialize_to_file(&tensors, Some(metadata), &path).unwrap();
+        let sha256 = |path: &Path| {
+            let digest = <sha2::Sha256 as sha2::Digest>::digest(std::fs::read(path).unwrap());
+            std::fs::write(format!("{}.sha256", path.display()), hex::encode(digest)).unwrap();
+        };
+        sha256(&path);
+
+        // Embedded metadata is covered by the checks of the weights
+        let provider = LocalFileProvider::new(path.clone())
+            .with_integrity(IntegrityPolicy::new().with_sha256_sidecar(true));
+        let manifest = provider.load_manifest().unwrap().unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::MLP));
+
+        // A sidecar manifest dropped next to verified weights is refused until it is verified
+        let manifest_path = dir.join("model.manifest.json");
+        std::fs::write(&manifest_path, r#"{"architecture": "conv"}"#).unwrap();
+        assert!(provider.load_manifest().is_err());
+        sha256(&manifest_path);
+        let manifest = provider.load_manifest().unwrap().unwrap();
+        assert_eq!(manifest.architecture, Some(ModelArchitecture::Conv));
+
+        // A pinned digest of the weights says nothing about the manifest
+        let digest = std::fs::read_to_string(format!("{}.sha256", path.display())).unwrap();
+        let provider =
+            LocalFileProvider::new(path).with_integrity(IntegrityPolicy::new().with_sha256(digest));
+        assert!(provider.load_manifest().is_err());
+        std::fs::remove_file(&manifest_path).unwrap();
+        assert!(provider.load_manifest().unwrap().is_some());
+    }
+
+    #[test]
+    fn test_sharded_weights_missing_shard() {
+        let dir = TempDir::new("weights");
+        let index = dir.join("model.safetensors.index.json");
+        std::fs::write(
+            &index,
+            r#"{"weight_map": {"a.weight": "missing.safetensors"}}"#,
+        )
+        .unwrap();
+
+        let provider = LocalFileProvider::new(index);
+        assert!(provider.load_weights().is_err());
     }
 }
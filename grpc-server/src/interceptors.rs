@@ -1,6 +1,7 @@
 use std::time::Instant;
 use tonic::{Request, Status, service::Interceptor};
 
+#[allow(clippy::result_large_err)]
 pub fn tracing_interceptor(mut req: Request<()>) -> Result<Request<()>, Status> {
     let start = Instant::now();
     let request_id = uuid::Uuid::new_v4();
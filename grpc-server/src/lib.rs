@@ -1,14 +1,28 @@
 #![allow(unused)]
 pub mod cli;
+pub mod commands;
 pub mod config;
 pub mod error;
+pub mod feedback;
+pub mod gateway;
 pub mod inference_engine;
 pub mod interceptors;
+pub mod metrics;
+pub mod open_inference;
+pub mod rollout;
+pub mod segmentation;
 pub mod server;
 pub mod service;
+#[cfg(test)]
+mod test_util;
 
 pub use error::{Error, Result};
 
 pub mod proto {
     tonic::include_proto!("mnist");
+
+    /// Open Inference Protocol (KServe V2) messages and service
+    pub mod inference {
+        tonic::include_proto!("inference");
+    }
 }
@@ -1,12 +1,31 @@
+use std::process::ExitCode;
+
 use clap::Parser;
 use grpc_server::cli::Args;
+use grpc_server::commands;
 use grpc_server::server::MnistGrpcServer;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Print with Display so multi-line reports stay readable
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments
     let args = Args::parse();
 
+    if let Some(command) = args.command {
+        commands::run(command).await?;
+        return Ok(());
+    }
+
     // Convert CLI args to server configuration
     let config = args.to_server_config()?;
 
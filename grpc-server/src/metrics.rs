@@ -0,0 +1,352 @@
+//! In-process metrics of the server
+//!
+//! Counters are plain atomics, snapshots are serializable for logs and JSON output and
+//! can be rendered in the Prometheus text exposition format.
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Number of digit classes tracked per class
+const NUM_CLASSES: usize = 10;
+
+/// Count, sum and maximum of observed latencies
+#[derive(Debug, Default)]
+pub struct LatencyStats {
+    count: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl LatencyStats {
+    pub fn record(&self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> LatencySnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        let total_micros = self.total_micros.load(Ordering::Relaxed);
+        LatencySnapshot {
+            count,
+            mean_ms: if count == 0 {
+                0.0
+            } else {
+                total_micros as f64 / count as f64 / 1000.0
+            },
+            max_ms: self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+            total_seconds: total_micros as f64 / 1e6,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LatencySnapshot {
+    pub count: u64,
+    pub mean_ms: f64,
+    pub max_ms: f64,
+    pub total_seconds: f64,
+}
+
+/// Latency of the predictions answered by the service
+///
+/// Predictions with test-time augmentation are tracked apart, since every image runs
+/// once per view.
+#[derive(Debug, Default)]
+pub struct InferenceMetrics {
+    latency: LatencyStats,
+    augmented_latency: LatencyStats,
+    /// Views run for augmented predictions, including the original images
+    augmented_views: AtomicU64,
+}
+
+impl InferenceMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a prediction averaged over `views`, 1 without test-time augmentation
+    pub fn record_prediction(&self, views: usize, latency: Duration) {
+        if views > 1 {
+            self.augmented_latency.record(latency);
+            self.augmented_views
+                .fetch_add(views as u64, Ordering::Relaxed);
+        } else {
+            self.latency.record(latency);
+        }
+    }
+
+    pub fn snapshot(&self) -> InferenceSnapshot {
+        InferenceSnapshot {
+            latency: self.latency.snapshot(),
+            augmented_latency: self.augmented_latency.snapshot(),
+            augmented_views: self.augmented_views.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InferenceSnapshot {
+    pub latency: LatencySnapshot,
+    pub augmented_latency: LatencySnapshot,
+    pub augmented_views: u64,
+}
+
+impl InferenceSnapshot {
+    /// Render the snapshot in the Prometheus text exposition format
+    pub fn render_prometheus(&self, out: &mut String) {
+        let _ = writeln!(
+            out,
+            "# HELP mnist_inference_latency_seconds Prediction latency by test-time augmentation\n\
+             # TYPE mnist_inference_latency_seconds summary"
+        );
+        for (augmentation, latency) in [("none", &self.latency), ("tta", &self.augmented_latency)] {
+            let _ = writeln!(
+                out,
+                "mnist_inference_latency_seconds_sum{{augmentation=\"{augmentation}\"}} {}\n\
+                 mnist_inference_latency_seconds_count{{augmentation=\"{augmentation}\"}} {}",
+                latency.total_seconds, latency.count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "# HELP mnist_inference_augmented_views_total Views run for predictions with test-time augmentation\n\
+             # TYPE mnist_inference_augmented_views_total counter\n\
+             mnist_inference_augmented_views_total {}",
+            self.augmented_views
+        );
+    }
+}
+
+/// Comparison of the served model against a candidate model
+#[derive(Debug, Default)]
+pub struct RolloutMetrics {
+    /// Requests answered by the candidate in canary mode
+    canary_requests: AtomicU64,
+    /// Requests run on both models
+    compared: AtomicU64,
+    agreed: AtomicU64,
+    /// Failed predictions of the model running in the background
+    shadow_errors: AtomicU64,
+    /// Sampled requests not compared because too many background predictions were running
+    skipped: AtomicU64,
+    /// Comparisons and disagreements, by digit predicted by the primary model
+    compared_by_class: [AtomicU64; NUM_CLASSES],
+    disagreed_by_class: [AtomicU64; NUM_CLASSES],
+    primary_latency: LatencyStats,
+    candidate_latency: LatencyStats,
+}
+
+impl RolloutMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_canary(&self) {
+        self.canary_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_shadow_error(&self) {
+        self.shadow_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_skipped(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_primary_latency(&self, latency: Duration) {
+        self.primary_latency.record(latency);
+    }
+
+    pub fn record_candidate_latency(&self, latency: Duration) {
+        self.candidate_latency.record(latency);
+    }
+
+    /// Record the digits both models predicted for the same image
+    ///
+    /// Returns the number of comparisons recorded so far.
+    pub fn record_comparison(&self, primary: u32, candidate: u32) -> u64 {
+        let compared = self.compared.fetch_add(1, Ordering::Relaxed) + 1;
+        let class = (primary as usize).min(NUM_CLASSES - 1);
+        self.compared_by_class[class].fetch_add(1, Ordering::Relaxed);
+        if primary == candidate {
+            self.agreed.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.disagreed_by_class[class].fetch_add(1, Ordering::Relaxed);
+        }
+        compared
+    }
+
+    pub fn snapshot(&self) -> RolloutSnapshot {
+        let compared = self.compared.load(Ordering::Relaxed);
+        let agreed = self.agreed.load(Ordering::Relaxed);
+        RolloutSnapshot {
+            canary_requests: self.canary_requests.load(Ordering::Relaxed),
+            compared,
+            agreed,
+            agreement_rate: if compared == 0 {
+                1.0
+            } else {
+                agreed as f64 / compared as f64
+            },
+            shadow_errors: self.shadow_errors.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            classes: (0..NUM_CLASSES)
+                .map(|class| ClassAgreement {
+                    class: class as u32,
+                    compared: self.compared_by_class[class].load(Ordering::Relaxed),
+                    disagreed: self.disagreed_by_class[class].load(Ordering::Relaxed),
+                })
+                .collect(),
+            primary_latency: self.primary_latency.snapshot(),
+            candidate_latency: self.candidate_latency.snapshot(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RolloutSnapshot {
+    pub canary_requests: u64,
+    pub compared: u64,
+    pub agreed: u64,
+    /// Fraction of compared requests where both models predicted the same digit
+    pub agreement_rate: f64,
+    pub shadow_errors: u64,
+    /// Sampled requests skipped while the background predictions were saturated
+    pub skipped: u64,
+    pub classes: Vec<ClassAgreement>,
+    pub primary_latency: LatencySnapshot,
+    pub candidate_latency: LatencySnapshot,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClassAgreement {
+    /// Digit predicted by the primary model
+    pub class: u32,
+    pub compared: u64,
+    pub disagreed: u64,
+}
+
+impl RolloutSnapshot {
+    /// Render the snapshot in the Prometheus text exposition format
+    pub fn render_prometheus(&self, out: &mut String) {
+        let mut counter = |name: &str, help: &str, value: u64| {
+            let _ = writeln!(
+                out,
+                "# HELP {name} {help}\n# TYPE {name} counter\n{name} {value}"
+            );
+        };
+        counter(
+            "mnist_rollout_canary_requests_total",
+            "Requests answered by the candidate model",
+            self.canary_requests,
+        );
+        counter(
+            "mnist_rollout_compared_total",
+            "Requests predicted by both the primary and the candidate model",
+            self.compared,
+        );
+        counter(
+            "mnist_rollout_agreed_total",
+            "Compared requests where both models predicted the same digit",
+            self.agreed,
+        );
+        counter(
+            "mnist_rollout_shadow_errors_total",
+            "Failed predictions of the model running in the background",
+            self.shadow_errors,
+        );
+        counter(
+            "mnist_rollout_skipped_total",
+            "Sampled requests not compared because too many background predictions were running",
+            self.skipped,
+        );
+
+        let _ = writeln!(
+            out,
+            "# HELP mnist_rollout_disagreed_total Compared requests with different digits, by primary digit\n\
+             # TYPE mnist_rollout_disagreed_total counter"
+        );
+        for class in &self.classes {
+            let _ = writeln!(
+                out,
+                "mnist_rollout_disagreed_total{{class=\"{}\"}} {}",
+                class.class, class.disagreed
+            );
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP mnist_rollout_latency_seconds Inference latency by model\n\
+             # TYPE mnist_rollout_latency_seconds summary"
+        );
+        for (model, latency) in [
+            ("primary", &self.primary_latency),
+            ("candidate", &self.candidate_latency),
+        ] {
+            let _ = writeln!(
+                out,
+                "mnist_rollout_latency_seconds_sum{{model=\"{model}\"}} {}\n\
+                 mnist_rollout_latency_seconds_count{{model=\"{model}\"}} {}",
+                latency.total_seconds, latency.count
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inference_metrics() {
+        let metrics = InferenceMetrics::new();
+        metrics.record_prediction(1, Duration::from_millis(1));
+        metrics.record_prediction(9, Duration::from_millis(6));
+        metrics.record_prediction(9, Duration::from_millis(8));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.latency.count, 1);
+        assert_eq!(snapshot.augmented_latency.count, 2);
+        assert!((snapshot.augmented_latency.mean_ms - 7.0).abs() < 1e-9);
+        assert_eq!(snapshot.augmented_views, 18);
+
+        let mut text = String::new();
+        snapshot.render_prometheus(&mut text);
+        assert!(text.contains("mnist_inference_latency_seconds_count{augmentation=\"tta\"} 2"));
+        assert!(
+            text.contains("mnist_inference_augmented_views_total 18"),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn test_rollout_metrics() {
+        let metrics = RolloutMetrics::new();
+        metrics.record_comparison(3, 3);
+        metrics.record_comparison(3, 5);
+        metrics.record_comparison(7, 7);
+        metrics.record_primary_latency(Duration::from_millis(2));
+        metrics.record_primary_latency(Duration::from_millis(4));
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.compared, 3);
+        assert!((snapshot.agreement_rate - 2.0 / 3.0).abs() < 1e-9);
+        assert_eq!(snapshot.classes[3].compared, 2);
+        assert_eq!(snapshot.classes[3].disagreed, 1);
+        assert_eq!(snapshot.classes[7].disagreed, 0);
+        assert_eq!(snapshot.primary_latency.count, 2);
+        assert!((snapshot.primary_latency.mean_ms - 3.0).abs() < 1e-9);
+        assert!((snapshot.primary_latency.max_ms - 4.0).abs() < 1e-9);
+
+        let mut text = String::new();
+        snapshot.render_prometheus(&mut text);
+        assert!(text.contains("mnist_rollout_compared_total 3"), "{}", text);
+        assert!(text.contains("mnist_rollout_disagreed_total{class=\"3\"} 1"));
+        assert!(text.contains("mnist_rollout_latency_seconds_count{model=\"primary\"} 2"));
+    }
+}
@@ -0,0 +1,586 @@
+//! Open Inference Protocol (KServe V2) next to the `mnist.Mnist` service
+//!
+//! The served model takes a single `input` tensor of images and returns the predicted
+//! digits with their probabilities and logits:
+//!
+//! ```text
+//! input          FP32 or UINT8   [-1, <input shape>]   pixels in [0, 1] or [0, 255]
+//! label          INT64           [-1]
+//! probabilities  FP32            [-1, <classes>]
+//! logits         FP32            [-1, <classes>]
+//! ```
+//!
+//! The input shape and the number of classes are the ones of the served model.
+//! Tensors are read from `contents` or `raw_input_contents`; the outputs are returned in
+//! the same form as the inputs. Requests are answered by the served model, candidates of
+//! a rollout only see `mnist.Mnist` traffic.
+use std::sync::Arc;
+
+use tonic::{Request, Response, Status};
+
+use crate::inference_engine::{INPUT_SIZE, InferenceEngine, Prediction};
+use crate::proto::inference::grpc_inference_service_server::GrpcInferenceService;
+use crate::proto::inference::model_infer_request::InferInputTensor;
+use crate::proto::inference::model_infer_response::InferOutputTensor;
+use crate::proto::inference::model_metadata_response::TensorMetadata;
+use crate::proto::inference::{
+    InferTensorContents, ModelInferRequest, ModelInferResponse, ModelMetadataRequest,
+    ModelMetadataResponse, ModelReadyRequest, ModelReadyResponse, ServerLiveRequest,
+    ServerLiveResponse, ServerMetadataRequest, ServerMetadataResponse, ServerReadyRequest,
+    ServerReadyResponse,
+};
+use crate::service::MnistService;
+use crate::{Error, Result};
+
+/// Name of the image tensor
+pub const INPUT_NAME: &str = "input";
+
+/// Datatypes accepted for the image tensor
+const INPUT_DATATYPES: [&str; 2] = ["FP32", "UINT8"];
+
+/// `GRPCInferenceService` answered by the model of an [`MnistService`]
+#[derive(Debug, Clone)]
+pub struct OpenInferenceService {
+    service: Arc<MnistService>,
+}
+
+/// Tensors returned by `ModelInfer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Output {
+    Label,
+    Probabilities,
+    Logits,
+}
+
+impl Output {
+    const ALL: [Output; 3] = [Output::Label, Output::Probabilities, Output::Logits];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Output::Label => "label",
+            Output::Probabilities => "probabilities",
+            Output::Logits => "logits",
+        }
+    }
+
+    fn datatype(&self) -> &'static str {
+        match self {
+            Output::Label => "INT64",
+            Output::Probabilities | Output::Logits => "FP32",
+        }
+    }
+
+    /// Shape for `batch` images, -1 for a variable batch size
+    fn shape(&self, batch: i64, num_classes: usize) -> Vec<i64> {
+        match self {
+            Output::Label => vec![batch],
+            Output::Probabilities | Output::Logits => vec![batch, num_classes as i64],
+        }
+    }
+
+    fn from_name(name: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|output| output.name() == name)
+            .ok_or_else(|| Error::invalid_argument(format!("Unknown output tensor '{}'", name)))
+    }
+
+    fn tensor(
+        &self,
+        predictions: &[Prediction],
+        num_classes: usize,
+        raw: bool,
+    ) -> (InferOutputTensor, Vec<u8>) {
+        let mut contents = InferTensorContents::default();
+        match self {
+            Output::Label => {
+                contents.int64_contents = predictions.iter().map(|p| p.digit as i64).collect();
+            }
+            Output::Probabilities => {
+                contents.fp32_contents = predictions
+                    .iter()
+                    .flat_map(|p| p.probabilities.iter().copied())
+                    .collect();
+            }
+            Output::Logits => {
+                contents.fp32_contents = predictions
+                    .iter()
+                    .flat_map(|p| p.logits.iter().copied())
+                    .collect();
+            }
+        }
+        let bytes = if raw {
+            let bytes = match self {
+                Output::Label => contents
+                    .int64_contents
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect(),
+                Output::Probabilities | Output::Logits => contents
+                    .fp32_contents
+                    .iter()
+                    .flat_map(|value| value.to_le_bytes())
+                    .collect(),
+            };
+            contents = InferTensorContents::default();
+            bytes
+        } else {
+            Vec::new()
+        };
+        let tensor = InferOutputTensor {
+            name: self.name().to_string(),
+            datatype: self.datatype().to_string(),
+            shape: self.shape(predictions.len() as i64, num_classes),
+            parameters: Default::default(),
+            contents: (!raw).then_some(contents),
+        };
+        (tensor, bytes)
+    }
+}
+
+impl OpenInferenceService {
+    pub fn new(service: Arc<MnistService>) -> Self {
+        Self { service }
+    }
+
+    fn engine(&self) -> &InferenceEngine {
+        self.service.inference_engine()
+    }
+
+    /// Check that a request addresses the served model; it is not versioned
+    fn check_model(&self, name: &str, version: &str) -> Result<()> {
+        if name != self.service.model_name() {
+            return Err(Error::ModelNotFound(name.to_string()));
+        }
+        if !version.is_empty() {
+            return Err(Error::ModelNotFound(format!(
+                "{} version {}",
+                name, version
+            )));
+        }
+        Ok(())
+    }
+
+    fn metadata(&self) -> ModelMetadataResponse {
+        let mut input_shape = vec![-1];
+        input_shape.extend(self.engine().input_shape().iter().map(|&dim| dim as i64));
+        let architecture = format!("{:?}", self.engine().architecture()).to_lowercase();
+        let num_classes = self.engine().num_classes();
+        ModelMetadataResponse {
+            name: self.service.model_name().to_string(),
+            versions: Vec::new(),
+            platform: format!("candle_{}", architecture),
+            // A tensor has a single datatype in the metadata, so the input is listed once
+            // per accepted datatype
+            inputs: INPUT_DATATYPES
+                .into_iter()
+                .map(|datatype| TensorMetadata {
+                    name: INPUT_NAME.to_string(),
+                    datatype: datatype.to_string(),
+                    shape: input_shape.clone(),
+                })
+                .collect(),
+            outputs: Output::ALL
+                .into_iter()
+                .map(|output| TensorMetadata {
+                    name: output.name().to_string(),
+                    datatype: output.datatype().to_string(),
+                    shape: output.shape(-1, num_classes),
+                })
+                .collect(),
+        }
+    }
+
+    fn infer(&self, request: ModelInferRequest) -> Result<ModelInferResponse> {
+        self.check_model(&request.model_name, &request.model_version)?;
+        let raw = !request.raw_input_contents.is_empty();
+        if raw && request.raw_input_contents.len() != request.inputs.len() {
+            return Err(Error::invalid_argument(format!(
+                "Expected raw contents for {} inputs, got {}",
+                request.inputs.len(),
+                request.raw_input_contents.len()
+            )));
+        }
+        let [input] = request.inputs.as_slice() else {
+            return Err(Error::invalid_argument(format!(
+                "Expected a single '{}' tensor, got {} inputs",
+                INPUT_NAME,
+                request.inputs.len()
+            )));
+        };
+        if input.name != INPUT_NAME {
+            return Err(Error::invalid_argument(format!(
+                "Unknown input tensor '{}', expected '{}'",
+                input.name, INPUT_NAME
+            )));
+        }
+        let outputs = if request.outputs.is_empty() {
+            Output::ALL.to_vec()
+        } else {
+            request
+                .outputs
+                .iter()
+                .map(|output| Output::from_name(&output.name))
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let images = input_images(
+            input,
+            request.raw_input_contents.first(),
+            self.engine().input_shape(),
+        )?;
+        let predictions = self
+            .engine()
+            .predict_batch(images, self.service.predict_defaults())?;
+
+        let num_classes = self.engine().num_classes();
+        let (outputs, raw_output_contents) = outputs
+            .iter()
+            .map(|output| output.tensor(&predictions, num_classes, raw))
+            .unzip();
+        Ok(ModelInferResponse {
+            model_name: request.model_name,
+            model_version: String::new(),
+            id: request.id,
+            parameters: Default::default(),
+            outputs,
+            raw_output_contents: if raw { raw_output_contents } else { Vec::new() },
+        })
+    }
+}
+
+/// Number of values in a tensor of the given shape, `None` if it overflows
+fn element_count(dims: &[usize]) -> Option<usize> {
+    dims.iter()
+        .try_fold(1usize, |count, &dim| count.checked_mul(dim))
+}
+
+/// Split the input tensor into images of `input_shape`, with the pixels scaled to [0, 1]
+fn input_images(
+    input: &InferInputTensor,
+    raw: Option<&Vec<u8>>,
+    input_shape: &[usize],
+) -> Result<Vec<Vec<f32>>> {
+    let dims = input
+        .shape
+        .iter()
+        .map(|&dim| usize::try_from(dim))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|_| Error::invalid_argument(format!("Invalid shape {:?}", input.shape)))?;
+    match dims.split_first() {
+        Some((&batch, image)) if batch > 0 && image == input_shape => {}
+        _ => {
+            return Err(Error::invalid_argument(format!(
+                "Shape {:?} does not hold a batch of images of shape {:?}",
+                input.shape, input_shape
+            )));
+        }
+    }
+
+    let contents = input.contents.as_ref();
+    if raw.is_some() && contents.is_some() {
+        return Err(Error::invalid_argument(
+            "Input contents must not be set together with raw input contents",
+        ));
+    }
+    let pixels: Vec<f32> = match (input.datatype.as_str(), raw) {
+        ("FP32", Some(raw)) => {
+            if raw.len() % 4 != 0 {
+                return Err(Error::invalid_argument(format!(
+                    "Raw FP32 contents of {} bytes are not a multiple of 4",
+                    raw.len()
+                )));
+            }
+            raw.chunks_exact(4)
+                .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect()
+        }
+        ("FP32", None) => contents
+            .map(|contents| contents.fp32_contents.clone())
+            .unwrap_or_default(),
+        ("UINT8", Some(raw)) => raw.iter().map(|&pixel| pixel as f32 / 255.0).collect(),
+        ("UINT8", None) => contents
+            .map(|contents| contents.uint_contents.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .map(|&pixel| {
+                u8::try_from(pixel)
+                    .map(|pixel| pixel as f32 / 255.0)
+                    .map_err(|_| Error::invalid_argument(format!("Invalid UINT8 value {}", pixel)))
+            })
+            .collect::<Result<_>>()?,
+        (datatype, _) => {
+            return Err(Error::invalid_argument(format!(
+                "Unsupported datatype {}, expected one of {:?}",
+                datatype, INPUT_DATATYPES
+            )));
+        }
+    };
+
+    let expected = element_count(&dims).ok_or_else(|| {
+        Error::invalid_argument(format!("Shape {:?} holds too many values", input.shape))
+    })?;
+    if pixels.len() != expected {
+        return Err(Error::invalid_argument(format!(
+            "Shape {:?} holds {} values, got {}",
+            input.shape,
+            expected,
+            pixels.len()
+        )));
+    }
+    // The engine only serves input shapes of INPUT_SIZE values
+    Ok(pixels
+        .chunks_exact(INPUT_SIZE)
+        .map(|image| image.to_vec())
+        .collect())
+}
+
+#[tonic::async_trait]
+impl GrpcInferenceService for OpenInferenceService {
+    async fn server_live(
+        &self,
+        _request: Request<ServerLiveRequest>,
+    ) -> std::result::Result<Response<ServerLiveResponse>, Status> {
+        Ok(Response::new(ServerLiveResponse { live: true }))
+    }
+
+    /// The model is loaded before the server starts listening
+    async fn server_ready(
+        &self,
+        _request: Request<ServerReadyRequest>,
+    ) -> std::result::Result<Response<ServerReadyResponse>, Status> {
+        Ok(Response::new(ServerReadyResponse { ready: true }))
+    }
+
+    async fn model_ready(
+        &self,
+        request: Request<ModelReadyRequest>,
+    ) -> std::result::Result<Response<ModelReadyResponse>, Status> {
+        let request = request.into_inner();
+        let ready = self.check_model(&request.name, &request.version).is_ok();
+        Ok(Response::new(ModelReadyResponse { ready }))
+    }
+
+    async fn server_metadata(
+        &self,
+        _request: Request<ServerMetadataRequest>,
+    ) -> std::result::Result<Response<ServerMetadataResponse>, Status> {
+        Ok(Response::new(ServerMetadataResponse {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            extensions: Vec::new(),
+        }))
+    }
+
+    async fn model_metadata(
+        &self,
+        request: Request<ModelMetadataRequest>,
+    ) -> std::result::Result<Response<ModelMetadataResponse>, Status> {
+        let request = request.into_inner();
+        self.check_model(&request.name, &request.version)?;
+        Ok(Response::new(self.metadata()))
+    }
+
+    async fn model_infer(
+        &self,
+        request: Request<ModelInferRequest>,
+    ) -> std::result::Result<Response<ModelInferResponse>, Status> {
+        Ok(Response::new(self.infer(request.into_inner())?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServiceConfig;
+    use crate::inference_engine::ModelArchitecture;
+    use crate::inference_engine::weights_provider::LocalFileProvider;
+    use crate::proto::inference::model_infer_request::InferRequestedOutputTensor;
+    use crate::test_util::random_weights;
+    use candle_core::{DType, Device};
+    use tonic::Code;
+
+    fn service() -> OpenInferenceService {
+        let (_dir, path) = random_weights(ModelArchitecture::Conv);
+        let config = ServiceConfig::new(
+            Device::Cpu,
+            DType::F32,
+            LocalFileProvider::new(path),
+            Some(ModelArchitecture::Conv),
+        );
+        OpenInferenceService::new(Arc::new(MnistService::new(config).unwrap()))
+    }
+
+    fn input(
+        datatype: &str,
+        shape: Vec<i64>,
+        contents: Option<InferTensorContents>,
+    ) -> InferInputTensor {
+        InferInputTensor {
+            name: INPUT_NAME.to_string(),
+            datatype: datatype.to_string(),
+            shape,
+            parameters: Default::default(),
+            contents,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_model_metadata() {
+        let service = service();
+        let metadata = service
+            .model_metadata(Request::new(ModelMetadataRequest {
+                name: "mnist".to_string(),
+                version: String::new(),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(metadata.platform, "candle_conv");
+        let inputs: Vec<_> = metadata
+            .inputs
+            .iter()
+            .map(|input| (input.name.as_str(), input.datatype.as_str()))
+            .collect();
+        assert_eq!(inputs, vec![(INPUT_NAME, "FP32"), (INPUT_NAME, "UINT8")]);
+        assert!(
+            metadata
+                .inputs
+                .iter()
+                .all(|input| input.shape == vec![-1, 1, 28, 28])
+        );
+        let outputs: Vec<_> = metadata.outputs.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(outputs, vec!["label", "probabilities", "logits"]);
+        assert_eq!(metadata.outputs[1].shape, vec![-1, 10]);
+
+        let status = service
+            .model_metadata(Request::new(ModelMetadataRequest {
+                name: "other".to_string(),
+                version: String::new(),
+            }))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let ready = |name: &str| ModelReadyRequest {
+            name: name.to_string(),
+            version: String::new(),
+        };
+        assert!(
+            service
+                .model_ready(Request::new(ready("mnist")))
+                .await
+                .unwrap()
+                .into_inner()
+                .ready
+        );
+        assert!(
+            !service
+                .model_ready(Request::new(ready("other")))
+                .await
+                .unwrap()
+                .into_inner()
+                .ready
+        );
+    }
+
+    #[tokio::test]
+    async fn test_model_infer_contents() {
+        let service = service();
+        let request = ModelInferRequest {
+            model_name: "mnist".to_string(),
+            id: "42".to_string(),
+            inputs: vec![input(
+                "FP32",
+                vec![2, 1, 28, 28],
+                Some(InferTensorContents {
+                    fp32_contents: vec![0.5; 2 * INPUT_SIZE],
+                    ..Default::default()
+                }),
+            )],
+            ..Default::default()
+        };
+        let response = service
+            .model_infer(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.id, "42");
+        assert!(response.raw_output_contents.is_empty());
+        assert_eq!(response.outputs.len(), 3);
+        let label = &response.outputs[0];
+        assert_eq!(label.shape, vec![2]);
+        assert_eq!(label.contents.as_ref().unwrap().int64_contents.len(), 2);
+        let probabilities = &response.outputs[1];
+        assert_eq!(probabilities.shape, vec![2, 10]);
+        let values = &probabilities.contents.as_ref().unwrap().fp32_contents;
+        assert_eq!(values.len(), 20);
+        assert!((values[..10].iter().sum::<f32>() - 1.0).abs() < 1e-4);
+    }
+
+    #[tokio::test]
+    async fn test_model_infer_raw() {
+        let service = service();
+        let request = ModelInferRequest {
+            model_name: "mnist".to_string(),
+            inputs: vec![input("UINT8", vec![1, 1, 28, 28], None)],
+            outputs: vec![InferRequestedOutputTensor {
+                name: "label".to_string(),
+                parameters: Default::default(),
+            }],
+            raw_input_contents: vec![vec![128; INPUT_SIZE]],
+            ..Default::default()
+        };
+        let response = service
+            .model_infer(Request::new(request))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(response.outputs.len(), 1);
+        assert_eq!(response.outputs[0].name, "label");
+        assert!(response.outputs[0].contents.is_none());
+        let raw = &response.raw_output_contents[0];
+        assert_eq!(raw.len(), 8);
+        assert!(i64::from_le_bytes(raw[..].try_into().unwrap()) < 10);
+    }
+
+    #[tokio::test]
+    async fn test_model_infer_invalid() {
+        let service = service();
+        let infer = |input: InferInputTensor| {
+            let service = service.clone();
+            async move {
+                let request = ModelInferRequest {
+                    model_name: "mnist".to_string(),
+                    inputs: vec![input],
+                    ..Default::default()
+                };
+                service
+                    .model_infer(Request::new(request))
+                    .await
+                    .unwrap_err()
+            }
+        };
+        let contents = Some(InferTensorContents {
+            fp32_contents: vec![0.0; INPUT_SIZE],
+            ..Default::default()
+        });
+        for input in [
+            input("FP32", vec![1, 27, 28], contents.clone()),
+            // The pixels of an image, but not in the input shape of the model
+            input("FP32", vec![1, 784], contents.clone()),
+            input("FP32", vec![1, 28, 28], contents.clone()),
+            input("FP32", vec![2, 1, 28, 28], contents.clone()),
+            input("FP64", vec![1, 1, 28, 28], contents.clone()),
+            // Shapes whose number of values overflows
+            input("FP32", vec![1, 1 << 62, 1 << 62], contents.clone()),
+            input("FP32", vec![i64::MAX, 1, 28, 28], contents.clone()),
+            InferInputTensor {
+                name: "image".to_string(),
+                ..input("FP32", vec![1, 1, 28, 28], contents.clone())
+            },
+        ] {
+            assert_eq!(infer(input).await.code(), Code::InvalidArgument);
+        }
+    }
+}
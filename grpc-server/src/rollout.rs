@@ -0,0 +1,360 @@
+//! Comparison of a candidate model against the served one on live traffic
+//!
+//! A sampled share of the `Predict` requests is run on both models. In shadow mode the
+//! served model answers and the candidate runs in the background; in canary mode the
+//! candidate answers and the served model runs in the background, so agreement is tracked
+//! in both modes. The background prediction never reaches the client.
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use derive_more::Display;
+use mnist::ModelSpec;
+use tokio::sync::Semaphore;
+
+use crate::inference_engine::weights_provider::LocalFileProvider;
+use crate::inference_engine::{InferenceEngine, ModelArchitecture, PredictOptions};
+use crate::metrics::RolloutMetrics;
+use crate::{Error, Result};
+
+/// A summary of the comparison is logged every `SUMMARY_INTERVAL` compared requests
+const SUMMARY_INTERVAL: u64 = 100;
+
+/// Background predictions that may run at once, further sampled requests are not compared
+const MAX_PENDING_COMPARISONS: usize = 4;
+
+/// Model answering the sampled requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Display, ValueEnum)]
+pub enum RolloutMode {
+    /// The served model answers, the candidate only runs in the background
+    #[default]
+    #[display("shadow")]
+    Shadow,
+    /// The candidate answers, the served model runs in the background
+    #[display("canary")]
+    Canary,
+}
+
+/// Candidate model and share of the traffic it receives
+#[derive(Debug, Clone)]
+pub struct CandidateConfig {
+    pub weights_provider: LocalFileProvider,
+    /// Architecture of the candidate, taken from its manifest if not set
+    pub model_architecture: Option<ModelArchitecture>,
+    /// Layers of a sequential candidate, taken from its manifest if not set
+    pub model_spec: Option<ModelSpec>,
+    pub mode: RolloutMode,
+    /// Percentage of the requests sent to the candidate, between 0 and 100
+    pub percent: f32,
+}
+
+impl CandidateConfig {
+    pub fn new(
+        weights_provider: LocalFileProvider,
+        mode: RolloutMode,
+        percent: f32,
+    ) -> Result<Self> {
+        if !(0.0..=100.0).contains(&percent) {
+            return Err(Error::invalid_argument(format!(
+                "rollout percentage must be between 0 and 100, got {}",
+                percent
+            )));
+        }
+        Ok(Self {
+            weights_provider,
+            model_architecture: None,
+            model_spec: None,
+            mode,
+            percent,
+        })
+    }
+
+    pub fn with_model_architecture(mut self, arch: ModelArchitecture) -> Self {
+        self.model_architecture = Some(arch);
+        self
+    }
+
+    pub fn with_model_spec(mut self, spec: ModelSpec) -> Self {
+        self.model_spec = Some(spec);
+        self
+    }
+}
+
+/// Selects an exact share of the requests, evenly spread over time
+///
+/// Request `n` is selected when `floor((n + 1) * p)` exceeds `floor(n * p)`, so out of
+/// any 10000 consecutive requests at most one more or less than `p * 10000` are selected.
+#[derive(Debug)]
+struct Sampler {
+    /// Selected share in hundredths of a percent
+    basis_points: u64,
+    requests: AtomicU64,
+}
+
+impl Sampler {
+    fn new(percent: f32) -> Self {
+        Self {
+            basis_points: (percent * 100.0).round() as u64,
+            requests: AtomicU64::new(0),
+        }
+    }
+
+    fn sample(&self) -> bool {
+        let n = self.requests.fetch_add(1, Ordering::Relaxed);
+        (n + 1) * self.basis_points / 10_000 > n * self.basis_points / 10_000
+    }
+}
+
+/// Candidate model receiving a share of the traffic
+#[derive(Debug)]
+pub struct Rollout {
+    candidate: Arc<InferenceEngine>,
+    mode: RolloutMode,
+    sampler: Sampler,
+    /// Permits for the background predictions, so a slow model cannot queue up work
+    comparisons: Arc<Semaphore>,
+    metrics: Arc<RolloutMetrics>,
+}
+
+impl Rollout {
+    pub fn new(candidate: InferenceEngine, mode: RolloutMode, percent: f32) -> Self {
+        Self {
+            candidate: Arc::new(candidate),
+            mode,
+            sampler: Sampler::new(percent),
+            comparisons: Arc::new(Semaphore::new(MAX_PENDING_COMPARISONS)),
+            metrics: Arc::new(RolloutMetrics::new()),
+        }
+    }
+
+    pub fn mode(&self) -> RolloutMode {
+        self.mode
+    }
+
+    pub fn candidate(&self) -> &Arc<InferenceEngine> {
+        &self.candidate
+    }
+
+    pub fn metrics(&self) -> &Arc<RolloutMetrics> {
+        &self.metrics
+    }
+
+    /// Decide whether the next request is part of the rollout
+    pub fn sample(&self) -> bool {
+        self.sampler.sample()
+    }
+
+    /// Run a sampled request on the model that did not answer it and record the comparison
+    ///
+    /// The prediction runs on the blocking thread pool when called from a Tokio runtime,
+    /// so the response is not delayed by it. When `MAX_PENDING_COMPARISONS` predictions are
+    /// already running, the request is skipped and counted instead.
+    pub fn mirror(
+        &self,
+        primary: &Arc<InferenceEngine>,
+        input: Vec<f32>,
+        options: PredictOptions,
+        served_digit: u32,
+        served_latency: Duration,
+    ) {
+        let engine = match self.mode {
+            RolloutMode::Shadow => Arc::clone(&self.candidate),
+            RolloutMode::Canary => {
+                self.metrics.record_canary();
+                Arc::clone(primary)
+            }
+        };
+        let Ok(permit) = Arc::clone(&self.comparisons).try_acquire_owned() else {
+            self.metrics.record_skipped();
+            return;
+        };
+        let mode = self.mode;
+        let metrics = Arc::clone(&self.metrics);
+        let compare = move || {
+            let _permit = permit;
+            compare(
+                &metrics,
+                mode,
+                &engine,
+                input,
+                &options,
+                served_digit,
+                served_latency,
+            )
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(compare);
+            }
+            Err(_) => compare(),
+        }
+    }
+}
+
+/// Predict with the background model and record how it compares to the served prediction
+fn compare(
+    metrics: &RolloutMetrics,
+    mode: RolloutMode,
+    engine: &InferenceEngine,
+    input: Vec<f32>,
+    options: &PredictOptions,
+    served_digit: u32,
+    served_latency: Duration,
+) {
+    let started = Instant::now();
+    let prediction = match engine.predict_with(input, options) {
+        Ok(prediction) => prediction,
+        Err(e) => {
+            metrics.record_shadow_error();
+            tracing::warn!(target: "rollout", %mode, error = %e, "Background prediction failed");
+            return;
+        }
+    };
+    let latency = started.elapsed();
+
+    let (primary_digit, primary_latency, candidate_digit, candidate_latency) = match mode {
+        RolloutMode::Shadow => (served_digit, served_latency, prediction.digit, latency),
+        RolloutMode::Canary => (prediction.digit, latency, served_digit, served_latency),
+    };
+    metrics.record_primary_latency(primary_latency);
+    metrics.record_candidate_latency(candidate_latency);
+    let compared = metrics.record_comparison(primary_digit, candidate_digit);
+    tracing::info!(
+        target: "rollout",
+        %mode,
+        primary_digit,
+        candidate_digit,
+        agreed = primary_digit == candidate_digit,
+        primary_latency_us = primary_latency.as_micros() as u64,
+        candidate_latency_us = candidate_latency.as_micros() as u64,
+        "Compared candidate model"
+    );
+
+    if compared.is_multiple_of(SUMMARY_INTERVAL) {
+        log_summary(metrics);
+    }
+}
+
+/// Log the agreement and latency of the candidate so far
+pub fn log_summary(metrics: &RolloutMetrics) {
+    let snapshot = metrics.snapshot();
+    let disagreements = snapshot
+        .classes
+        .iter()
+        .map(|class| class.disagreed.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    tracing::info!(
+        target: "rollout",
+        compared = snapshot.compared,
+        agreement_rate = snapshot.agreement_rate,
+        canary_requests = snapshot.canary_requests,
+        shadow_errors = snapshot.shadow_errors,
+        skipped = snapshot.skipped,
+        disagreements_by_class = %disagreements,
+        primary_latency_ms = snapshot.primary_latency.mean_ms,
+        candidate_latency_ms = snapshot.candidate_latency.mean_ms,
+        "Candidate model summary"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::random_weights;
+
+    fn random_engine() -> InferenceEngine {
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        InferenceEngine::builder()
+            .model_architecture(ModelArchitecture::MLP)
+            .build(LocalFileProvider::new(path))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_sampler() {
+        let sampler = Sampler::new(12.5);
+        let selected = (0..800).filter(|_| sampler.sample()).count();
+        assert_eq!(selected, 100);
+
+        assert!((0..100).all(|_| Sampler::new(100.0).sample()));
+        assert!(!(0..100).any(|_| Sampler::new(0.0).sample()));
+    }
+
+    #[test]
+    fn test_candidate_percent() {
+        let provider = LocalFileProvider::new("candidate.safetensors".into());
+        assert!(CandidateConfig::new(provider.clone(), RolloutMode::Shadow, 10.0).is_ok());
+        assert!(CandidateConfig::new(provider, RolloutMode::Canary, 150.0).is_err());
+    }
+
+    #[test]
+    fn test_mirror() {
+        let primary = Arc::new(random_engine());
+        let input = vec![0.5; 784];
+        let served = primary.predict(input.clone()).unwrap();
+
+        // Without a runtime the comparison runs inline
+        let rollout = Rollout::new(random_engine(), RolloutMode::Shadow, 100.0);
+        rollout.mirror(
+            &primary,
+            input.clone(),
+            PredictOptions::default(),
+            served.digit,
+            Duration::from_millis(1),
+        );
+        let snapshot = rollout.metrics().snapshot();
+        assert_eq!(snapshot.compared, 1);
+        assert_eq!(snapshot.canary_requests, 0);
+        assert_eq!(snapshot.primary_latency.mean_ms, 1.0);
+
+        // In canary mode the primary model runs in the background and agrees with itself
+        let rollout = Rollout::new(random_engine(), RolloutMode::Canary, 100.0);
+        rollout.mirror(
+            &primary,
+            input,
+            PredictOptions::default(),
+            served.digit,
+            Duration::from_millis(1),
+        );
+        let snapshot = rollout.metrics().snapshot();
+        assert_eq!(snapshot.canary_requests, 1);
+        assert_eq!(snapshot.agreed, 1);
+        assert_eq!(snapshot.candidate_latency.mean_ms, 1.0);
+    }
+
+    #[test]
+    fn test_mirror_saturated() {
+        let primary = Arc::new(random_engine());
+        let rollout = Rollout::new(random_engine(), RolloutMode::Shadow, 100.0);
+        let permits = rollout
+            .comparisons
+            .try_acquire_many(MAX_PENDING_COMPARISONS as u32)
+            .unwrap();
+
+        // Every permit is taken, so the comparison is skipped
+        rollout.mirror(
+            &primary,
+            vec![0.5; 784],
+            PredictOptions::default(),
+            0,
+            Duration::from_millis(1),
+        );
+        let snapshot = rollout.metrics().snapshot();
+        assert_eq!(snapshot.compared, 0);
+        assert_eq!(snapshot.skipped, 1);
+
+        drop(permits);
+        rollout.mirror(
+            &primary,
+            vec![0.5; 784],
+            PredictOptions::default(),
+            0,
+            Duration::from_millis(1),
+        );
+        let snapshot = rollout.metrics().snapshot();
+        assert_eq!(snapshot.compared, 1);
+        assert_eq!(snapshot.skipped, 1);
+    }
+}
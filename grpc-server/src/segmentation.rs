@@ -0,0 +1,468 @@
+//! Segmentation of an image of a number into MNIST-style digits
+//!
+//! The ink is separated from the background with Otsu's threshold, whatever the polarity
+//! of the image. Connected components of ink are then grouped into digits:
+//!
+//! 1. components stacked on top of each other are merged, e.g. the parts of a broken `5`,
+//! 2. groups much smaller than the largest one are dropped as noise,
+//! 3. groups too wide for a single digit are cut at the column with the least ink,
+//!    which separates digits written without lifting the pen.
+//!
+//! Every digit is then normalized like the MNIST dataset: scaled to fit a 20x20 box and
+//! centered by its center of mass in a 28x28 image, white on black.
+use image::{GrayImage, Luma};
+
+use crate::{Error, Result};
+
+/// Number of digits an image may hold
+pub const MAX_DIGITS: usize = 32;
+
+/// Size of the box the digits are scaled into, as in MNIST
+const DIGIT_SIZE: u32 = 20;
+const IMAGE_SIZE: u32 = 28;
+
+/// Groups with less ink than this fraction of the largest one are noise
+const MIN_AREA_FRACTION: f32 = 0.1;
+/// Components with less ink than this fraction of the largest one are dropped before
+/// merging, so that dust does not stretch the box of the digit it is above or below
+const MIN_SPECK_FRACTION: f32 = 0.01;
+/// Width over height above which a component is assumed to hold several digits
+const MAX_ASPECT_RATIO: f32 = 1.25;
+/// Difference between the darkest and brightest pixel below which an image is blank
+const MIN_CONTRAST: u8 = 32;
+
+/// Rectangle in pixels, with the origin at the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A digit cut from the image
+#[derive(Debug, Clone)]
+pub struct DigitImage {
+    /// Where the digit is in the original image
+    pub bounding_box: BoundingBox,
+    /// 28x28 pixels, white digit on black as in the MNIST dataset
+    pub pixels: Vec<u8>,
+}
+
+/// Pixels of ink belonging to one digit
+#[derive(Debug, Clone)]
+struct Group {
+    /// `(x, y)` of every pixel
+    pixels: Vec<(u32, u32)>,
+    min_x: u32,
+    max_x: u32,
+    min_y: u32,
+    max_y: u32,
+}
+
+impl Group {
+    fn new(pixels: Vec<(u32, u32)>) -> Self {
+        let mut group = Self {
+            pixels,
+            min_x: u32::MAX,
+            max_x: 0,
+            min_y: u32::MAX,
+            max_y: 0,
+        };
+        for &(x, y) in &group.pixels {
+            group.min_x = group.min_x.min(x);
+            group.max_x = group.max_x.max(x);
+            group.min_y = group.min_y.min(y);
+            group.max_y = group.max_y.max(y);
+        }
+        group
+    }
+
+    fn width(&self) -> u32 {
+        self.max_x - self.min_x + 1
+    }
+
+    fn height(&self) -> u32 {
+        self.max_y - self.min_y + 1
+    }
+
+    fn bounding_box(&self) -> BoundingBox {
+        BoundingBox {
+            x: self.min_x,
+            y: self.min_y,
+            width: self.width(),
+            height: self.height(),
+        }
+    }
+
+    /// Whether the groups share at least half the width of the narrower one
+    fn overlaps(&self, other: &Group) -> bool {
+        let overlap = self.max_x.min(other.max_x) as i64 - self.min_x.max(other.min_x) as i64 + 1;
+        overlap * 2 >= self.width().min(other.width()) as i64
+    }
+
+    fn merge(&mut self, other: Group) {
+        self.min_x = self.min_x.min(other.min_x);
+        self.max_x = self.max_x.max(other.max_x);
+        self.min_y = self.min_y.min(other.min_y);
+        self.max_y = self.max_y.max(other.max_y);
+        self.pixels.extend(other.pixels);
+    }
+
+    /// Cut a group wider than a digit into digits, at the columns with the least ink
+    fn split(self) -> Vec<Group> {
+        let (width, height) = (self.width(), self.height());
+        if (width as f32) <= MAX_ASPECT_RATIO * height as f32 || width < 4 {
+            return vec![self];
+        }
+        let mut columns = vec![0usize; width as usize];
+        for &(x, _) in &self.pixels {
+            columns[(x - self.min_x) as usize] += 1;
+        }
+        // Only cut in the middle half, so that both parts keep a digit
+        let (start, end) = (width as usize / 4, width as usize * 3 / 4);
+        let cut = (start..end)
+            .min_by_key(|&column| columns[column])
+            .map_or(start, |column| column) as u32
+            + self.min_x;
+        let (left, right): (Vec<_>, Vec<_>) = self.pixels.into_iter().partition(|&(x, _)| x < cut);
+        if left.is_empty() || right.is_empty() {
+            return vec![Group::new(if left.is_empty() { right } else { left })];
+        }
+        let mut groups = Group::new(left).split();
+        groups.extend(Group::new(right).split());
+        groups
+    }
+}
+
+/// Cut the digits out of an image of a number, from left to right
+///
+/// Returns no digits for a blank image.
+pub fn segment_digits(image: &GrayImage) -> Result<Vec<DigitImage>> {
+    let Some(ink) = ink(image) else {
+        return Ok(Vec::new());
+    };
+    let mut groups = without_noise(components(&ink), MIN_SPECK_FRACTION);
+    groups.sort_by_key(|group| group.min_x);
+    let mut merged: Vec<Group> = Vec::with_capacity(groups.len());
+    for group in groups {
+        match merged.iter_mut().find(|digit| digit.overlaps(&group)) {
+            Some(digit) => digit.merge(group),
+            None => merged.push(group),
+        }
+    }
+    let mut digits: Vec<Group> = without_noise(merged, MIN_AREA_FRACTION)
+        .into_iter()
+        .flat_map(Group::split)
+        .collect();
+    if digits.len() > MAX_DIGITS {
+        return Err(Error::invalid_argument(format!(
+            "Found {} digits, at most {} are supported",
+            digits.len(),
+            MAX_DIGITS
+        )));
+    }
+    digits.sort_by_key(|group| group.min_x);
+
+    Ok(digits
+        .into_iter()
+        .map(|group| DigitImage {
+            bounding_box: group.bounding_box(),
+            pixels: normalize(&group, &ink),
+        })
+        .collect())
+}
+
+/// Drop the groups with less ink than `fraction` of the largest one
+fn without_noise(groups: Vec<Group>, fraction: f32) -> Vec<Group> {
+    let largest = groups.iter().map(|g| g.pixels.len()).max().unwrap_or(0);
+    groups
+        .into_iter()
+        .filter(|group| group.pixels.len() as f32 >= largest as f32 * fraction)
+        .collect()
+}
+
+/// Amount of ink of every pixel and whether it is part of a stroke
+struct Ink {
+    /// Intensity of the ink, high on the strokes and low on the background
+    intensity: GrayImage,
+    /// Ink above this intensity belongs to a stroke
+    threshold: u8,
+}
+
+/// Separate the ink from the background, `None` if the image is blank
+///
+/// The background is the color of most pixels, so both dark ink on paper and white
+/// digits on black are read.
+fn ink(image: &GrayImage) -> Option<Ink> {
+    let (min, max) = image.pixels().fold((u8::MAX, u8::MIN), |(min, max), p| {
+        (min.min(p[0]), max.max(p[0]))
+    });
+    if max.saturating_sub(min) < MIN_CONTRAST {
+        return None;
+    }
+    let threshold = otsu_threshold(image);
+    let bright = image.pixels().filter(|p| p[0] > threshold).count();
+    let dark_ink = bright * 2 > image.pixels().len();
+    let intensity = GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let value = image.get_pixel(x, y)[0];
+        Luma([if dark_ink { 255 - value } else { value }])
+    });
+    let threshold = if dark_ink {
+        255 - threshold - 1
+    } else {
+        threshold
+    };
+    Some(Ink {
+        intensity,
+        threshold,
+    })
+}
+
+/// Threshold maximizing the variance between the two classes of pixels
+fn otsu_threshold(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &count)| value as f64 * count as f64)
+        .sum();
+    let (mut weight_below, mut sum_below) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0, -1.0);
+    for (value, &count) in histogram.iter().enumerate() {
+        weight_below += count as f64;
+        sum_below += value as f64 * count as f64;
+        let weight_above = total - weight_below;
+        if weight_below == 0.0 || weight_above == 0.0 {
+            continue;
+        }
+        let mean_below = sum_below / weight_below;
+        let mean_above = (sum - sum_below) / weight_above;
+        let variance = weight_below * weight_above * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = value;
+        }
+    }
+    best as u8
+}
+
+/// 8-connected components of the stroke pixels
+fn components(ink: &Ink) -> Vec<Group> {
+    let (width, height) = ink.intensity.dimensions();
+    let stroke = |x: u32, y: u32| ink.intensity.get_pixel(x, y)[0] > ink.threshold;
+    let mut visited = vec![false; (width * height) as usize];
+    let mut groups = Vec::new();
+    for start_y in 0..height {
+        for start_x in 0..width {
+            let index = (start_y * width + start_x) as usize;
+            if visited[index] || !stroke(start_x, start_y) {
+                continue;
+            }
+            visited[index] = true;
+            let mut pixels = Vec::new();
+            let mut stack = vec![(start_x, start_y)];
+            while let Some((x, y)) = stack.pop() {
+                pixels.push((x, y));
+                for ny in y.saturating_sub(1)..=(y + 1).min(height - 1) {
+                    for nx in x.saturating_sub(1)..=(x + 1).min(width - 1) {
+                        let index = (ny * width + nx) as usize;
+                        if !visited[index] && stroke(nx, ny) {
+                            visited[index] = true;
+                            stack.push((nx, ny));
+                        }
+                    }
+                }
+            }
+            groups.push(Group::new(pixels));
+        }
+    }
+    groups
+}
+
+/// Draw a digit in the MNIST layout: fit into 20x20, centered by mass in 28x28
+fn normalize(group: &Group, ink: &Ink) -> Vec<u8> {
+    let (width, height) = (group.width(), group.height());
+    let mut crop = GrayImage::new(width, height);
+    for &(x, y) in &group.pixels {
+        let value = ink.intensity.get_pixel(x, y)[0];
+        crop.put_pixel(x - group.min_x, y - group.min_y, Luma([value]));
+    }
+
+    let scale = DIGIT_SIZE as f32 / width.max(height) as f32;
+    let scaled_width = ((width as f32 * scale).round() as u32).clamp(1, DIGIT_SIZE);
+    let scaled_height = ((height as f32 * scale).round() as u32).clamp(1, DIGIT_SIZE);
+    let scaled = image::imageops::resize(
+        &crop,
+        scaled_width,
+        scaled_height,
+        image::imageops::FilterType::Triangle,
+    );
+    // Strokes fade when thin digits are scaled down, MNIST strokes are saturated
+    let max = scaled.pixels().map(|p| p[0]).max().unwrap_or(0).max(1) as f32;
+
+    let (mut mass, mut mass_x, mut mass_y) = (0.0, 0.0, 0.0);
+    for (x, y, pixel) in scaled.enumerate_pixels() {
+        let value = pixel[0] as f32;
+        mass += value;
+        mass_x += value * x as f32;
+        mass_y += value * y as f32;
+    }
+    let offset = |center: f32, size: u32| {
+        let offset = (IMAGE_SIZE as f32 / 2.0 - center).round() as i64;
+        offset.clamp(0, (IMAGE_SIZE - size) as i64) as u32
+    };
+    let (center_x, center_y) = if mass > 0.0 {
+        (mass_x / mass, mass_y / mass)
+    } else {
+        (scaled_width as f32 / 2.0, scaled_height as f32 / 2.0)
+    };
+    let (offset_x, offset_y) = (
+        offset(center_x, scaled_width),
+        offset(center_y, scaled_height),
+    );
+
+    let mut digit = GrayImage::new(IMAGE_SIZE, IMAGE_SIZE);
+    for (x, y, pixel) in scaled.enumerate_pixels() {
+        let value = (pixel[0] as f32 * 255.0 / max).round().min(255.0) as u8;
+        digit.put_pixel(x + offset_x, y + offset_y, Luma([value]));
+    }
+    digit.into_raw()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Dark rectangles on a white page
+    fn page(width: u32, height: u32, rects: &[(u32, u32, u32, u32)]) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            let ink = rects
+                .iter()
+                .any(|&(rx, ry, rw, rh)| x >= rx && x < rx + rw && y >= ry && y < ry + rh);
+            Luma([if ink { 20 } else { 235 }])
+        })
+    }
+
+    fn boxes(image: &GrayImage) -> Vec<BoundingBox> {
+        segment_digits(image)
+            .unwrap()
+            .iter()
+            .map(|digit| digit.bounding_box)
+            .collect()
+    }
+
+    #[test]
+    fn test_separate_digits() {
+        // Three strokes, one speck of dust
+        let image = page(
+            100,
+            50,
+            &[
+                (70, 10, 6, 30),
+                (10, 10, 6, 30),
+                (40, 12, 6, 28),
+                (90, 45, 1, 1),
+            ],
+        );
+        let boxes = boxes(&image);
+        assert_eq!(
+            boxes,
+            vec![
+                BoundingBox {
+                    x: 10,
+                    y: 10,
+                    width: 6,
+                    height: 30
+                },
+                BoundingBox {
+                    x: 40,
+                    y: 12,
+                    width: 6,
+                    height: 28
+                },
+                BoundingBox {
+                    x: 70,
+                    y: 10,
+                    width: 6,
+                    height: 30
+                },
+            ]
+        );
+
+        // The same digits written in white on black
+        let inverted = GrayImage::from_fn(100, 50, |x, y| Luma([255 - image.get_pixel(x, y)[0]]));
+        assert_eq!(self::boxes(&inverted), boxes);
+    }
+
+    #[test]
+    fn test_merge_and_split() {
+        // A digit broken in two parts above each other, next to two touching digits
+        let image = page(
+            120,
+            50,
+            &[
+                (10, 10, 16, 5),
+                (10, 20, 16, 20),
+                (50, 10, 20, 30),
+                (70, 30, 10, 2),
+                (80, 10, 20, 30),
+            ],
+        );
+        let boxes = boxes(&image);
+        assert_eq!(boxes.len(), 3);
+        assert_eq!(
+            boxes[0],
+            BoundingBox {
+                x: 10,
+                y: 10,
+                width: 16,
+                height: 30
+            }
+        );
+        assert!(boxes[1].x == 50 && boxes[1].x + boxes[1].width <= 81);
+        assert!(boxes[2].x >= 70 && boxes[2].x + boxes[2].width == 100);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let image = page(200, 100, &[(20, 10, 30, 80)]);
+        let digits = segment_digits(&image).unwrap();
+        assert_eq!(digits.len(), 1);
+        let pixels = &digits[0].pixels;
+        assert_eq!(pixels.len(), (IMAGE_SIZE * IMAGE_SIZE) as usize);
+        assert_eq!(pixels.iter().copied().max(), Some(255));
+        // The stroke is scaled to 20 pixels high, 8 wide, and centered
+        let inked: Vec<(usize, usize)> = (0..pixels.len())
+            .filter(|&i| pixels[i] > 127)
+            .map(|i| (i % 28, i / 28))
+            .collect();
+        let (min_x, max_x) = (
+            inked.iter().map(|p| p.0).min().unwrap(),
+            inked.iter().map(|p| p.0).max().unwrap(),
+        );
+        let (min_y, max_y) = (
+            inked.iter().map(|p| p.1).min().unwrap(),
+            inked.iter().map(|p| p.1).max().unwrap(),
+        );
+        assert!((4..=5).contains(&min_y) && max_y == min_y + 19);
+        assert!((9..=11).contains(&min_x) && (16..=18).contains(&max_x));
+    }
+
+    #[test]
+    fn test_blank() {
+        assert!(segment_digits(&page(50, 50, &[])).unwrap().is_empty());
+        let noisy = GrayImage::from_fn(50, 50, |x, y| Luma([200 + ((x * 7 + y * 3) % 20) as u8]));
+        assert!(segment_digits(&noisy).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_too_many_digits() {
+        let rects: Vec<_> = (0..40).map(|i| (i * 10 + 2, 5, 4, 20)).collect();
+        assert!(segment_digits(&page(400, 30, &rects)).is_err());
+    }
+}
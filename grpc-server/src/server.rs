@@ -1,12 +1,20 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
 
-use http::{Request, Response};
+use http::{HeaderName, HeaderValue, Method, Request, Response};
 use tonic::transport::Server;
+use tonic_web::GrpcWebLayer;
+use tower::util::option_layer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing::Span;
 use uuid::Uuid;
 
-use crate::config::{ServerConfig, TracingConfig};
+use crate::config::{ServerConfig, TracingConfig, WebConfig};
+use crate::gateway;
+use crate::open_inference::OpenInferenceService;
+use crate::proto::inference::grpc_inference_service_server::GrpcInferenceServiceServer;
 use crate::proto::mnist_server::MnistServer;
 use crate::service::MnistService;
 use crate::{Error, Result};
@@ -15,14 +23,21 @@ use crate::{Error, Result};
 pub struct MnistGrpcServer {
     config: ServerConfig,
     service: MnistService,
+    /// Set if cross-origin requests are allowed
+    cors: Option<CorsLayer>,
 }
 
 impl MnistGrpcServer {
     /// Create a new server instance with the given configuration
     pub fn new(config: ServerConfig) -> Result<Self> {
+        let cors = cors_layer(&config.web)?;
         let service = MnistService::new(config.service.clone())?;
 
-        Ok(Self { config, service })
+        Ok(Self {
+            config,
+            service,
+            cors,
+        })
     }
 
     /// Initialize tracing based on the configuration
@@ -39,8 +54,27 @@ impl MnistGrpcServer {
     /// Start the server and listen for incoming requests
     pub async fn serve(self) -> Result<()> {
         tracing::info!("Starting MNIST gRPC server on {}", self.config.address);
+        let rollout_metrics = self.service.rollout_metrics();
+        let service = Arc::new(self.service);
+        let http_address = self.config.http_address;
+        let http = {
+            let service = Arc::clone(&service);
+            let cors = self.cors.clone();
+            async move {
+                match http_address {
+                    Some(address) => serve_http(address, service, cors).await,
+                    None => std::future::pending().await,
+                }
+            }
+        };
+        let grpc_web = self.config.web.grpc_web;
+        if grpc_web {
+            tracing::info!(allowed_origins = ?self.config.web.allowed_origins, "Accepting gRPC-Web requests");
+        }
 
         let server = Server::builder()
+            // gRPC-Web clients in browsers speak HTTP/1.1
+            .accept_http1(grpc_web)
             .layer(
                 TraceLayer::new_for_grpc()
                     .make_span_with(|_req: &Request<tonic::body::Body>| {
@@ -51,7 +85,13 @@ impl MnistGrpcServer {
                         )
                     })
                     .on_request(|request: &Request<tonic::body::Body>, span: &Span| {
-                        let request_id = Uuid::new_v4().to_string();
+                        // Keep the id set by clients such as `mnist-client`
+                        let request_id = request
+                            .headers()
+                            .get("x-request-id")
+                            .and_then(|value| value.to_str().ok())
+                            .map(str::to_string)
+                            .unwrap_or_else(|| Uuid::new_v4().to_string());
                         span.record("request_id", request_id);
                         tracing::info!(
                             method = %request.method(),
@@ -71,7 +111,12 @@ impl MnistGrpcServer {
                         },
                     ),
             )
-            .add_service(MnistServer::new(self.service))
+            .layer(option_layer(self.cors))
+            .layer(option_layer(grpc_web.then(GrpcWebLayer::new)))
+            .add_service(GrpcInferenceServiceServer::new(OpenInferenceService::new(
+                Arc::clone(&service),
+            )))
+            .add_service(MnistServer::from_arc(service))
             .serve(self.config.address);
 
         // Handle graceful shutdown
@@ -79,16 +124,80 @@ impl MnistGrpcServer {
             result = server => {
                 result.map_err(|e| Error::custom(format!("Server error: {}", e)))?;
             }
+            result = http => result?,
             _ = tokio::signal::ctrl_c() => {
                 tracing::info!("Received shutdown signal, stopping server...");
             }
         }
 
+        if let Some(metrics) = rollout_metrics {
+            crate::rollout::log_summary(&metrics);
+        }
         tracing::info!("Server stopped");
         Ok(())
     }
 }
 
+/// Serve the HTTP/JSON gateway until it fails
+async fn serve_http(
+    address: SocketAddr,
+    service: Arc<MnistService>,
+    cors: Option<CorsLayer>,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| Error::custom(format!("Failed to bind {}: {}", address, e)))?;
+    tracing::info!("Starting HTTP gateway on {}", address);
+    let mut router = gateway::router(service);
+    if let Some(cors) = cors {
+        router = router.layer(cors);
+    }
+    let router = router.layer(TraceLayer::new_for_http());
+    axum::serve(listener, router)
+        .await
+        .map_err(|e| Error::custom(format!("HTTP gateway error: {}", e)))
+}
+
+/// CORS policy for the allowed origins, `None` if there are none
+///
+/// Besides the preflight of gRPC-Web calls, it lets browsers read the gRPC status that
+/// gRPC-Web returns in headers for responses without a body.
+fn cors_layer(web: &WebConfig) -> Result<Option<CorsLayer>> {
+    if web.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    let allow_origin = if web.allowed_origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = web
+            .allowed_origins
+            .iter()
+            .map(|origin| {
+                HeaderValue::from_str(origin.trim_end_matches('/'))
+                    .map_err(|_| Error::custom(format!("Invalid allowed origin '{}'", origin)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(origins)
+    };
+    let cors = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::POST, Method::OPTIONS])
+        .allow_headers([
+            http::header::CONTENT_TYPE,
+            http::header::AUTHORIZATION,
+            HeaderName::from_static("x-grpc-web"),
+            HeaderName::from_static("x-user-agent"),
+            HeaderName::from_static("grpc-timeout"),
+        ])
+        .expose_headers([
+            HeaderName::from_static("grpc-status"),
+            HeaderName::from_static("grpc-message"),
+            HeaderName::from_static("grpc-status-details-bin"),
+        ])
+        .max_age(Duration::from_secs(24 * 60 * 60));
+    Ok(Some(cors))
+}
+
 /// Server builder for convenient server construction
 pub struct ServerBuilder {
     config: Option<ServerConfig>,
@@ -105,7 +214,7 @@ impl ServerBuilder {
     }
 
     pub fn build(self) -> Result<MnistGrpcServer> {
-        let config = self.config.unwrap_or_else(|| ServerConfig::default());
+        let config = self.config.unwrap_or_default();
 
         MnistGrpcServer::new(config)
     }
@@ -142,6 +251,91 @@ mod tests {
         assert!(result.is_err()); // Expected to fail due to missing weights file
     }
 
+    #[test]
+    fn test_cors_layer() {
+        assert!(cors_layer(&WebConfig::new(true)).unwrap().is_none());
+        let web = WebConfig::new(true).with_allowed_origins(["https://digits.example.com/"]);
+        assert!(cors_layer(&web).unwrap().is_some());
+        let web = WebConfig::new(true).with_allowed_origins(["*"]);
+        assert!(cors_layer(&web).unwrap().is_some());
+        let web = WebConfig::new(true).with_allowed_origins(["https://digits\nexample.com"]);
+        assert!(cors_layer(&web).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_grpc_web_predict() {
+        use crate::proto::MnistImage;
+        use crate::test_util::random_weights;
+        use axum::body::Body;
+        use prost::Message;
+        use tower::{ServiceBuilder, ServiceExt};
+
+        let (_dir, path) = random_weights(ModelArchitecture::MLP);
+        let config = ServiceConfig::new(
+            Device::Cpu,
+            DType::F32,
+            LocalFileProvider::new(path),
+            Some(ModelArchitecture::MLP),
+        );
+        let web = WebConfig::new(true).with_allowed_origins(["https://digits.example.com"]);
+        let service = ServiceBuilder::new()
+            .option_layer(cors_layer(&web).unwrap())
+            .layer(GrpcWebLayer::new())
+            .service(MnistServer::new(MnistService::new(config).unwrap()));
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/mnist.Mnist/Predict")
+            .header("origin", "https://digits.example.com")
+            .header("access-control-request-method", "POST")
+            .header("access-control-request-headers", "content-type,x-grpc-web")
+            .body(Body::empty())
+            .unwrap();
+        let response = service.clone().oneshot(preflight).await.unwrap();
+        assert_eq!(
+            response.headers()["access-control-allow-origin"],
+            "https://digits.example.com"
+        );
+
+        let mut png = std::io::Cursor::new(Vec::new());
+        image::GrayImage::new(28, 28)
+            .write_to(&mut png, image::ImageFormat::Png)
+            .unwrap();
+        let message = MnistImage {
+            data: png.into_inner(),
+            ..Default::default()
+        }
+        .encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend((message.len() as u32).to_be_bytes());
+        frame.extend(message);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri("/mnist.Mnist/Predict")
+            .header("origin", "https://digits.example.com")
+            .header("content-type", "application/grpc-web+proto")
+            .header("x-grpc-web", "1")
+            .body(Body::from(frame))
+            .unwrap();
+        let response = service.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.headers()["content-type"],
+            "application/grpc-web+proto"
+        );
+        let body = axum::body::to_bytes(Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        // A data frame with the prediction followed by a trailers frame
+        assert_eq!(body[0], 0);
+        let length = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+        let prediction = crate::proto::MnistPrediction::decode(&body[5..5 + length]).unwrap();
+        assert_eq!(prediction.probabilities.len(), 10);
+        assert_eq!(body[5 + length], 0x80);
+        let trailers = String::from_utf8_lossy(&body[10 + length..]);
+        assert!(trailers.contains("grpc-status:0"), "{}", trailers);
+    }
+
     #[test]
     fn test_server_builder() {
         let builder = ServerBuilder::new();
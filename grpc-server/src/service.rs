@@ -1,62 +1,484 @@
+use std::sync::Arc;
+use std::time::Instant;
+
 use crate::{Error, Result};
 use tonic::{Request, Response, Status};
 
 use crate::proto::mnist_server::Mnist;
-use crate::proto::{MnistImage, MnistPrediction};
+use crate::proto::{
+    self, BoundingBox, ClassScore, EmbedRequest, Embedding, Embeddings, ExplainRequest,
+    Explanation, Feedback, FeedbackReceipt, MemberPrediction, MnistImage, MnistPrediction,
+    NumberImage, RecognizedDigit, RecognizedNumber,
+};
 
 use crate::config::ServiceConfig;
+use crate::feedback::{FeedbackSample, FeedbackStore};
+use crate::inference_engine::explain::{self, ExplainMethod};
+use crate::inference_engine::postprocess::{OutputMode, PostProcessing};
 use crate::inference_engine::weights_provider::{LocalFileProvider, WeightsProvider};
-use crate::inference_engine::{InferenceEngine, InferenceEngineBuilder, ModelArchitecture};
+use crate::inference_engine::{
+    InferenceEngine, InferenceEngineBuilder, ModelArchitecture, PredictOptions,
+};
+use crate::metrics::{InferenceMetrics, RolloutMetrics};
+use crate::rollout::{Rollout, RolloutMode};
+use crate::segmentation;
 use candle_core::{DType, Device};
+use mnist::ModelSpec;
+
+/// Number of images an `Embed` request may hold
+pub const MAX_EMBED_IMAGES: usize = 256;
 
 #[derive(Debug)]
 pub struct MnistService {
-    inference_engine: InferenceEngine,
+    inference_engine: Arc<InferenceEngine>,
+    /// Candidate model receiving a share of the traffic
+    rollout: Option<Rollout>,
+    /// Store of corrected samples, `SubmitFeedback` is disabled without one
+    feedback: Option<FeedbackStore>,
+    defaults: PredictOptions,
+    model_name: String,
+    /// Latency of `Predict` requests
+    metrics: InferenceMetrics,
 }
 
+/// Name of the model if neither the configuration nor the model artifact declare one
+pub const DEFAULT_MODEL_NAME: &str = "mnist";
+
 impl MnistService {
     pub fn new(config: ServiceConfig) -> Result<Self> {
-        let inference_engine = InferenceEngineBuilder::new()
-            .model_architecture(config.model_architecture)
-            .device(config.device)
-            .dtype(config.dtype)
-            .build(config.weights_provider)?;
+        let inference_engine = build_engine(
+            &config,
+            config.weights_provider.clone(),
+            config.model_architecture,
+            config.model_spec.clone(),
+        )?;
+        let rollout = match &config.candidate {
+            Some(candidate) => {
+                let engine = build_engine(
+                    &config,
+                    candidate.weights_provider.clone(),
+                    candidate.model_architecture,
+                    candidate.model_spec.clone(),
+                )?;
+                tracing::info!(
+                    mode = %candidate.mode,
+                    percent = candidate.percent,
+                    architecture = ?engine.architecture(),
+                    "Loaded candidate model"
+                );
+                Some(Rollout::new(engine, candidate.mode, candidate.percent))
+            }
+            None => None,
+        };
+        let feedback = config
+            .feedback_dir
+            .as_ref()
+            .map(FeedbackStore::open)
+            .transpose()?;
+
+        let model_name = config
+            .model_name
+            .or_else(|| inference_engine.name().map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_MODEL_NAME.to_string());
+
+        Ok(MnistService {
+            inference_engine: Arc::new(inference_engine),
+            rollout,
+            feedback,
+            defaults: config.predict_options,
+            model_name,
+            metrics: InferenceMetrics::new(),
+        })
+    }
+
+    /// Name the model is served under
+    pub fn model_name(&self) -> &str {
+        &self.model_name
+    }
 
-        Ok(MnistService { inference_engine })
+    /// Engine of the served model
+    pub fn inference_engine(&self) -> &InferenceEngine {
+        &self.inference_engine
+    }
+
+    /// Options applied to requests that do not set their own
+    pub fn predict_defaults(&self) -> &PredictOptions {
+        &self.defaults
+    }
+
+    /// Latency of the predictions, with and without test-time augmentation
+    pub fn metrics(&self) -> &InferenceMetrics {
+        &self.metrics
+    }
+
+    /// Metrics comparing the candidate model to the served one, if there is a candidate
+    pub fn rollout_metrics(&self) -> Option<Arc<RolloutMetrics>> {
+        self.rollout
+            .as_ref()
+            .map(|rollout| Arc::clone(rollout.metrics()))
     }
 }
 
+fn build_engine(
+    config: &ServiceConfig,
+    provider: LocalFileProvider,
+    arch: Option<ModelArchitecture>,
+    spec: Option<ModelSpec>,
+) -> Result<InferenceEngine> {
+    let mut builder = InferenceEngineBuilder::new()
+        .device(config.device.clone())
+        .dtype(config.dtype);
+    if let Some(quantization) = config.quantization {
+        builder = builder.quantization(quantization);
+    }
+    if let Some(arch) = arch {
+        builder = builder.model_architecture(arch);
+    }
+    if let Some(spec) = spec {
+        builder = builder.model_spec(spec);
+    }
+    builder.build(provider)
+}
+
 #[tonic::async_trait]
 impl Mnist for MnistService {
     async fn predict(
         &self,
         request: Request<MnistImage>,
     ) -> std::result::Result<Response<MnistPrediction>, Status> {
-        let processed_image = preprocess_image(&request.into_inner().data);
+        let request = request.into_inner();
+        if !request.model.is_empty() && request.model != self.model_name {
+            return Err(Error::ModelNotFound(request.model).into());
+        }
+        let options = self.predict_options(&request)?;
+        let processed_image = preprocess_image(&request.data)?;
 
-        let prediction = self.inference_engine.predict(processed_image)?;
+        // A sampled request is also run on the other model once answered
+        let rollout = self.rollout.as_ref().filter(|rollout| rollout.sample());
+        let engine = match rollout {
+            Some(rollout) if rollout.mode() == RolloutMode::Canary => rollout.candidate(),
+            _ => &self.inference_engine,
+        };
+        let mirrored_image = rollout.map(|_| processed_image.clone());
+        let started = Instant::now();
+        let prediction = engine.predict_with(processed_image, &options)?;
+        let latency = started.elapsed();
+        self.metrics.record_prediction(prediction.views, latency);
+        if let (Some(rollout), Some(image)) = (rollout, mirrored_image) {
+            rollout.mirror(
+                &self.inference_engine,
+                image,
+                options,
+                prediction.digit,
+                latency,
+            );
+        }
+        if let Some(rejection) = &prediction.rejection {
+            tracing::info!(digit = prediction.digit, %rejection, "Rejected prediction");
+        }
+        if prediction.out_of_distribution {
+            tracing::info!(
+                digit = prediction.digit,
+                ood_score = prediction.ood_score,
+                "Out-of-distribution input"
+            );
+        }
 
         Ok(Response::new(MnistPrediction {
             label: prediction.digit as i32,
             probabilities: prediction.probabilities,
+            label_name: prediction.label.unwrap_or_default(),
+            scores: prediction.scores,
+            top_k: prediction
+                .top_k
+                .into_iter()
+                .map(|score| ClassScore {
+                    label: score.class as i32,
+                    label_name: score.label.unwrap_or_default(),
+                    probability: score.probability,
+                })
+                .collect(),
+            rejected: prediction.rejection.is_some(),
+            rejection_reason: prediction
+                .rejection
+                .map(|rejection| rejection.to_string())
+                .unwrap_or_default(),
+            members: prediction
+                .members
+                .into_iter()
+                .map(|member| MemberPrediction {
+                    name: member.name,
+                    label: member.digit as i32,
+                    probabilities: member.probabilities,
+                })
+                .collect(),
+            model: self.model_name.clone(),
+            views: prediction.views as u32,
+            ood_score: prediction.ood_score,
+            out_of_distribution: prediction.out_of_distribution,
+        }))
+    }
+
+    async fn submit_feedback(
+        &self,
+        request: Request<Feedback>,
+    ) -> std::result::Result<Response<FeedbackReceipt>, Status> {
+        let request = request.into_inner();
+        let store = self.feedback.as_ref().ok_or_else(|| {
+            Error::NotEnabled("feedback collection, start the server with --feedback-dir".into())
+        })?;
+        if !request.model.is_empty() && request.model != self.model_name {
+            return Err(Error::ModelNotFound(request.model).into());
+        }
+        let label = digit("label", request.label)?;
+        let predicted_label = request
+            .predicted_label
+            .map(|predicted| digit("predicted_label", predicted))
+            .transpose()?;
+        let pixels = mnist_pixels(&request.data)?;
+
+        let record = store.record(FeedbackSample {
+            data: request.data,
+            pixels,
+            label,
+            predicted_label,
+            model: self.model_name.clone(),
+        })?;
+        tracing::info!(
+            id = %record.id,
+            label,
+            predicted_label,
+            samples = record.index + 1,
+            "Stored feedback"
+        );
+
+        Ok(Response::new(FeedbackReceipt {
+            id: record.id,
+            samples: record.index as u64 + 1,
         }))
     }
+
+    /// Read the digits of a number with the served model
+    ///
+    /// Every digit is classified on its own, in a single batch. Rollout candidates only
+    /// see `Predict` traffic.
+    async fn recognize_number(
+        &self,
+        request: Request<NumberImage>,
+    ) -> std::result::Result<Response<RecognizedNumber>, Status> {
+        let request = request.into_inner();
+        if !request.model.is_empty() && request.model != self.model_name {
+            return Err(Error::ModelNotFound(request.model).into());
+        }
+        let mut options = self.defaults;
+        if request.min_confidence != 0.0 {
+            options.rejection = options
+                .rejection
+                .with_min_confidence(request.min_confidence)?;
+        }
+        let image = decode_image(&request.data)?;
+        let digits = segmentation::segment_digits(&image)?;
+        if digits.is_empty() {
+            return Ok(Response::new(RecognizedNumber {
+                model: self.model_name.clone(),
+                ..Default::default()
+            }));
+        }
+
+        let inputs = digits
+            .iter()
+            .map(|digit| digit.pixels.iter().map(|&p| p as f32 / 255.0).collect())
+            .collect();
+        let predictions = self.inference_engine.predict_batch(inputs, &options)?;
+        let digits: Vec<RecognizedDigit> = digits
+            .into_iter()
+            .zip(predictions)
+            .map(|(digit, prediction)| RecognizedDigit {
+                label: prediction.digit as i32,
+                probability: prediction.probabilities[prediction.digit as usize],
+                bounding_box: Some(BoundingBox {
+                    x: digit.bounding_box.x,
+                    y: digit.bounding_box.y,
+                    width: digit.bounding_box.width,
+                    height: digit.bounding_box.height,
+                }),
+                rejected: prediction.rejection.is_some(),
+            })
+            .collect();
+        let number: String = digits.iter().map(|digit| digit.label.to_string()).collect();
+        tracing::info!(number, digits = digits.len(), "Recognized number");
+
+        Ok(Response::new(RecognizedNumber {
+            number,
+            confidence: digits.iter().map(|digit| digit.probability).product(),
+            rejected: digits.iter().any(|digit| digit.rejected),
+            digits,
+            model: self.model_name.clone(),
+        }))
+    }
+
+    /// Attribute the prediction of the served model to the pixels of the image
+    async fn explain(
+        &self,
+        request: Request<ExplainRequest>,
+    ) -> std::result::Result<Response<Explanation>, Status> {
+        let request = request.into_inner();
+        if !request.model.is_empty() && request.model != self.model_name {
+            return Err(Error::ModelNotFound(request.model).into());
+        }
+        let label = request
+            .label
+            .map(|label| digit("label", label))
+            .transpose()?;
+        let method = match request.method() {
+            proto::ExplainMethod::Occlusion => ExplainMethod::Occlusion,
+            proto::ExplainMethod::Gradient => ExplainMethod::Gradient,
+        };
+        let image = preprocess_image(&request.data)?;
+        let explanation =
+            self.inference_engine
+                .explain(image.clone(), label.map(u32::from), method)?;
+        let heatmap = if request.render_heatmap {
+            explain::render_heatmap(&image, &explanation.attributions)?
+        } else {
+            Vec::new()
+        };
+        tracing::info!(
+            label = explanation.class,
+            predicted_label = explanation.predicted,
+            ?method,
+            "Explained prediction"
+        );
+
+        Ok(Response::new(Explanation {
+            label: explanation.class as i32,
+            probability: explanation.probability,
+            predicted_label: explanation.predicted as i32,
+            attributions: explanation.attributions,
+            heatmap,
+            model: self.model_name.clone(),
+        }))
+    }
+
+    /// Penultimate activations of the served model, all images in a single forward pass
+    async fn embed(
+        &self,
+        request: Request<EmbedRequest>,
+    ) -> std::result::Result<Response<Embeddings>, Status> {
+        let request = request.into_inner();
+        if !request.model.is_empty() && request.model != self.model_name {
+            return Err(Error::ModelNotFound(request.model).into());
+        }
+        check_embed_images(request.images.len())?;
+        let inputs = request
+            .images
+            .iter()
+            .map(|image| preprocess_image(image))
+            .collect::<Result<Vec<_>>>()?;
+        let mut embeddings = self.inference_engine.embed_batch(inputs)?;
+        if request.normalize {
+            for embedding in &mut embeddings {
+                let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+                if norm > 0.0 {
+                    embedding.iter_mut().for_each(|x| *x /= norm);
+                }
+            }
+        }
+
+        Ok(Response::new(Embeddings {
+            dimensions: embeddings.first().map_or(0, Vec::len) as u32,
+            embeddings: embeddings
+                .into_iter()
+                .map(|values| Embedding { values })
+                .collect(),
+            model: self.model_name.clone(),
+        }))
+    }
+}
+
+/// Reject `Embed` requests with more than `MAX_EMBED_IMAGES` images
+pub(crate) fn check_embed_images(count: usize) -> Result<()> {
+    if count > MAX_EMBED_IMAGES {
+        return Err(Error::invalid_argument(format!(
+            "Got {} images, at most {} are supported",
+            count, MAX_EMBED_IMAGES
+        )));
+    }
+    Ok(())
+}
+
+fn digit(name: &str, value: i32) -> Result<u8> {
+    u8::try_from(value)
+        .ok()
+        .filter(|digit| *digit <= 9)
+        .ok_or_else(|| Error::invalid_argument(format!("{} must be a digit, got {}", name, value)))
+}
+
+impl MnistService {
+    /// Merge the options of a request with the server defaults
+    fn predict_options(&self, request: &MnistImage) -> Result<PredictOptions> {
+        let mut options = self.defaults;
+        options.post_processing = post_processing(request)?;
+        if request.top_k != 0 {
+            options.top_k = request.top_k as usize;
+        }
+        if request.min_confidence != 0.0 {
+            options.rejection = options
+                .rejection
+                .with_min_confidence(request.min_confidence)?;
+        }
+        if request.min_margin != 0.0 {
+            options.rejection = options.rejection.with_min_margin(request.min_margin)?;
+        }
+        options.include_members |= request.include_members;
+        if request.test_time_augmentation.is_some() {
+            options.test_time_augmentation = request.test_time_augmentation;
+        }
+        Ok(options)
+    }
+}
+
+/// Read the output post-processing requested by the client
+fn post_processing(request: &MnistImage) -> Result<PostProcessing> {
+    let mode = match request.output_mode() {
+        proto::OutputMode::Softmax => OutputMode::Softmax,
+        proto::OutputMode::LogSoftmax => OutputMode::LogSoftmax,
+        proto::OutputMode::Logits => OutputMode::Logits,
+    };
+    let post = PostProcessing::new(mode);
+    if request.temperature == 0.0 {
+        Ok(post)
+    } else {
+        post.with_temperature(request.temperature)
+    }
 }
 
 /// Convert the image bytes to a vector of f32
-fn preprocess_image(image_bytes: &[u8]) -> Vec<f32> {
-    // Decode image
-    let img = image::load_from_memory(image_bytes).unwrap();
+fn preprocess_image(image_bytes: &[u8]) -> Result<Vec<f32>> {
+    Ok(mnist_pixels(image_bytes)?
+        .into_iter()
+        .map(|b| b as f32 / 255.0)
+        .collect())
+}
+
+/// Decode an image to grayscale
+fn decode_image(image_bytes: &[u8]) -> Result<image::GrayImage> {
+    let img = image::load_from_memory(image_bytes)
+        .map_err(|e| Error::invalid_argument(format!("Invalid image: {}", e)))?;
+    Ok(img.to_luma8())
+}
 
+/// Decode an image into 28x28 pixels in the layout of the MNIST dataset
+fn mnist_pixels(image_bytes: &[u8]) -> Result<Vec<u8>> {
     // Convert to grayscale and resize to 28x28
-    let gray = img.to_luma8();
+    let gray = decode_image(image_bytes)?;
     let resized = image::imageops::resize(&gray, 28, 28, image::imageops::FilterType::Triangle);
-    let data: Vec<f32> = resized
+    let data: Vec<u8> = resized
         .into_raw()
         .into_iter()
-        // Converts to black bacground and white pencil by substracting from one
-        .map(|b| 1.0 - (b as f32) / 255.0)
+        // Converts to black bacground and white pencil by inverting the pixels
+        .map(|b| 255 - b)
         .collect();
 
-    data
+    Ok(data)
 }
@@ -0,0 +1,73 @@
+//! Fixtures shared by the unit tests
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+use candle_core::{DType, Device};
+use candle_nn::{VarBuilder, VarMap};
+use mnist::{ConvNet, MnistMLP, ModelSpec, SequentialModel};
+
+use crate::inference_engine::ModelArchitecture;
+
+/// Directory under the system temp dir, removed with its contents when dropped
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(prefix: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{}-{}", prefix, uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Save randomly initialized weights for `arch` to `model.safetensors` in a new directory
+///
+/// The directory is removed when the returned guard is dropped.
+pub fn random_weights(arch: ModelArchitecture) -> (TempDir, PathBuf) {
+    random_spec_weights(arch, None)
+}
+
+/// Like [`random_weights`], for the layers of `spec` if the architecture is sequential
+pub fn random_spec_weights(
+    arch: ModelArchitecture,
+    spec: Option<&ModelSpec>,
+) -> (TempDir, PathBuf) {
+    let dir = TempDir::new("weights");
+    let path = dir.join("model.safetensors");
+    let varmap = VarMap::new();
+    let varbuilder = VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu);
+    match arch {
+        ModelArchitecture::MLP => {
+            MnistMLP::new(varbuilder).unwrap();
+        }
+        ModelArchitecture::Conv => {
+            ConvNet::new(varbuilder).unwrap();
+        }
+        ModelArchitecture::Sequential => {
+            SequentialModel::new(spec.unwrap(), varbuilder).unwrap();
+        }
+        other => panic!("No random weights for {:?}", other),
+    }
+    varmap.save(&path).unwrap();
+    (dir, path)
+}
@@ -0,0 +1,5 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Server stubs are generated too, for mock servers in tests
+    tonic_build::configure().compile_protos(&["../proto/mnist.proto"], &["../proto"])?;
+    Ok(())
+}
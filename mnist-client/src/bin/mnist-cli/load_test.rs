@@ -0,0 +1,288 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use mnist_client::{Error, MnistClient, Result, RetryPolicy};
+use serde::Serialize;
+
+use crate::{ConnectionArgs, Format, LoadTestArgs, collect_images, read};
+
+/// Outcome of a load test
+#[derive(Debug, Serialize)]
+struct LoadTestReport {
+    duration_secs: f64,
+    concurrency: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_rps: Option<f64>,
+    requests: usize,
+    succeeded: usize,
+    failed: usize,
+    achieved_rps: f64,
+    /// Failed requests by gRPC status code
+    errors: BTreeMap<String, usize>,
+    /// Latency of the successful requests, absent if there were none
+    latency_ms: Option<Latency>,
+}
+
+/// Latency distribution in milliseconds
+#[derive(Debug, Serialize, PartialEq)]
+struct Latency {
+    min: f64,
+    mean: f64,
+    p50: f64,
+    p90: f64,
+    p95: f64,
+    p99: f64,
+    max: f64,
+}
+
+impl Latency {
+    fn new(mut latencies: Vec<Duration>) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        latencies.sort();
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        // Nearest-rank percentile
+        let percentile = |p: f64| {
+            let rank = (p / 100.0 * latencies.len() as f64).ceil() as usize;
+            ms(latencies[rank.clamp(1, latencies.len()) - 1])
+        };
+        Some(Self {
+            min: ms(latencies[0]),
+            mean: ms(latencies.iter().sum::<Duration>()) / latencies.len() as f64,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: ms(latencies[latencies.len() - 1]),
+        })
+    }
+}
+
+/// When requests are sent, shared by the workers
+struct Schedule {
+    start: Instant,
+    deadline: Instant,
+    /// Target requests per second, unlimited if not set
+    rps: Option<f64>,
+    /// Number of requests taken by the workers so far
+    sent: AtomicUsize,
+}
+
+impl Schedule {
+    /// Index of the next request and when it is due, `None` once the test is over
+    fn next(&self) -> Option<(usize, Instant)> {
+        let index = self.sent.fetch_add(1, Ordering::Relaxed);
+        let due = match self.rps {
+            Some(rps) => self.start + Duration::from_secs_f64(index as f64 / rps),
+            None => Instant::now(),
+        };
+        (due < self.deadline).then_some((index, due))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    latencies: Vec<Duration>,
+    errors: BTreeMap<String, usize>,
+}
+
+/// Send the images in turn for the configured duration, and print the latency report
+///
+/// Retries are disabled so that every failure is counted. Returns whether all requests
+/// succeeded.
+pub async fn run(connection: &ConnectionArgs, args: LoadTestArgs) -> Result<bool> {
+    if args.rps.is_some_and(|rps| rps.is_nan() || rps <= 0.0) {
+        return Err(Error::config("--rps must be positive"));
+    }
+    let images = collect_images(&args.paths)?
+        .iter()
+        .map(|path| read(path))
+        .collect::<Result<Vec<_>>>()?;
+    let images = Arc::new(images);
+    let client = connection
+        .builder()?
+        .retry_policy(RetryPolicy::none())
+        .connect()
+        .await?;
+
+    let concurrency = args.concurrency.max(1);
+    eprintln!(
+        "Sending {} images for {}s with {} requests in flight{}",
+        images.len(),
+        args.duration_secs,
+        concurrency,
+        args.rps
+            .map(|rps| format!(" at {} requests/s", rps))
+            .unwrap_or_default()
+    );
+    let start = Instant::now();
+    let schedule = Arc::new(Schedule {
+        start,
+        deadline: start + Duration::from_secs(args.duration_secs),
+        rps: args.rps,
+        sent: AtomicUsize::new(0),
+    });
+    let workers: Vec<_> = (0..concurrency)
+        .map(|_| {
+            tokio::spawn(worker(
+                client.clone(),
+                Arc::clone(&images),
+                Arc::clone(&schedule),
+            ))
+        })
+        .collect();
+    let mut samples = Samples::default();
+    for worker in workers {
+        let worker = worker
+            .await
+            .map_err(|e| Error::config(format!("Load test worker failed: {}", e)))?;
+        samples.latencies.extend(worker.latencies);
+        for (code, count) in worker.errors {
+            *samples.errors.entry(code).or_default() += count;
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let succeeded = samples.latencies.len();
+    let failed = samples.errors.values().sum();
+    let report = LoadTestReport {
+        duration_secs: elapsed,
+        concurrency,
+        target_rps: args.rps,
+        requests: succeeded + failed,
+        succeeded,
+        failed,
+        achieved_rps: (succeeded + failed) as f64 / elapsed,
+        errors: samples.errors,
+        latency_ms: Latency::new(samples.latencies),
+    };
+    match args.format {
+        Format::Table => print_table(&report),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report serializes to JSON")
+        ),
+        Format::Csv => print_csv(&report),
+    }
+    Ok(report.failed == 0)
+}
+
+/// Send requests until the schedule is over
+///
+/// With a target rate, latency is measured from when a request was due rather than when
+/// it was sent, so that a slow server also counts the time requests waited for a worker.
+async fn worker(
+    client: MnistClient,
+    images: Arc<Vec<Vec<u8>>>,
+    schedule: Arc<Schedule>,
+) -> Samples {
+    let mut samples = Samples::default();
+    while let Some((index, due)) = schedule.next() {
+        tokio::time::sleep_until(due.into()).await;
+        let image = images[index % images.len()].clone();
+        match client.predict_bytes(image).await {
+            Ok(_) => samples.latencies.push(due.elapsed()),
+            Err(e) => {
+                let code = match e.code() {
+                    Some(code) => format!("{:?}", code),
+                    None => "Other".to_string(),
+                };
+                *samples.errors.entry(code).or_default() += 1;
+            }
+        }
+    }
+    samples
+}
+
+fn print_table(report: &LoadTestReport) {
+    println!(
+        "{} requests in {:.1}s ({:.1} requests/s): {} succeeded, {} failed",
+        report.requests, report.duration_secs, report.achieved_rps, report.succeeded, report.failed
+    );
+    for (code, count) in &report.errors {
+        println!("  {:<18} {}", code, count);
+    }
+    if let Some(latency) = &report.latency_ms {
+        println!();
+        println!("latency (ms)     min     mean      p50      p90      p95      p99      max");
+        println!(
+            "             {:>7.2}  {:>7.2}  {:>7.2}  {:>7.2}  {:>7.2}  {:>7.2}  {:>7.2}",
+            latency.min,
+            latency.mean,
+            latency.p50,
+            latency.p90,
+            latency.p95,
+            latency.p99,
+            latency.max
+        );
+    }
+}
+
+fn print_csv(report: &LoadTestReport) {
+    println!("metric,value");
+    println!("duration_secs,{}", report.duration_secs);
+    println!("concurrency,{}", report.concurrency);
+    if let Some(rps) = report.target_rps {
+        println!("target_rps,{}", rps);
+    }
+    println!("requests,{}", report.requests);
+    println!("succeeded,{}", report.succeeded);
+    println!("failed,{}", report.failed);
+    println!("achieved_rps,{}", report.achieved_rps);
+    for (code, count) in &report.errors {
+        println!("errors_{},{}", code, count);
+    }
+    if let Some(latency) = &report.latency_ms {
+        for (name, value) in [
+            ("min", latency.min),
+            ("mean", latency.mean),
+            ("p50", latency.p50),
+            ("p90", latency.p90),
+            ("p95", latency.p95),
+            ("p99", latency.p99),
+            ("max", latency.max),
+        ] {
+            println!("latency_{}_ms,{}", name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency() {
+        let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+        let latency = Latency::new(latencies).unwrap();
+        assert_eq!(latency.min, 1.0);
+        assert_eq!(latency.p50, 50.0);
+        assert_eq!(latency.p90, 90.0);
+        assert_eq!(latency.p99, 99.0);
+        assert_eq!(latency.max, 100.0);
+        assert!((latency.mean - 50.5).abs() < 1e-9);
+
+        let single = Latency::new(vec![Duration::from_millis(3)]).unwrap();
+        assert_eq!(single.p50, 3.0);
+        assert_eq!(single.p99, 3.0);
+        assert_eq!(Latency::new(Vec::new()), None);
+    }
+
+    #[test]
+    fn test_schedule() {
+        let start = Instant::now();
+        let schedule = Schedule {
+            start,
+            deadline: start + Duration::from_secs(1),
+            rps: Some(4.0),
+            sent: AtomicUsize::new(0),
+        };
+        let due: Vec<Duration> = std::iter::from_fn(|| schedule.next())
+            .map(|(_, due)| due - start)
+            .collect();
+        assert_eq!(due, [0, 250, 500, 750].map(Duration::from_millis).to_vec());
+    }
+}
@@ -0,0 +1,247 @@
+//! Command line client of the MNIST gRPC server
+//!
+//! ```text
+//! mnist-cli predict digits/ --format csv
+//! mnist-cli load-test digits/ --concurrency 16 --duration-secs 30 --rps 200
+//! ```
+mod load_test;
+mod predict;
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use mnist_client::{ClientBuilder, Error, MnistClient, Result};
+
+#[derive(Debug, Parser)]
+#[command(name = "mnist-cli")]
+#[command(about = "Send images to the MNIST gRPC server and measure its latency")]
+struct Cli {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Args)]
+struct ConnectionArgs {
+    /// Address of the server, `https://` enables TLS
+    #[arg(long, global = true, default_value = "http://[::1]:50051")]
+    endpoint: String,
+
+    /// Deadline of every request in milliseconds
+    #[arg(long, global = true)]
+    timeout_ms: Option<u64>,
+
+    /// Bearer token sent in the `authorization` header
+    #[arg(long, global = true)]
+    token: Option<String>,
+
+    /// PEM file of the CA that signed the server certificate
+    #[arg(long, global = true)]
+    ca_cert: Option<PathBuf>,
+
+    /// Name of the model to address, the served model if omitted
+    #[arg(long, global = true)]
+    model: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Predict the digits in image files, or in all images of a directory
+    Predict(PredictArgs),
+    /// Send images at a fixed concurrency and rate, and report latency percentiles
+    LoadTest(LoadTestArgs),
+}
+
+#[derive(Debug, Args)]
+struct PredictArgs {
+    /// Image files or directories of images
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+
+    /// Number of most probable digits to return
+    #[arg(long, default_value_t = 1)]
+    top_k: u32,
+
+    /// Number of requests in flight
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+}
+
+#[derive(Debug, Args)]
+struct LoadTestArgs {
+    /// Image files or directories of images, sent in turn
+    #[arg(required = true)]
+    paths: Vec<PathBuf>,
+
+    /// Number of requests in flight
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// How long to send requests for
+    #[arg(long, default_value_t = 10)]
+    duration_secs: u64,
+
+    /// Target rate of requests per second over all workers, as fast as possible if omitted
+    #[arg(long)]
+    rps: Option<f64>,
+
+    /// Output format of the report, `csv` prints one `metric,value` row per line
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    Table,
+    Json,
+    Csv,
+}
+
+impl ConnectionArgs {
+    fn builder(&self) -> Result<ClientBuilder> {
+        let mut builder = MnistClient::builder(&self.endpoint);
+        if let Some(timeout) = self.timeout_ms {
+            builder = builder.timeout(Duration::from_millis(timeout));
+        }
+        if let Some(token) = &self.token {
+            builder = builder.bearer_token(token);
+        }
+        if let Some(path) = &self.ca_cert {
+            builder = builder.ca_certificate(read(path)?);
+        }
+        if let Some(model) = &self.model {
+            builder = builder.model(model);
+        }
+        Ok(builder)
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| Error::Image(path.to_path_buf(), e))
+}
+
+/// Extensions of the files picked up from directories
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "bmp", "gif", "webp", "pgm"];
+
+/// Files given on the command line, and the images found in directories, sorted
+fn collect_images(paths: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut images = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            let mut found = Vec::new();
+            find_images(path, &mut found)?;
+            found.sort();
+            images.extend(found);
+        } else {
+            images.push(path.clone());
+        }
+    }
+    if images.is_empty() {
+        return Err(Error::config("No images found"));
+    }
+    Ok(images)
+}
+
+fn find_images(dir: &Path, images: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = std::fs::read_dir(dir).map_err(|e| Error::Image(dir.to_path_buf(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| Error::Image(dir.to_path_buf(), e))?
+            .path();
+        if path.is_dir() {
+            find_images(&path, images)?;
+        } else if path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+        {
+            images.push(path);
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Predict(args) => predict::run(&cli.connection, args).await,
+        Command::LoadTest(args) => load_test::run(&cli.connection, args).await,
+    };
+    match result {
+        Ok(true) => ExitCode::SUCCESS,
+        // Failed requests have been reported with the results
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let cli = Cli::parse_from([
+            "mnist-cli",
+            "load-test",
+            "digits",
+            "--rps",
+            "50",
+            "--endpoint",
+            "https://mnist.example.com",
+        ]);
+        assert_eq!(cli.connection.endpoint, "https://mnist.example.com");
+        let Command::LoadTest(args) = cli.command else {
+            panic!("expected load-test");
+        };
+        assert_eq!(args.paths, vec![PathBuf::from("digits")]);
+        assert_eq!(args.rps, Some(50.0));
+        assert_eq!(args.concurrency, 8);
+
+        assert!(Cli::try_parse_from(["mnist-cli", "predict"]).is_err());
+    }
+
+    /// Directory removed with its contents when dropped, also when an assertion fails
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_collect_images() {
+        let temp =
+            TempDir(std::env::temp_dir().join(format!("mnist-cli-{}", uuid::Uuid::new_v4())));
+        let dir = temp.0.clone();
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.png", "a.PNG", "notes.txt", "nested/c.jpg"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+        let extra = dir.join("notes.txt");
+        let images = collect_images(&[dir.clone(), extra.clone()]).unwrap();
+        assert_eq!(
+            images,
+            vec![
+                dir.join("a.PNG"),
+                dir.join("b.png"),
+                dir.join("nested/c.jpg"),
+                extra
+            ]
+        );
+        assert!(collect_images(&[]).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+use std::path::PathBuf;
+
+use futures_util::StreamExt;
+use mnist_client::{MnistClient, MnistImage, MnistPrediction, Result};
+use serde::Serialize;
+
+use crate::{ConnectionArgs, Format, PredictArgs, collect_images, read};
+
+/// Prediction of one image, or why there is none
+#[derive(Debug, Serialize)]
+struct PredictionRow {
+    image: PathBuf,
+    label: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label_name: Option<String>,
+    /// Probability of the predicted digit
+    probability: Option<f32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    top_k: Vec<TopK>,
+    rejected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rejection_reason: Option<String>,
+    /// Whether the server flagged the image as unlike its training data
+    out_of_distribution: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct TopK {
+    label: i32,
+    probability: f32,
+}
+
+impl PredictionRow {
+    fn new(image: PathBuf, result: Result<MnistPrediction>) -> Self {
+        match result {
+            Ok(prediction) => Self {
+                image,
+                label: Some(prediction.label),
+                label_name: Some(prediction.label_name).filter(|name| !name.is_empty()),
+                probability: usize::try_from(prediction.label)
+                    .ok()
+                    .and_then(|label| prediction.probabilities.get(label).copied()),
+                top_k: prediction
+                    .top_k
+                    .into_iter()
+                    .map(|score| TopK {
+                        label: score.label,
+                        probability: score.probability,
+                    })
+                    .collect(),
+                rejected: prediction.rejected,
+                rejection_reason: Some(prediction.rejection_reason)
+                    .filter(|reason| !reason.is_empty()),
+                out_of_distribution: prediction.out_of_distribution,
+                error: None,
+            },
+            Err(e) => Self {
+                image,
+                label: None,
+                label_name: None,
+                probability: None,
+                top_k: Vec::new(),
+                rejected: false,
+                rejection_reason: None,
+                out_of_distribution: false,
+                error: Some(e.to_string()),
+            },
+        }
+    }
+}
+
+/// Predict every image and print the results in the requested format
+///
+/// Returns whether all predictions succeeded.
+pub async fn run(connection: &ConnectionArgs, args: PredictArgs) -> Result<bool> {
+    let images = collect_images(&args.paths)?;
+    let client = connection.builder()?.connect().await?;
+    let top_k = args.top_k;
+    let rows: Vec<PredictionRow> = futures_util::stream::iter(images)
+        .map(|image| {
+            let client = &client;
+            async move {
+                let result = predict(client, &image, top_k).await;
+                PredictionRow::new(image, result)
+            }
+        })
+        .buffered(args.concurrency.max(1))
+        .collect()
+        .await;
+
+    match args.format {
+        Format::Table => print_table(&rows),
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&rows).expect("rows serialize to JSON")
+        ),
+        Format::Csv => print_csv(&rows),
+    }
+    Ok(rows.iter().all(|row| row.error.is_none()))
+}
+
+async fn predict(
+    client: &MnistClient,
+    image: &std::path::Path,
+    top_k: u32,
+) -> Result<MnistPrediction> {
+    let data = read(image)?;
+    client
+        .predict(MnistImage {
+            data,
+            top_k,
+            ..Default::default()
+        })
+        .await
+}
+
+fn print_table(rows: &[PredictionRow]) {
+    let width = rows
+        .iter()
+        .map(|row| row.image.display().to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max("image".len());
+    println!("{:<width$}  label  probability  result", "image");
+    for row in rows {
+        let image = row.image.display().to_string();
+        match (&row.error, row.label, row.probability) {
+            (Some(error), _, _) => {
+                println!("{:<width$}  {:>5}  {:>11}  {}", image, "-", "-", error)
+            }
+            (None, label, probability) => {
+                let result = match (&row.rejection_reason, &row.label_name) {
+                    (Some(reason), _) => format!("rejected: {}", reason),
+                    (None, _) if row.out_of_distribution => "out of distribution".to_string(),
+                    (None, Some(name)) => name.clone(),
+                    (None, None) => String::new(),
+                };
+                println!(
+                    "{:<width$}  {:>5}  {:>11.4}  {}",
+                    image,
+                    label.unwrap_or_default(),
+                    probability.unwrap_or_default(),
+                    result
+                );
+            }
+        }
+    }
+}
+
+fn print_csv(rows: &[PredictionRow]) {
+    println!("image,label,probability,top_k,rejected,out_of_distribution,error");
+    for row in rows {
+        let top_k = row
+            .top_k
+            .iter()
+            .map(|score| format!("{}:{}", score.label, score.probability))
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!(
+            "{},{},{},{},{},{},{}",
+            csv_field(&row.image.display().to_string()),
+            row.label.map(|label| label.to_string()).unwrap_or_default(),
+            row.probability
+                .map(|probability| probability.to_string())
+                .unwrap_or_default(),
+            top_k,
+            row.rejected,
+            row.out_of_distribution,
+            csv_field(row.error.as_deref().unwrap_or_default())
+        );
+    }
+}
+
+/// Quote a field if it contains a separator, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("digits/7.png"), "digits/7.png");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(
+            csv_field("Invalid argument: \"x\""),
+            "\"Invalid argument: \"\"x\"\"\""
+        );
+    }
+}
@@ -0,0 +1,607 @@
+use std::future::Future;
+use std::path::Path;
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use tonic::metadata::{AsciiMetadataValue, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint, Identity};
+use tonic::{Request, Response, Status};
+use uuid::Uuid;
+
+use crate::proto::mnist_client::MnistClient as GrpcClient;
+use crate::proto::{
+    EmbedRequest, Embeddings, ExplainRequest, Explanation, Feedback, FeedbackReceipt, MnistImage,
+    MnistPrediction, NumberImage, RecognizedNumber,
+};
+use crate::{Error, Result, RetryPolicy};
+
+/// Metadata key carrying the id of a call, the same for all of its attempts
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Number of requests the batch and stream helpers keep in flight by default
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Client of the `mnist.Mnist` service
+///
+/// Cloning is cheap, clones share the connection.
+#[derive(Debug, Clone)]
+pub struct MnistClient {
+    inner: GrpcClient<Channel>,
+    retry: RetryPolicy,
+    /// Deadline of every attempt, sent to the server as `grpc-timeout`
+    timeout: Option<Duration>,
+    authorization: Option<AsciiMetadataValue>,
+    /// Model the requests are addressed to, empty for the served model
+    model: String,
+    concurrency: usize,
+}
+
+/// Builder for [`MnistClient`]
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    endpoint: String,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    retry: RetryPolicy,
+    bearer_token: Option<String>,
+    ca_certificate: Option<Vec<u8>>,
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+    domain_name: Option<String>,
+    model: String,
+    concurrency: usize,
+}
+
+impl ClientBuilder {
+    pub fn new<S: Into<String>>(endpoint: S) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            timeout: None,
+            connect_timeout: None,
+            retry: RetryPolicy::default(),
+            bearer_token: None,
+            ca_certificate: None,
+            identity: None,
+            domain_name: None,
+            model: String::new(),
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// Deadline of every attempt of a call
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    pub fn retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Send `authorization: Bearer <token>` with every request
+    pub fn bearer_token<S: Into<String>>(mut self, token: S) -> Self {
+        self.bearer_token = Some(token.into());
+        self
+    }
+
+    /// PEM certificate of the CA that signed the server certificate, enables TLS
+    ///
+    /// Without it, `https` endpoints are verified against the system roots.
+    pub fn ca_certificate<B: Into<Vec<u8>>>(mut self, pem: B) -> Self {
+        self.ca_certificate = Some(pem.into());
+        self
+    }
+
+    /// PEM certificate and key presented to servers requiring mutual TLS, enables TLS
+    pub fn identity<C: Into<Vec<u8>>, K: Into<Vec<u8>>>(mut self, certificate: C, key: K) -> Self {
+        self.identity = Some((certificate.into(), key.into()));
+        self
+    }
+
+    /// Name to verify the server certificate against, defaults to the endpoint host
+    pub fn domain_name<S: Into<String>>(mut self, domain_name: S) -> Self {
+        self.domain_name = Some(domain_name.into());
+        self
+    }
+
+    /// Address requests to this model instead of the served one
+    pub fn model<S: Into<String>>(mut self, model: S) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Number of requests [`MnistClient::predict_batch`] and
+    /// [`MnistClient::predict_stream`] keep in flight
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Connect to the server, failing if it cannot be reached
+    pub async fn connect(self) -> Result<MnistClient> {
+        let authorization = self.authorization()?;
+        let channel = self.endpoint()?.connect().await?;
+        Ok(self.client(channel, authorization))
+    }
+
+    /// Create the client without connecting, the connection is made on the first call
+    pub fn connect_lazy(self) -> Result<MnistClient> {
+        let authorization = self.authorization()?;
+        let channel = self.endpoint()?.connect_lazy();
+        Ok(self.client(channel, authorization))
+    }
+
+    fn endpoint(&self) -> Result<Endpoint> {
+        let mut endpoint = Endpoint::from_shared(self.endpoint.clone())
+            .map_err(|e| Error::config(format!("Invalid endpoint '{}': {}", self.endpoint, e)))?;
+        if let Some(timeout) = self.timeout {
+            endpoint = endpoint.timeout(timeout);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            endpoint = endpoint.connect_timeout(timeout);
+        }
+        if let Some(tls) = self.tls_config() {
+            endpoint = endpoint.tls_config(tls)?;
+        }
+        Ok(endpoint)
+    }
+
+    /// TLS settings, `None` for plaintext `http` endpoints
+    fn tls_config(&self) -> Option<ClientTlsConfig> {
+        let tls = self.endpoint.starts_with("https://")
+            || self.ca_certificate.is_some()
+            || self.identity.is_some()
+            || self.domain_name.is_some();
+        if !tls {
+            return None;
+        }
+        let mut config = ClientTlsConfig::new();
+        config = match &self.ca_certificate {
+            Some(pem) => config.ca_certificate(Certificate::from_pem(pem)),
+            None => config.with_native_roots(),
+        };
+        if let Some((certificate, key)) = &self.identity {
+            config = config.identity(Identity::from_pem(certificate, key));
+        }
+        if let Some(domain_name) = &self.domain_name {
+            config = config.domain_name(domain_name);
+        }
+        Some(config)
+    }
+
+    fn authorization(&self) -> Result<Option<AsciiMetadataValue>> {
+        self.bearer_token
+            .as_ref()
+            .map(|token| {
+                MetadataValue::try_from(format!("Bearer {}", token))
+                    .map_err(|_| Error::config("Bearer token is not a valid header value"))
+            })
+            .transpose()
+    }
+
+    fn client(self, channel: Channel, authorization: Option<AsciiMetadataValue>) -> MnistClient {
+        MnistClient {
+            inner: GrpcClient::new(channel),
+            retry: self.retry,
+            timeout: self.timeout,
+            authorization,
+            model: self.model,
+            concurrency: self.concurrency,
+        }
+    }
+}
+
+impl MnistClient {
+    pub fn builder<S: Into<String>>(endpoint: S) -> ClientBuilder {
+        ClientBuilder::new(endpoint)
+    }
+
+    /// Connect to `endpoint` with the default settings
+    pub async fn connect<S: Into<String>>(endpoint: S) -> Result<Self> {
+        ClientBuilder::new(endpoint).connect().await
+    }
+
+    /// Predict the digit in an image file
+    pub async fn predict_image<P: AsRef<Path>>(&self, path: P) -> Result<MnistPrediction> {
+        let path = path.as_ref();
+        let data = tokio::fs::read(path)
+            .await
+            .map_err(|e| Error::Image(path.to_path_buf(), e))?;
+        self.predict_bytes(data).await
+    }
+
+    /// Predict the digit in an encoded image, e.g. the contents of a PNG file
+    pub async fn predict_bytes<B: Into<Vec<u8>>>(&self, image: B) -> Result<MnistPrediction> {
+        self.predict(MnistImage {
+            data: image.into(),
+            ..Default::default()
+        })
+        .await
+    }
+
+    /// Send a request with all options, addressed to the configured model if it names none
+    pub async fn predict(&self, mut request: MnistImage) -> Result<MnistPrediction> {
+        if request.model.is_empty() {
+            request.model.clone_from(&self.model);
+        }
+        self.call(request, |mut client, request| async move {
+            client.predict(request).await
+        })
+        .await
+    }
+
+    /// Predict several images concurrently, returning the results in the same order
+    pub async fn predict_batch<I>(&self, images: I) -> Vec<Result<MnistPrediction>>
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+    {
+        futures_util::stream::iter(images)
+            .map(|image| self.predict_bytes(image))
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+
+    /// Predict the images of a stream as they arrive, yielding the results in order
+    pub fn predict_stream<S>(
+        &self,
+        images: S,
+    ) -> impl Stream<Item = Result<MnistPrediction>> + use<S>
+    where
+        S: Stream<Item = Vec<u8>>,
+    {
+        let client = self.clone();
+        images
+            .map(move |image| {
+                let client = client.clone();
+                async move { client.predict_bytes(image).await }
+            })
+            .buffered(self.concurrency)
+    }
+
+    /// Read a number of several digits, e.g. a field of a scanned form
+    pub async fn recognize_number<B: Into<Vec<u8>>>(&self, image: B) -> Result<RecognizedNumber> {
+        let request = NumberImage {
+            data: image.into(),
+            model: self.model.clone(),
+            ..Default::default()
+        };
+        self.call(request, |mut client, request| async move {
+            client.recognize_number(request).await
+        })
+        .await
+    }
+
+    /// Ask which pixels a prediction relies on, addressed to the configured model if the
+    /// request names none
+    pub async fn explain(&self, mut request: ExplainRequest) -> Result<Explanation> {
+        if request.model.is_empty() {
+            request.model.clone_from(&self.model);
+        }
+        self.call(request, |mut client, request| async move {
+            client.explain(request).await
+        })
+        .await
+    }
+
+    /// Penultimate-layer features of several images, computed in one forward pass
+    ///
+    /// With `normalize` every embedding has unit length, for cosine similarity.
+    pub async fn embed<I, B>(&self, images: I, normalize: bool) -> Result<Embeddings>
+    where
+        I: IntoIterator<Item = B>,
+        B: Into<Vec<u8>>,
+    {
+        let request = EmbedRequest {
+            images: images.into_iter().map(Into::into).collect(),
+            normalize,
+            model: self.model.clone(),
+        };
+        self.call(request, |mut client, request| async move {
+            client.embed(request).await
+        })
+        .await
+    }
+
+    /// Report the correct digit of an image, e.g. after a wrong prediction
+    ///
+    /// The call is not retried: the server may have stored the sample before the error
+    /// reached the client, and a retry would store it twice.
+    pub async fn submit_feedback<B: Into<Vec<u8>>>(
+        &self,
+        image: B,
+        label: i32,
+        predicted_label: Option<i32>,
+    ) -> Result<FeedbackReceipt> {
+        let feedback = Feedback {
+            data: image.into(),
+            label,
+            predicted_label,
+            model: self.model.clone(),
+        };
+        self.call_with(
+            RetryPolicy::none(),
+            feedback,
+            |mut client, request| async move { client.submit_feedback(request).await },
+        )
+        .await
+    }
+
+    /// Send `message`, retrying while the server is unavailable
+    async fn call<T, R, F, Fut>(&self, message: T, send: F) -> Result<R>
+    where
+        T: Clone,
+        F: Fn(GrpcClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        self.call_with(self.retry, message, send).await
+    }
+
+    /// Send `message`, retrying as `policy` allows
+    async fn call_with<T, R, F, Fut>(&self, policy: RetryPolicy, message: T, send: F) -> Result<R>
+    where
+        T: Clone,
+        F: Fn(GrpcClient<Channel>, Request<T>) -> Fut,
+        Fut: Future<Output = std::result::Result<Response<R>, Status>>,
+    {
+        let request_id = Uuid::new_v4().to_string();
+        let mut retry = 0;
+        loop {
+            let request = self.request(message.clone(), &request_id);
+            let status = match send(self.inner.clone(), request).await {
+                Ok(response) => return Ok(response.into_inner()),
+                Err(status) => status,
+            };
+            let Some(backoff) = policy.backoff(retry, status.code()) else {
+                return Err(status.into());
+            };
+            tracing::debug!(
+                request_id,
+                retry,
+                backoff_ms = backoff.as_millis(),
+                message = status.message(),
+                "Server unavailable, retrying"
+            );
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+        }
+    }
+
+    fn request<T>(&self, message: T, request_id: &str) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Ok(request_id) = MetadataValue::try_from(request_id) {
+            request.metadata_mut().insert(REQUEST_ID_HEADER, request_id);
+        }
+        if let Some(authorization) = &self.authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        if let Some(timeout) = self.timeout {
+            request.set_timeout(timeout);
+        }
+        request
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio_stream::wrappers::TcpListenerStream;
+    use tonic::Code;
+    use tonic::transport::Server;
+
+    use super::*;
+    use crate::proto::mnist_server::{Mnist, MnistServer};
+
+    /// Answers with the image size as label after failing `failures` times
+    #[derive(Default)]
+    struct FakeMnist {
+        failures: Mutex<usize>,
+        /// Request id, authorization and model of every attempt
+        requests: Arc<Mutex<Vec<(String, String, String)>>>,
+    }
+
+    #[tonic::async_trait]
+    impl Mnist for FakeMnist {
+        async fn predict(
+            &self,
+            request: Request<MnistImage>,
+        ) -> std::result::Result<Response<MnistPrediction>, Status> {
+            let header = |key: &str| {
+                request
+                    .metadata()
+                    .get(key)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_string()
+            };
+            self.requests.lock().unwrap().push((
+                header(REQUEST_ID_HEADER),
+                header("authorization"),
+                request.get_ref().model.clone(),
+            ));
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Status::unavailable("starting"));
+            }
+            if request.get_ref().data.is_empty() {
+                return Err(Status::invalid_argument("empty image"));
+            }
+            Ok(Response::new(MnistPrediction {
+                label: request.get_ref().data.len() as i32,
+                ..Default::default()
+            }))
+        }
+
+        async fn submit_feedback(
+            &self,
+            request: Request<Feedback>,
+        ) -> std::result::Result<Response<FeedbackReceipt>, Status> {
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(Default::default());
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Status::unavailable("starting"));
+            }
+            Ok(Response::new(FeedbackReceipt {
+                id: request.get_ref().label.to_string(),
+                samples: requests.len() as u64,
+            }))
+        }
+
+        async fn recognize_number(
+            &self,
+            _request: Request<NumberImage>,
+        ) -> std::result::Result<Response<RecognizedNumber>, Status> {
+            Err(Status::unimplemented("recognize_number"))
+        }
+
+        async fn explain(
+            &self,
+            _request: Request<ExplainRequest>,
+        ) -> std::result::Result<Response<Explanation>, Status> {
+            Err(Status::unimplemented("explain"))
+        }
+
+        async fn embed(
+            &self,
+            _request: Request<EmbedRequest>,
+        ) -> std::result::Result<Response<Embeddings>, Status> {
+            Err(Status::unimplemented("embed"))
+        }
+    }
+
+    async fn serve(failures: usize) -> (String, Arc<Mutex<Vec<(String, String, String)>>>) {
+        let fake = FakeMnist {
+            failures: Mutex::new(failures),
+            ..Default::default()
+        };
+        let requests = Arc::clone(&fake.requests);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(
+            Server::builder()
+                .add_service(MnistServer::new(fake))
+                .serve_with_incoming(TcpListenerStream::new(listener)),
+        );
+        (format!("http://{}", address), requests)
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy::default().with_initial_backoff(Duration::from_millis(1))
+    }
+
+    #[tokio::test]
+    async fn test_retry_unavailable() {
+        let (endpoint, requests) = serve(2).await;
+        let client = MnistClient::builder(endpoint)
+            .retry_policy(retry_policy())
+            .bearer_token("secret")
+            .model("mnist")
+            .timeout(Duration::from_secs(5))
+            .connect()
+            .await
+            .unwrap();
+        let prediction = client.predict_bytes(vec![1, 2, 3]).await.unwrap();
+        assert_eq!(prediction.label, 3);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 3);
+        let (request_id, authorization, model) = &requests[0];
+        assert!(Uuid::parse_str(request_id).is_ok());
+        assert_eq!(authorization, "Bearer secret");
+        assert_eq!(model, "mnist");
+        // Retries keep the id of the call
+        assert!(requests.iter().all(|request| &request.0 == request_id));
+    }
+
+    #[tokio::test]
+    async fn test_no_retry() {
+        let (endpoint, requests) = serve(5).await;
+        let client = MnistClient::builder(&endpoint)
+            .retry_policy(retry_policy().with_max_retries(1))
+            .connect()
+            .await
+            .unwrap();
+        let error = client.predict_bytes(vec![1]).await.unwrap_err();
+        assert_eq!(error.code(), Some(Code::Unavailable));
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        let (endpoint, requests) = serve(0).await;
+        let client = MnistClient::connect(endpoint).await.unwrap();
+        let error = client.predict_bytes(Vec::new()).await.unwrap_err();
+        assert_eq!(error.code(), Some(Code::InvalidArgument));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_feedback_not_retried() {
+        let (endpoint, requests) = serve(1).await;
+        let client = MnistClient::builder(endpoint)
+            .retry_policy(retry_policy())
+            .connect()
+            .await
+            .unwrap();
+        let error = client.submit_feedback(vec![1], 7, None).await.unwrap_err();
+        assert_eq!(error.code(), Some(Code::Unavailable));
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        let receipt = client.submit_feedback(vec![1], 7, None).await.unwrap();
+        assert_eq!(receipt.id, "7");
+        assert_eq!(requests.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_predict_batch_and_stream() {
+        let (endpoint, requests) = serve(0).await;
+        let client = MnistClient::builder(endpoint)
+            .concurrency(3)
+            .connect_lazy()
+            .unwrap();
+        let images: Vec<Vec<u8>> = (1..=5).map(|size| vec![0; size]).collect();
+
+        let labels: Vec<i32> = client
+            .predict_batch(images.clone())
+            .await
+            .into_iter()
+            .map(|prediction| prediction.unwrap().label)
+            .collect();
+        assert_eq!(labels, vec![1, 2, 3, 4, 5]);
+
+        let labels: Vec<i32> = client
+            .predict_stream(futures_util::stream::iter(images))
+            .map(|prediction| prediction.unwrap().label)
+            .collect()
+            .await;
+        assert_eq!(labels, vec![1, 2, 3, 4, 5]);
+
+        // Every call gets its own id
+        let requests = requests.lock().unwrap();
+        let mut ids: Vec<&String> = requests.iter().map(|request| &request.0).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 10);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let error = MnistClient::builder("not a uri")
+            .connect_lazy()
+            .unwrap_err();
+        assert!(matches!(error, Error::Config(_)));
+        let error = MnistClient::builder("http://[::1]:50051")
+            .bearer_token("new\nline")
+            .connect_lazy()
+            .unwrap_err();
+        assert!(matches!(error, Error::Config(_)));
+    }
+}
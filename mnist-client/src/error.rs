@@ -0,0 +1,59 @@
+use tonic::{Code, Status};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+use derive_more::{Display, From};
+
+#[derive(Debug, Display, From)]
+pub enum Error {
+    /// Invalid client configuration, e.g. a malformed endpoint or token
+    #[display("Invalid configuration: {_0}")]
+    Config(String),
+
+    /// The image file could not be read
+    #[display("Failed to read {}: {}", _0.display(), _1)]
+    Image(std::path::PathBuf, std::io::Error),
+
+    /// The connection to the server failed
+    #[from]
+    #[display("Transport error: {}", with_sources(_0))]
+    Transport(tonic::transport::Error),
+
+    /// The server answered with an error, after retries if it was unavailable
+    #[display("{}: {}", _0.code(), _0.message())]
+    Status(Box<Status>),
+}
+
+impl Error {
+    pub fn config<S: Into<String>>(msg: S) -> Self {
+        Error::Config(msg.into())
+    }
+
+    /// gRPC status code if the server answered with an error
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            Error::Status(status) => Some(status.code()),
+            _ => None,
+        }
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        Error::Status(Box::new(status))
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Message of an error followed by its causes, tonic keeps the details in the sources
+fn with_sources(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
@@ -0,0 +1,35 @@
+//! Async client for the MNIST gRPC server
+//!
+//! ```no_run
+//! # async fn run() -> mnist_client::Result<()> {
+//! use std::time::Duration;
+//!
+//! let client = mnist_client::MnistClient::builder("http://[::1]:50051")
+//!     .timeout(Duration::from_secs(2))
+//!     .bearer_token("secret")
+//!     .connect()
+//!     .await?;
+//! let prediction = client.predict_image("digit.png").await?;
+//! println!("{} ({:.2})", prediction.label, prediction.probabilities[prediction.label as usize]);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Every call carries a fresh [`REQUEST_ID_HEADER`], is retried with backoff while the
+//! server is unavailable (see [`RetryPolicy`]) and is sent with the configured deadline.
+//! Feedback is the exception to retries, as it must not be stored twice.
+pub mod client;
+pub mod error;
+pub mod retry;
+
+pub use client::{ClientBuilder, MnistClient, REQUEST_ID_HEADER};
+pub use error::{Error, Result};
+pub use proto::{
+    Embeddings, ExplainMethod, ExplainRequest, Explanation, FeedbackReceipt, MnistImage,
+    MnistPrediction, OutputMode, RecognizedNumber,
+};
+pub use retry::RetryPolicy;
+
+pub mod proto {
+    tonic::include_proto!("mnist");
+}
@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use tonic::Code;
+
+/// Exponential backoff for calls failing with `UNAVAILABLE`
+///
+/// The server answers `UNAVAILABLE` while it is starting or shutting down, and tonic
+/// reports connection failures with the same code. The policy applies to the calls that
+/// only read, which can be repeated; `SubmitFeedback` stores a sample and is never retried.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt, 0 disables retries
+    pub max_retries: u32,
+    /// Delay before the first retry
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between two attempts
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after every retry
+    pub multiplier: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(2),
+            multiplier: 2.0,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Fail on the first error
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_initial_backoff(mut self, backoff: Duration) -> Self {
+        self.initial_backoff = backoff;
+        self
+    }
+
+    pub fn with_max_backoff(mut self, backoff: Duration) -> Self {
+        self.max_backoff = backoff;
+        self
+    }
+
+    /// Factor the delay grows by, at least 1
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Delay before retry number `retry`, starting at 0, or `None` once retries are exhausted
+    pub fn backoff(&self, retry: u32, code: Code) -> Option<Duration> {
+        if code != Code::Unavailable || retry >= self.max_retries {
+            return None;
+        }
+        // The fields are public, so the multiplier may not have gone through the builder
+        let multiplier = self.multiplier.max(1.0);
+        let backoff = self.initial_backoff.as_secs_f64() * multiplier.powi(retry as i32);
+        Some(Duration::from_secs_f64(
+            backoff.min(self.max_backoff.as_secs_f64()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default().with_max_backoff(Duration::from_millis(300));
+        assert_eq!(
+            policy.backoff(0, Code::Unavailable),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.backoff(1, Code::Unavailable),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            policy.backoff(2, Code::Unavailable),
+            Some(Duration::from_millis(300))
+        );
+        assert_eq!(policy.backoff(3, Code::Unavailable), None);
+        assert_eq!(policy.backoff(0, Code::InvalidArgument), None);
+        assert_eq!(RetryPolicy::none().backoff(0, Code::Unavailable), None);
+    }
+
+    #[test]
+    fn test_invalid_multiplier() {
+        for multiplier in [-2.0, 0.5, f64::NAN] {
+            let policy = RetryPolicy::default().with_multiplier(multiplier);
+            assert_eq!(policy.multiplier, 1.0);
+            assert_eq!(
+                policy.backoff(1, Code::Unavailable),
+                Some(Duration::from_millis(100))
+            );
+        }
+
+        let policy = RetryPolicy {
+            multiplier: -3.0,
+            ..RetryPolicy::default()
+        };
+        assert_eq!(
+            policy.backoff(1, Code::Unavailable),
+            Some(Duration::from_millis(100))
+        );
+        let policy = RetryPolicy::default().with_multiplier(f64::MAX);
+        assert_eq!(
+            policy.backoff(2, Code::Unavailable),
+            Some(Duration::from_secs(2))
+        );
+    }
+}
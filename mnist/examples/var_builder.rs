@@ -4,6 +4,7 @@ use candle_nn::{
     linear::{Linear, linear},
 };
 
+#[allow(clippy::upper_case_acronyms)]
 struct MLP {
     layer1: Linear,
     layer2: Linear,
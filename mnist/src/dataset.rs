@@ -0,0 +1,388 @@
+//! Reader for the MNIST dataset in its original IDX format
+//!
+//! The files from <http://yann.lecun.com/exdb/mnist/> (`t10k-images-idx3-ubyte`,
+//! `t10k-labels-idx1-ubyte`, ...) can be read as is or gzip compressed (`.gz`).
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use candle_core::bail;
+
+type Result<T> = std::result::Result<T, candle_core::Error>;
+
+/// Magic number of an IDX file holding unsigned bytes in `dims` dimensions
+fn magic(dims: u8) -> u32 {
+    0x0800 | dims as u32
+}
+
+/// Labeled images read from a pair of IDX files
+#[derive(Debug, Clone)]
+pub struct MnistDataset {
+    images: Vec<u8>,
+    labels: Vec<u8>,
+    rows: usize,
+    cols: usize,
+}
+
+impl MnistDataset {
+    /// Read an images file (`idx3`) and the matching labels file (`idx1`)
+    pub fn load<P: AsRef<Path>, Q: AsRef<Path>>(images: P, labels: Q) -> Result<Self> {
+        let (image_dims, images) = read_idx(images.as_ref(), 3)?;
+        let (label_dims, labels) = read_idx(labels.as_ref(), 1)?;
+        if image_dims[0] != label_dims[0] {
+            bail!("{} images but {} labels", image_dims[0], label_dims[0])
+        }
+        if let Some(label) = labels.iter().find(|&&label| label > 9) {
+            bail!("invalid label {label}, expected a digit")
+        }
+        Ok(Self {
+            images,
+            labels,
+            rows: image_dims[1],
+            cols: image_dims[2],
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// Height and width of the images
+    pub fn image_size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Raw pixels of an image, row by row, white digits on a black background
+    pub fn image(&self, index: usize) -> &[u8] {
+        let size = self.rows * self.cols;
+        &self.images[index * size..(index + 1) * size]
+    }
+
+    /// Pixels of an image scaled to [0, 1]
+    pub fn pixels(&self, index: usize) -> Vec<f32> {
+        self.image(index)
+            .iter()
+            .map(|&pixel| pixel as f32 / 255.0)
+            .collect()
+    }
+
+    pub fn label(&self, index: usize) -> u8 {
+        self.labels[index]
+    }
+
+    pub fn labels(&self) -> &[u8] {
+        &self.labels
+    }
+
+    /// Keep only the first `len` samples
+    pub fn truncate(&mut self, len: usize) {
+        if len < self.len() {
+            self.labels.truncate(len);
+            self.images.truncate(len * self.rows * self.cols);
+        }
+    }
+
+    /// Append the samples of another dataset with the same image size
+    pub fn extend(&mut self, other: &MnistDataset) -> Result<()> {
+        if other.image_size() != self.image_size() {
+            bail!(
+                "cannot merge {:?} images into a dataset of {:?} images",
+                other.image_size(),
+                self.image_size()
+            )
+        }
+        self.images.extend_from_slice(&other.images);
+        self.labels.extend_from_slice(&other.labels);
+        Ok(())
+    }
+
+    /// Append one sample to a pair of uncompressed IDX files, creating them if needed
+    ///
+    /// `image` holds `rows * cols` pixels in the layout of [`MnistDataset::image`].
+    /// Returns the number of samples in the files afterwards.
+    ///
+    /// The counts in the headers are only updated once both items are written, so an
+    /// interrupted append leaves uncounted bytes behind, which reading ignores and the next
+    /// append overwrites.
+    pub fn append<P: AsRef<Path>, Q: AsRef<Path>>(
+        images: P,
+        labels: Q,
+        (rows, cols): (usize, usize),
+        image: &[u8],
+        label: u8,
+    ) -> Result<usize> {
+        if image.len() != rows * cols {
+            bail!("expected {} pixels, got {}", rows * cols, image.len())
+        }
+        if label > 9 {
+            bail!("invalid label {label}, expected a digit")
+        }
+        let mut images = IdxAppender::open(images.as_ref(), &[rows, cols])?;
+        let mut labels = IdxAppender::open(labels.as_ref(), &[])?;
+        // An append interrupted between the two counts leaves one image more than labels
+        let count = match images.count.checked_sub(labels.count) {
+            Some(0 | 1) => labels.count,
+            _ => bail!("{} images but {} labels", images.count, labels.count),
+        };
+        images.write_item(count, image)?;
+        labels.write_item(count, &[label])?;
+        images.set_count(count + 1)?;
+        labels.set_count(count + 1)?;
+        Ok(count + 1)
+    }
+}
+
+/// Uncompressed IDX file of unsigned bytes opened for appending
+struct IdxAppender {
+    file: File,
+    header_len: usize,
+    item_size: usize,
+    /// Number of items in the header
+    count: usize,
+}
+
+impl IdxAppender {
+    /// Open or create the file, checking that it holds items of shape `item_dims`
+    fn open(path: &Path, item_dims: &[usize]) -> Result<Self> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| {
+                candle_core::Error::msg(format!("failed to open {}: {e}", path.display()))
+            })?;
+
+        let dims = 1 + item_dims.len();
+        let header_len = 4 + 4 * dims;
+        let item_size = item_dims.iter().product();
+        let len = file.metadata()?.len() as usize;
+        let count = if len == 0 {
+            file.write_all(&magic(dims as u8).to_be_bytes())?;
+            file.write_all(&0u32.to_be_bytes())?;
+            for &dim in item_dims {
+                file.write_all(&(dim as u32).to_be_bytes())?;
+            }
+            0
+        } else {
+            if len < header_len {
+                bail!("{} is too short for an IDX file", path.display())
+            }
+            let mut header = vec![0u8; header_len];
+            file.read_exact(&mut header)?;
+            let word = |i: usize| u32::from_be_bytes(header[4 * i..4 * i + 4].try_into().unwrap());
+            if word(0) != magic(dims as u8) {
+                bail!(
+                    "{} is not an IDX file of {dims} dimensional unsigned bytes (magic {:#010x})",
+                    path.display(),
+                    word(0)
+                )
+            }
+            let found: Vec<usize> = (2..=dims).map(|i| word(i) as usize).collect();
+            if found != item_dims {
+                bail!(
+                    "{} holds items of shape {found:?}, expected {item_dims:?}",
+                    path.display()
+                )
+            }
+            let count = word(1) as usize;
+            if len < header_len + count * item_size {
+                bail!(
+                    "{} holds {} bytes of data, expected {} for {count} items",
+                    path.display(),
+                    len - header_len,
+                    count * item_size
+                )
+            }
+            count
+        };
+        Ok(Self {
+            file,
+            header_len,
+            item_size,
+            count,
+        })
+    }
+
+    /// Write `item` at position `index`, dropping the bytes after it
+    fn write_item(&mut self, index: usize, item: &[u8]) -> Result<()> {
+        let offset = (self.header_len + index * self.item_size) as u64;
+        self.file.set_len(offset)?;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(item)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Set the number of items in the header, once they are written
+    fn set_count(&mut self, count: usize) -> Result<()> {
+        let Ok(count) = u32::try_from(count) else {
+            bail!("an IDX file holds at most {} items", u32::MAX)
+        };
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file.write_all(&count.to_be_bytes())?;
+        self.file.sync_data()?;
+        self.count = count as usize;
+        Ok(())
+    }
+}
+
+/// Read the dimensions and data of an IDX file of unsigned bytes
+fn read_idx(path: &Path, dims: u8) -> Result<(Vec<usize>, Vec<u8>)> {
+    let file = std::fs::File::open(path)
+        .map_err(|e| candle_core::Error::msg(format!("failed to open {}: {e}", path.display())))?;
+    let mut data = Vec::new();
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        flate2::read::GzDecoder::new(file).read_to_end(&mut data)?;
+    } else {
+        std::io::BufReader::new(file).read_to_end(&mut data)?;
+    }
+
+    let header_len = 4 + 4 * dims as usize;
+    if data.len() < header_len {
+        bail!("{} is too short for an IDX file", path.display())
+    }
+    let word = |i: usize| u32::from_be_bytes(data[4 * i..4 * i + 4].try_into().unwrap());
+    if word(0) != magic(dims) {
+        bail!(
+            "{} is not an IDX file of {dims} dimensional unsigned bytes (magic {:#010x})",
+            path.display(),
+            word(0)
+        )
+    }
+    let shape: Vec<usize> = (1..=dims as usize).map(|i| word(i) as usize).collect();
+    let len: usize = shape.iter().product();
+    // Bytes after the counted items are left by an interrupted append
+    if data.len() - header_len < len {
+        bail!(
+            "{} holds {} bytes of data, expected {len} for shape {shape:?}",
+            path.display(),
+            data.len() - header_len
+        )
+    }
+    data.truncate(header_len + len);
+    data.drain(..header_len);
+    Ok((shape, data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mnist-idx-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn image(value: u8) -> Vec<u8> {
+        vec![value; 4 * 3]
+    }
+
+    /// Append the bytes to a file without updating its header, as an interrupted append
+    fn append_uncounted(path: &Path, bytes: &[u8]) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let dir = temp_dir("append");
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        for (i, label) in [3u8, 7, 0].into_iter().enumerate() {
+            let count =
+                MnistDataset::append(&images, &labels, (4, 3), &image(label * 10), label).unwrap();
+            assert_eq!(count, i + 1);
+        }
+
+        let dataset = MnistDataset::load(&images, &labels).unwrap();
+        assert_eq!(dataset.len(), 3);
+        assert_eq!(dataset.image_size(), (4, 3));
+        assert_eq!(dataset.labels(), &[3, 7, 0]);
+        assert_eq!(dataset.image(1), image(70).as_slice());
+        assert_eq!(dataset.pixels(0)[0], 30.0 / 255.0);
+
+        assert!(MnistDataset::append(&images, &labels, (4, 3), &image(0), 10).is_err());
+        assert!(MnistDataset::append(&images, &labels, (4, 3), &[0; 5], 1).is_err());
+        // The images of the files are 4x3
+        assert!(MnistDataset::append(&images, &labels, (3, 4), &image(0), 1).is_err());
+        assert!(MnistDataset::append(&labels, &images, (4, 3), &image(0), 1).is_err());
+        assert_eq!(MnistDataset::load(&images, &labels).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_load_gzip() {
+        let dir = temp_dir("gzip");
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        MnistDataset::append(&images, &labels, (4, 3), &image(1), 1).unwrap();
+        MnistDataset::append(&images, &labels, (4, 3), &image(2), 2).unwrap();
+
+        let gzip = |path: &Path| {
+            let gz = path.with_extension("gz");
+            let mut encoder = flate2::write::GzEncoder::new(
+                File::create(&gz).unwrap(),
+                flate2::Compression::default(),
+            );
+            encoder.write_all(&std::fs::read(path).unwrap()).unwrap();
+            encoder.finish().unwrap();
+            gz
+        };
+        let dataset = MnistDataset::load(gzip(&images), gzip(&labels)).unwrap();
+        assert_eq!(dataset.labels(), &[1, 2]);
+        assert_eq!(dataset.image(1), image(2).as_slice());
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let dir = temp_dir("invalid");
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        MnistDataset::append(&images, &labels, (4, 3), &image(1), 1).unwrap();
+
+        // Files swapped, and fewer bytes than the header counts
+        assert!(MnistDataset::load(&labels, &images).is_err());
+        let data = std::fs::read(&images).unwrap();
+        std::fs::write(&images, &data[..data.len() - 1]).unwrap();
+        assert!(MnistDataset::load(&images, &labels).is_err());
+        assert!(MnistDataset::append(&images, &labels, (4, 3), &image(2), 2).is_err());
+        std::fs::write(&images, &data[..10]).unwrap();
+        assert!(MnistDataset::load(&images, &labels).is_err());
+    }
+
+    #[test]
+    fn test_interrupted_append() {
+        let dir = temp_dir("interrupted");
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        MnistDataset::append(&images, &labels, (4, 3), &image(1), 1).unwrap();
+
+        // Bytes written before the headers were updated are ignored, then overwritten
+        append_uncounted(&images, &image(9)[..7]);
+        append_uncounted(&labels, &[9]);
+        assert_eq!(MnistDataset::load(&images, &labels).unwrap().len(), 1);
+        assert_eq!(
+            MnistDataset::append(&images, &labels, (4, 3), &image(2), 2).unwrap(),
+            2
+        );
+        let dataset = MnistDataset::load(&images, &labels).unwrap();
+        assert_eq!(dataset.labels(), &[1, 2]);
+        assert_eq!(dataset.image(1), image(2).as_slice());
+
+        // Interrupted after counting the image but not its label
+        let mut appender = IdxAppender::open(&images, &[4, 3]).unwrap();
+        appender.write_item(2, &image(9)).unwrap();
+        appender.set_count(3).unwrap();
+        assert!(MnistDataset::load(&images, &labels).is_err());
+        assert_eq!(
+            MnistDataset::append(&images, &labels, (4, 3), &image(3), 3).unwrap(),
+            3
+        );
+        let dataset = MnistDataset::load(&images, &labels).unwrap();
+        assert_eq!(dataset.labels(), &[1, 2, 3]);
+        assert_eq!(dataset.image(2), image(3).as_slice());
+    }
+}
@@ -2,6 +2,16 @@ use candle_core::Tensor;
 use candle_nn::Module;
 use candle_nn::{self as nn};
 
+pub mod dataset;
+pub mod quantized;
+pub mod spec;
+pub mod train;
+
+pub use dataset::MnistDataset;
+pub use quantized::{Quantization, QuantizedConvNet, QuantizedMLP, QuantizedWeights};
+pub use spec::{LayerSpec, ModelSpec, SequentialModel};
+pub use train::{TrainConfig, Trainer};
+
 type Result<T> = std::result::Result<T, candle_core::Error>;
 
 #[derive(Debug)]
@@ -20,12 +30,27 @@ impl MnistMLP {
         Ok(Self { fc1, fc2, fc3 })
     }
 
+    /// Returns the unnormalized class scores (logits)
     pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.fc3.forward(&self.forward_features(x)?)
+    }
+
+    /// Returns the penultimate activations, the 64 `fc2` outputs after the ReLU
+    pub fn forward_features(&self, x: &Tensor) -> Result<Tensor> {
         let x = self.fc1.forward(x)?.relu()?;
-        let x = self.fc2.forward(&x)?.relu()?;
-        let x = self.fc3.forward(&x)?;
-        let out = nn::ops::softmax_last_dim(&x)?;
-        Ok(out)
+        self.fc2.forward(&x)?.relu()
+    }
+
+    /// Returns the logits and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let features = self.forward_features(x)?;
+        Ok((self.fc3.forward(&features)?, features))
+    }
+}
+
+impl Module for MnistMLP {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        MnistMLP::forward(self, xs)
     }
 }
 
@@ -69,7 +94,13 @@ impl ConvNet {
         })
     }
 
+    /// Returns the unnormalized class scores (logits)
     pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.linear_2.forward(&self.forward_features(x)?)
+    }
+
+    /// Returns the penultimate activations, the 128 `linear_1` outputs after the ReLU
+    pub fn forward_features(&self, x: &Tensor) -> Result<Tensor> {
         let x = self.conv2d_1.forward(x)?;
         let x = x.relu()?;
         let x = x.max_pool2d((2, 2))?;
@@ -78,8 +109,18 @@ impl ConvNet {
         let x = x.max_pool2d((2, 2))?;
         let x = x.flatten(1, 3)?;
         let x = self.linear_1.forward(&x)?;
-        let x = x.relu()?;
-        let x = self.linear_2.forward(&x)?;
-        candle_nn::ops::softmax_last_dim(&x)
+        x.relu()
+    }
+
+    /// Returns the logits and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let features = self.forward_features(x)?;
+        Ok((self.linear_2.forward(&features)?, features))
+    }
+}
+
+impl Module for ConvNet {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        ConvNet::forward(self, xs)
     }
 }
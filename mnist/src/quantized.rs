@@ -0,0 +1,329 @@
+//! Quantized variants of the MNIST models
+//!
+//! The weights of the linear layers are stored as ggml quantized tensors (e.g. 8-bit
+//! `q8_0` blocks) and multiplied without being dequantized. Convolutions and biases are
+//! small and stay in f32. Quantized weights are saved and loaded as GGUF files.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{Read, Seek, Write};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use candle_core::quantized::{GgmlDType, QMatMul, QTensor, gguf_file};
+use candle_core::{Device, Module, Tensor, bail};
+use candle_nn::{Conv2d, Conv2dConfig};
+
+type Result<T> = std::result::Result<T, candle_core::Error>;
+
+/// Format of the quantized linear weights
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantization {
+    F16,
+    Q8_0,
+    Q5_1,
+    Q5_0,
+    Q4_1,
+    Q4_0,
+}
+
+impl Quantization {
+    pub fn dtype(&self) -> GgmlDType {
+        match self {
+            Quantization::F16 => GgmlDType::F16,
+            Quantization::Q8_0 => GgmlDType::Q8_0,
+            Quantization::Q5_1 => GgmlDType::Q5_1,
+            Quantization::Q5_0 => GgmlDType::Q5_0,
+            Quantization::Q4_1 => GgmlDType::Q4_1,
+            Quantization::Q4_0 => GgmlDType::Q4_0,
+        }
+    }
+}
+
+impl fmt::Display for Quantization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Quantization::F16 => "f16",
+            Quantization::Q8_0 => "q8_0",
+            Quantization::Q5_1 => "q5_1",
+            Quantization::Q5_0 => "q5_0",
+            Quantization::Q4_1 => "q4_1",
+            Quantization::Q4_0 => "q4_0",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Quantization {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "f16" => Ok(Quantization::F16),
+            "q8_0" => Ok(Quantization::Q8_0),
+            "q5_1" => Ok(Quantization::Q5_1),
+            "q5_0" => Ok(Quantization::Q5_0),
+            "q4_1" => Ok(Quantization::Q4_1),
+            "q4_0" => Ok(Quantization::Q4_0),
+            _ => Err(format!(
+                "unknown quantization '{s}', expected one of f16, q8_0, q5_1, q5_0, q4_1, q4_0"
+            )),
+        }
+    }
+}
+
+/// Columns of a matrix with `cols` columns once quantized to `dtype`
+///
+/// Some formats process blocks in pairs, so the columns are zero-padded to a multiple of
+/// two blocks (784 inputs become 832 for 32-element blocks).
+pub fn padded_columns(cols: usize, dtype: GgmlDType) -> usize {
+    cols.next_multiple_of(2 * dtype.block_size())
+}
+
+/// Tensors of a quantized model, by name
+#[derive(Debug, Clone)]
+pub struct QuantizedWeights {
+    tensors: HashMap<String, Arc<QTensor>>,
+    device: Device,
+}
+
+impl QuantizedWeights {
+    /// Quantize the weights of a model
+    ///
+    /// Matrices are quantized, with their columns zero-padded as in [`padded_columns`].
+    /// Other tensors are kept in f32.
+    pub fn quantize(
+        tensors: &HashMap<String, Tensor>,
+        quantization: Quantization,
+        device: &Device,
+    ) -> Result<Self> {
+        let dtype = quantization.dtype();
+        let tensors = tensors
+            .iter()
+            .map(|(name, tensor)| {
+                let tensor = tensor.to_dtype(candle_core::DType::F32)?;
+                let qtensor = match tensor.dims() {
+                    &[_, cols] => {
+                        let padding = padded_columns(cols, dtype) - cols;
+                        let tensor = tensor.pad_with_zeros(1, 0, padding)?;
+                        QTensor::quantize_onto(&tensor, dtype, device)?
+                    }
+                    _ => QTensor::quantize_onto(&tensor, GgmlDType::F32, device)?,
+                };
+                Ok((name.clone(), Arc::new(qtensor)))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            tensors,
+            device: device.clone(),
+        })
+    }
+
+    /// Read the tensors of a GGUF file
+    pub fn from_gguf<R: Read + Seek>(reader: &mut R, device: &Device) -> Result<Self> {
+        let content = gguf_file::Content::read(reader)?;
+        let mut tensors = HashMap::new();
+        for name in content.tensor_infos.keys() {
+            let tensor = content.tensor(reader, name, device)?;
+            tensors.insert(name.clone(), Arc::new(tensor));
+        }
+        Ok(Self {
+            tensors,
+            device: device.clone(),
+        })
+    }
+
+    /// Write the tensors to a GGUF file with the given metadata
+    pub fn write_gguf<W: Write + Seek>(
+        &self,
+        writer: &mut W,
+        metadata: &[(&str, &gguf_file::Value)],
+    ) -> Result<()> {
+        let mut names: Vec<&String> = self.tensors.keys().collect();
+        names.sort();
+        let tensors: Vec<(&str, &QTensor)> = names
+            .into_iter()
+            .map(|name| (name.as_str(), self.tensors[name].as_ref()))
+            .collect();
+        gguf_file::write(writer, metadata, &tensors)
+    }
+
+    /// Names, shapes and formats of the tensors, sorted by name
+    pub fn tensor_infos(&self) -> Vec<(String, Vec<usize>, GgmlDType)> {
+        let mut infos: Vec<_> = self
+            .tensors
+            .iter()
+            .map(|(name, tensor)| (name.clone(), tensor.shape().dims().to_vec(), tensor.dtype()))
+            .collect();
+        infos.sort_by(|a, b| a.0.cmp(&b.0));
+        infos
+    }
+
+    /// Size of the tensor data in bytes
+    pub fn storage_size(&self) -> usize {
+        self.tensors
+            .values()
+            .map(|tensor| tensor.storage_size_in_bytes())
+            .sum()
+    }
+
+    fn qtensor(&self, name: &str) -> Result<Arc<QTensor>> {
+        match self.tensors.get(name) {
+            Some(tensor) => Ok(Arc::clone(tensor)),
+            None => bail!("cannot find tensor {name} in the quantized weights"),
+        }
+    }
+
+    /// Dequantized tensor, which must have the given shape
+    fn tensor(&self, name: &str, dims: &[usize]) -> Result<Tensor> {
+        let tensor = self.qtensor(name)?.dequantize(&self.device)?;
+        if tensor.dims() != dims {
+            bail!(
+                "tensor {name} has shape {:?}, expected {dims:?}",
+                tensor.dims()
+            );
+        }
+        Ok(tensor)
+    }
+}
+
+/// Linear layer multiplying by a quantized weight matrix
+#[derive(Debug, Clone)]
+pub struct QLinear {
+    weight: QMatMul,
+    bias: Tensor,
+    /// Columns of the stored weight, which may be padded beyond the input size
+    in_features: usize,
+}
+
+impl QLinear {
+    /// Layer mapping `in_dim` inputs to `out_dim` outputs
+    ///
+    /// The columns of the weight may be padded up to [`padded_columns`] of `in_dim`.
+    pub fn new(
+        weights: &QuantizedWeights,
+        prefix: &str,
+        in_dim: usize,
+        out_dim: usize,
+    ) -> Result<Self> {
+        let weight = weights.qtensor(&format!("{prefix}.weight"))?;
+        let (rows, in_features) = weight.shape().dims2()?;
+        if rows != out_dim
+            || in_features < in_dim
+            || in_features > padded_columns(in_dim, weight.dtype())
+        {
+            bail!(
+                "tensor {prefix}.weight has shape [{rows}, {in_features}], expected [{out_dim}, {in_dim}]"
+            );
+        }
+        Ok(Self {
+            weight: QMatMul::from_arc(weight)?,
+            bias: weights.tensor(&format!("{prefix}.bias"), &[out_dim])?,
+            in_features,
+        })
+    }
+}
+
+impl Module for QLinear {
+    fn forward(&self, xs: &Tensor) -> Result<Tensor> {
+        let padding = self
+            .in_features
+            .saturating_sub(xs.dim(candle_core::D::Minus1)?);
+        let xs = if padding > 0 {
+            xs.pad_with_zeros(candle_core::D::Minus1, 0, padding)?
+        } else {
+            xs.contiguous()?
+        };
+        self.weight.forward(&xs)?.broadcast_add(&self.bias)
+    }
+}
+
+/// [`crate::MnistMLP`] with quantized layers
+#[derive(Debug)]
+pub struct QuantizedMLP {
+    fc1: QLinear,
+    fc2: QLinear,
+    fc3: QLinear,
+}
+
+impl QuantizedMLP {
+    pub fn new(weights: &QuantizedWeights) -> Result<Self> {
+        Ok(Self {
+            fc1: QLinear::new(weights, "fc1", 784, 128)?,
+            fc2: QLinear::new(weights, "fc2", 128, 64)?,
+            fc3: QLinear::new(weights, "fc3", 64, 10)?,
+        })
+    }
+
+    /// Returns the penultimate activations, see [`crate::MnistMLP::forward_features`]
+    pub fn forward_features(&self, x: &Tensor) -> Result<Tensor> {
+        let x = self.fc1.forward(x)?.relu()?;
+        self.fc2.forward(&x)?.relu()
+    }
+
+    /// Returns the logits and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let features = self.forward_features(x)?;
+        Ok((self.fc3.forward(&features)?, features))
+    }
+}
+
+impl Module for QuantizedMLP {
+    /// Returns the unnormalized class scores (logits)
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.fc3.forward(&self.forward_features(x)?)
+    }
+}
+
+/// [`crate::ConvNet`] with quantized linear layers
+#[derive(Debug)]
+pub struct QuantizedConvNet {
+    conv2d_1: Conv2d,
+    conv2d_2: Conv2d,
+    linear_1: QLinear,
+    linear_2: QLinear,
+}
+
+impl QuantizedConvNet {
+    pub fn new(weights: &QuantizedWeights) -> Result<Self> {
+        let conv = |prefix: &str, in_channels: usize, out_channels: usize| -> Result<Conv2d> {
+            Ok(Conv2d::new(
+                weights.tensor(
+                    &format!("{prefix}.weight"),
+                    &[out_channels, in_channels, 3, 3],
+                )?,
+                Some(weights.tensor(&format!("{prefix}.bias"), &[out_channels])?),
+                Conv2dConfig {
+                    padding: 1,
+                    ..Default::default()
+                },
+            ))
+        };
+        Ok(Self {
+            conv2d_1: conv("conv2d_1", 1, 32)?,
+            conv2d_2: conv("conv2d_2", 32, 64)?,
+            linear_1: QLinear::new(weights, "linear_1", 64 * 7 * 7, 128)?,
+            linear_2: QLinear::new(weights, "linear_2", 128, 10)?,
+        })
+    }
+
+    /// Returns the penultimate activations, see [`crate::ConvNet::forward_features`]
+    pub fn forward_features(&self, x: &Tensor) -> Result<Tensor> {
+        let x = self.conv2d_1.forward(x)?.relu()?.max_pool2d((2, 2))?;
+        let x = self.conv2d_2.forward(&x)?.relu()?.max_pool2d((2, 2))?;
+        let x = x.flatten(1, 3)?;
+        self.linear_1.forward(&x)?.relu()
+    }
+
+    /// Returns the logits and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let features = self.forward_features(x)?;
+        Ok((self.linear_2.forward(&features)?, features))
+    }
+}
+
+impl Module for QuantizedConvNet {
+    /// Returns the unnormalized class scores (logits)
+    fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        self.linear_2.forward(&self.forward_features(x)?)
+    }
+}
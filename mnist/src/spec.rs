@@ -0,0 +1,257 @@
+//! Declarative model definitions
+//!
+//! A [`ModelSpec`] describes a feed-forward network as a sequence of layers, so new
+//! topologies trained in Python can be served without writing Rust code. Layers with
+//! parameters read them from `<name>.weight` / `<name>.bias`, where the name defaults to
+//! the layer index like in a PyTorch `nn.Sequential`.
+//!
+//! ```json
+//! {
+//!   "input_shape": [1, 28, 28],
+//!   "layers": [
+//!     {"type": "conv2d", "in_channels": 1, "out_channels": 16, "kernel_size": 3, "padding": 1},
+//!     {"type": "relu"},
+//!     {"type": "maxpool2d", "kernel_size": 2},
+//!     {"type": "flatten"},
+//!     {"type": "linear", "in_features": 3136, "out_features": 10}
+//!   ]
+//! }
+//! ```
+use std::path::Path;
+
+use candle_core::{ModuleT, Tensor};
+use candle_nn::Module;
+use candle_nn::{self as nn};
+use serde::{Deserialize, Serialize};
+
+type Result<T> = std::result::Result<T, candle_core::Error>;
+
+/// Description of a sequential model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelSpec {
+    /// Shape of a single input sample, excluding the batch dimension
+    #[serde(default = "default_input_shape")]
+    pub input_shape: Vec<usize>,
+    pub layers: Vec<LayerSpec>,
+}
+
+fn default_input_shape() -> Vec<usize> {
+    vec![1, 28, 28]
+}
+
+/// A single layer of a [`ModelSpec`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LayerSpec {
+    Conv2d {
+        name: Option<String>,
+        in_channels: usize,
+        out_channels: usize,
+        kernel_size: usize,
+        #[serde(default)]
+        padding: usize,
+        #[serde(default = "one")]
+        stride: usize,
+    },
+    Linear {
+        name: Option<String>,
+        in_features: usize,
+        out_features: usize,
+        #[serde(default = "yes")]
+        bias: bool,
+    },
+    #[serde(alias = "batchnorm")]
+    BatchNorm {
+        name: Option<String>,
+        num_features: usize,
+        #[serde(default = "default_eps")]
+        eps: f64,
+    },
+    Relu,
+    #[serde(alias = "max_pool2d")]
+    Maxpool2d {
+        kernel_size: usize,
+        stride: Option<usize>,
+    },
+    Dropout {
+        #[serde(default = "default_dropout")]
+        p: f32,
+    },
+    Flatten,
+    Softmax,
+}
+
+fn one() -> usize {
+    1
+}
+
+fn yes() -> bool {
+    true
+}
+
+fn default_eps() -> f64 {
+    1e-5
+}
+
+fn default_dropout() -> f32 {
+    0.5
+}
+
+impl ModelSpec {
+    /// Parse a spec from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(candle_core::Error::msg)
+    }
+
+    /// Parse a spec from TOML, with the layers given as `[[layers]]` tables
+    pub fn from_toml(toml: &str) -> Result<Self> {
+        toml::from_str(toml).map_err(candle_core::Error::msg)
+    }
+
+    /// Read a spec from a `.json` or `.toml` file
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&contents),
+            _ => Self::from_json(&contents),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Layer {
+    Conv2d(nn::Conv2d),
+    Linear(nn::Linear),
+    BatchNorm(nn::BatchNorm),
+    Relu,
+    MaxPool2d { kernel_size: usize, stride: usize },
+    Dropout(nn::Dropout),
+    Flatten,
+    Softmax,
+}
+
+/// Model built from a [`ModelSpec`]
+#[derive(Debug)]
+pub struct SequentialModel {
+    layers: Vec<Layer>,
+}
+
+impl SequentialModel {
+    pub fn new(spec: &ModelSpec, varbuilder: nn::VarBuilder) -> Result<Self> {
+        if spec.layers.is_empty() {
+            candle_core::bail!("model spec has no layers")
+        }
+
+        let mut layers = Vec::with_capacity(spec.layers.len());
+        for (index, layer) in spec.layers.iter().enumerate() {
+            let prefix = |name: &Option<String>| {
+                varbuilder.pp(name.clone().unwrap_or_else(|| index.to_string()))
+            };
+            let layer = match layer {
+                LayerSpec::Conv2d {
+                    name,
+                    in_channels,
+                    out_channels,
+                    kernel_size,
+                    padding,
+                    stride,
+                } => Layer::Conv2d(nn::conv2d(
+                    *in_channels,
+                    *out_channels,
+                    *kernel_size,
+                    nn::Conv2dConfig {
+                        padding: *padding,
+                        stride: *stride,
+                        ..Default::default()
+                    },
+                    prefix(name),
+                )?),
+                LayerSpec::Linear {
+                    name,
+                    in_features,
+                    out_features,
+                    bias,
+                } => Layer::Linear(nn::linear_b(
+                    *in_features,
+                    *out_features,
+                    *bias,
+                    prefix(name),
+                )?),
+                LayerSpec::BatchNorm {
+                    name,
+                    num_features,
+                    eps,
+                } => Layer::BatchNorm(nn::batch_norm(*num_features, *eps, prefix(name))?),
+                LayerSpec::Relu => Layer::Relu,
+                LayerSpec::Maxpool2d {
+                    kernel_size,
+                    stride,
+                } => Layer::MaxPool2d {
+                    kernel_size: *kernel_size,
+                    stride: stride.unwrap_or(*kernel_size),
+                },
+                LayerSpec::Dropout { p } => Layer::Dropout(nn::Dropout::new(*p)),
+                LayerSpec::Flatten => Layer::Flatten,
+                LayerSpec::Softmax => Layer::Softmax,
+            };
+            layers.push(layer);
+        }
+        Ok(Self { layers })
+    }
+
+    /// Returns true if the last layer is a softmax, i.e. the model outputs probabilities
+    /// instead of logits
+    pub fn outputs_probabilities(&self) -> bool {
+        matches!(self.layers.last(), Some(Layer::Softmax))
+    }
+
+    pub fn forward(&self, x: &Tensor) -> Result<Tensor> {
+        forward_layers(&self.layers, x)
+    }
+
+    /// Returns the input of the last linear layer, i.e. the penultimate activations
+    pub fn forward_features(&self, x: &Tensor) -> Result<Tensor> {
+        forward_layers(&self.layers[..self.last_linear()?], x)
+    }
+
+    /// Returns the outputs and the penultimate activations of a single forward pass
+    pub fn forward_with_features(&self, x: &Tensor) -> Result<(Tensor, Tensor)> {
+        let last_linear = self.last_linear()?;
+        let features = forward_layers(&self.layers[..last_linear], x)?;
+        let outputs = forward_layers(&self.layers[last_linear..], &features)?;
+        Ok((outputs, features))
+    }
+
+    fn last_linear(&self) -> Result<usize> {
+        let Some(last_linear) = self
+            .layers
+            .iter()
+            .rposition(|layer| matches!(layer, Layer::Linear(_)))
+        else {
+            candle_core::bail!("The model has no linear layer to take features from");
+        };
+        Ok(last_linear)
+    }
+}
+
+fn forward_layers(layers: &[Layer], x: &Tensor) -> Result<Tensor> {
+    let mut x = x.clone();
+    for layer in layers {
+        x = match layer {
+            Layer::Conv2d(conv) => conv.forward(&x)?,
+            Layer::Linear(linear) => linear.forward(&x)?,
+            Layer::BatchNorm(batch_norm) => batch_norm.forward_t(&x, false)?,
+            Layer::Relu => x.relu()?,
+            Layer::MaxPool2d {
+                kernel_size,
+                stride,
+            } => x.max_pool2d_with_stride(*kernel_size, *stride)?,
+            // Dropout is the identity at inference time
+            Layer::Dropout(dropout) => dropout.forward_t(&x, false)?,
+            Layer::Flatten => x.flatten_from(1)?,
+            Layer::Softmax => nn::ops::softmax_last_dim(&x)?,
+        };
+    }
+    Ok(x)
+}
@@ -0,0 +1,312 @@
+//! Training of the MNIST models with the candle-nn optimizers
+//!
+//! The trained variables live in a [`VarMap`], so a checkpoint is written with
+//! [`VarMap::save`] and has the tensor names the server expects.
+use std::str::FromStr;
+
+use candle_core::{D, DType, Device, Tensor};
+use candle_nn::{AdamW, Module, Optimizer as _, ParamsAdamW, SGD, VarMap, loss};
+use serde::{Deserialize, Serialize};
+
+use crate::MnistDataset;
+
+type Result<T> = std::result::Result<T, candle_core::Error>;
+
+/// Optimizer updating the model variables
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OptimizerKind {
+    /// Plain stochastic gradient descent
+    Sgd,
+    /// Adam with decoupled weight decay
+    #[default]
+    AdamW,
+}
+
+impl FromStr for OptimizerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "sgd" => Ok(OptimizerKind::Sgd),
+            "adamw" => Ok(OptimizerKind::AdamW),
+            _ => Err(format!("unknown optimizer '{s}', expected sgd or adamw")),
+        }
+    }
+}
+
+/// Hyperparameters of a training run
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainConfig {
+    pub optimizer: OptimizerKind,
+    pub learning_rate: f64,
+    /// Decoupled weight decay, only used by AdamW
+    pub weight_decay: f64,
+    pub epochs: usize,
+    pub batch_size: usize,
+    /// Seed of the shuffling of the training set
+    pub seed: u64,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            optimizer: OptimizerKind::AdamW,
+            learning_rate: 1e-3,
+            weight_decay: 0.01,
+            epochs: 5,
+            batch_size: 64,
+            seed: 42,
+        }
+    }
+}
+
+/// Loss and accuracy on the training set over one epoch
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct EpochStats {
+    /// Epoch number, starting at 1
+    pub epoch: usize,
+    /// Mean cross-entropy of the batches
+    pub loss: f32,
+    pub accuracy: f32,
+}
+
+enum Optimizer {
+    Sgd(SGD),
+    AdamW(AdamW),
+}
+
+impl Optimizer {
+    fn new(config: &TrainConfig, varmap: &VarMap) -> Result<Self> {
+        let vars = varmap.all_vars();
+        Ok(match config.optimizer {
+            OptimizerKind::Sgd => Optimizer::Sgd(SGD::new(vars, config.learning_rate)?),
+            OptimizerKind::AdamW => Optimizer::AdamW(AdamW::new(
+                vars,
+                ParamsAdamW {
+                    lr: config.learning_rate,
+                    weight_decay: config.weight_decay,
+                    ..Default::default()
+                },
+            )?),
+        })
+    }
+
+    fn backward_step(&mut self, loss: &Tensor) -> Result<()> {
+        match self {
+            Optimizer::Sgd(sgd) => sgd.backward_step(loss),
+            Optimizer::AdamW(adamw) => adamw.backward_step(loss),
+        }
+    }
+}
+
+/// Trains a model on the variables of a [`VarMap`]
+pub struct Trainer {
+    config: TrainConfig,
+    device: Device,
+    /// Shape of a single sample as the model expects it, e.g. `[784]` or `[1, 28, 28]`
+    input_shape: Vec<usize>,
+    /// Mean and standard deviation applied to the [0, 1] scaled pixels
+    normalization: Option<(f32, f32)>,
+}
+
+impl Trainer {
+    pub fn new(config: TrainConfig, device: Device, input_shape: Vec<usize>) -> Self {
+        Self {
+            config,
+            device,
+            input_shape,
+            normalization: None,
+        }
+    }
+
+    /// Normalize the pixels as `(x - mean) / std`, as the model will see them when served
+    pub fn with_normalization(mut self, mean: f32, std: f32) -> Self {
+        self.normalization = Some((mean, std));
+        self
+    }
+
+    pub fn config(&self) -> &TrainConfig {
+        &self.config
+    }
+
+    /// Train `model`, whose variables are in `varmap`, for the configured number of epochs
+    ///
+    /// `on_epoch` is called after every epoch, e.g. to evaluate or checkpoint the model.
+    /// To fine-tune a checkpoint, load it into `varmap` with [`VarMap::load`] first.
+    pub fn fit<M, F>(
+        &self,
+        model: &M,
+        varmap: &VarMap,
+        dataset: &MnistDataset,
+        mut on_epoch: F,
+    ) -> Result<Vec<EpochStats>>
+    where
+        M: Module + ?Sized,
+        F: FnMut(&EpochStats) -> Result<()>,
+    {
+        if dataset.is_empty() {
+            candle_core::bail!("cannot train on an empty dataset")
+        }
+        let mut optimizer = Optimizer::new(&self.config, varmap)?;
+        let mut indices: Vec<usize> = (0..dataset.len()).collect();
+        let mut rng = SplitMix64(self.config.seed);
+        let mut history = Vec::with_capacity(self.config.epochs);
+
+        for epoch in 1..=self.config.epochs {
+            rng.shuffle(&mut indices);
+            let (mut total_loss, mut batches, mut correct) = (0f32, 0usize, 0usize);
+            for batch in indices.chunks(self.config.batch_size.max(1)) {
+                let (images, labels) = self.batch(dataset, batch)?;
+                let logits = model.forward(&images)?;
+                let loss = loss::cross_entropy(&logits, &labels)?;
+                optimizer.backward_step(&loss)?;
+
+                total_loss += loss.to_scalar::<f32>()?;
+                batches += 1;
+                correct += count_correct(&logits, &labels)?;
+            }
+
+            let stats = EpochStats {
+                epoch,
+                loss: total_loss / batches as f32,
+                accuracy: correct as f32 / dataset.len() as f32,
+            };
+            on_epoch(&stats)?;
+            history.push(stats);
+        }
+        Ok(history)
+    }
+
+    /// Fraction of the dataset the model classifies correctly
+    pub fn accuracy<M: Module + ?Sized>(&self, model: &M, dataset: &MnistDataset) -> Result<f32> {
+        let indices: Vec<usize> = (0..dataset.len()).collect();
+        let mut correct = 0;
+        for batch in indices.chunks(self.config.batch_size.max(1)) {
+            let (images, labels) = self.batch(dataset, batch)?;
+            correct += count_correct(&model.forward(&images)?, &labels)?;
+        }
+        Ok(correct as f32 / dataset.len().max(1) as f32)
+    }
+
+    /// Stack the images and labels of the given samples into tensors
+    fn batch(&self, dataset: &MnistDataset, indices: &[usize]) -> Result<(Tensor, Tensor)> {
+        let mut pixels: Vec<f32> = indices.iter().flat_map(|&i| dataset.pixels(i)).collect();
+        if let Some((mean, std)) = self.normalization {
+            pixels.iter_mut().for_each(|x| *x = (*x - mean) / std);
+        }
+        let mut shape = vec![indices.len()];
+        shape.extend(&self.input_shape);
+        let images = Tensor::from_vec(pixels, shape, &self.device)?;
+        let labels: Vec<u32> = indices.iter().map(|&i| dataset.label(i) as u32).collect();
+        let labels = Tensor::new(labels, &self.device)?;
+        Ok((images, labels))
+    }
+}
+
+fn count_correct(logits: &Tensor, labels: &Tensor) -> Result<usize> {
+    let predicted = logits.argmax(D::Minus1)?;
+    let correct = predicted.eq(labels)?.to_dtype(DType::U32)?.sum_all()?;
+    Ok(correct.to_scalar::<u32>()? as usize)
+}
+
+/// Small deterministic generator for shuffling, see <https://prng.di.unimi.it/splitmix64.c>
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Fisher-Yates shuffle
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = (self.next() % (i as u64 + 1)) as usize;
+            items.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MnistMLP;
+    use candle_nn::VarBuilder;
+
+    /// Dataset of 28x28 images with a bright band whose row depends on the label
+    fn synthetic_dataset(name: &str, samples: usize) -> MnistDataset {
+        let dir = std::env::temp_dir().join(format!("mnist-train-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (images, labels) = (dir.join("images-idx3-ubyte"), dir.join("labels-idx1-ubyte"));
+        for i in 0..samples {
+            let label = (i % 10) as u8;
+            let mut image = vec![0u8; 28 * 28];
+            let row = 4 + 2 * label as usize;
+            image[row * 28..(row + 2) * 28].fill(255);
+            MnistDataset::append(&images, &labels, (28, 28), &image, label).unwrap();
+        }
+        MnistDataset::load(&images, &labels).unwrap()
+    }
+
+    #[test]
+    fn test_fit_reduces_loss() {
+        let dataset = synthetic_dataset("fit", 40);
+        for (optimizer, learning_rate) in [(OptimizerKind::Sgd, 0.1), (OptimizerKind::AdamW, 1e-3)]
+        {
+            let varmap = VarMap::new();
+            let model =
+                MnistMLP::new(VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu)).unwrap();
+            let config = TrainConfig {
+                optimizer,
+                learning_rate,
+                epochs: 5,
+                batch_size: 8,
+                ..Default::default()
+            };
+            let trainer = Trainer::new(config, Device::Cpu, vec![784]);
+            let mut epochs = 0;
+            let history = trainer
+                .fit(&model, &varmap, &dataset, |_| {
+                    epochs += 1;
+                    Ok(())
+                })
+                .unwrap();
+            assert_eq!(epochs, 5);
+            assert_eq!(history.len(), 5);
+            let (first, last) = (history[0].loss, history[4].loss);
+            assert!(last < first, "{optimizer:?}: loss {first} -> {last}");
+        }
+    }
+
+    #[test]
+    fn test_fit_empty_dataset() {
+        let mut dataset = synthetic_dataset("empty", 1);
+        dataset.truncate(0);
+        let varmap = VarMap::new();
+        let model =
+            MnistMLP::new(VarBuilder::from_varmap(&varmap, DType::F32, &Device::Cpu)).unwrap();
+        let trainer = Trainer::new(TrainConfig::default(), Device::Cpu, vec![784]);
+        assert!(trainer.fit(&model, &varmap, &dataset, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_shuffle_seed() {
+        let shuffled = |seed: u64| {
+            let mut items: Vec<usize> = (0..100).collect();
+            SplitMix64(seed).shuffle(&mut items);
+            items
+        };
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), shuffled(43));
+
+        let mut sorted = shuffled(42);
+        sorted.sort();
+        assert_eq!(sorted, (0..100).collect::<Vec<_>>());
+    }
+}
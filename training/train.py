@@ -1,4 +1,5 @@
 import argparse
+import json
 from safetensors.torch import save_file
 
 import torch
@@ -95,8 +96,14 @@ def main():
     print(f"\n✅ Final test accuracy: {acc:.4f}")
 
     # Save weights in SafeTensors format
+    # The metadata doubles as the model manifest read by the inference server
     state_dict = model.model.state_dict()
-    save_file(state_dict, args.output)
+    metadata = {
+        "architecture": "conv",
+        "input_shape": json.dumps([1, 28, 28]),
+        "metrics": json.dumps({"test_accuracy": acc}),
+    }
+    save_file(state_dict, args.output, metadata=metadata)
     print(f"📦 Saved model weights to: {args.output}")
 
 